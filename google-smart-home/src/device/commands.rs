@@ -0,0 +1,128 @@
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivateScene {
+    /// True to cancel a scene, false to activate a scene.
+    #[serde(default)]
+    pub deactivate: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BrightnessAbsolute {
+    /// Brightness percentage.
+    pub brightness: u8,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, rename_all = "camelCase")]
+pub enum BrightnessRelative {
+    /// Brightness percentage to change.
+    Percent { brightness_relative_percent: i8 },
+    /// Ambiguous amount to change the brightness, between -5 and +5.
+    Weight { brightness_relative_weight: i8 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ColorAbsolute {
+    pub color: Color,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Color {
+    pub name: Option<String>,
+    /// The colour value, if one was given rather than only a name.
+    #[serde(flatten)]
+    pub value: Option<ColorValue>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, rename_all = "camelCase")]
+pub enum ColorValue {
+    Temperature {
+        temperature: u16,
+    },
+    Rgb {
+        #[serde(rename = "spectrumRGB")]
+        spectrum_rgb: u32,
+    },
+    Hsv {
+        #[serde(rename = "spectrumHSV")]
+        spectrum_hsv: Hsv,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Hsv {
+    pub hue: f64,
+    pub saturation: f64,
+    pub value: f64,
+}
+
+/// Return to the charging dock. This has no parameters.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Dock {}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct LockUnlock {
+    /// True to lock, false to unlock.
+    pub lock: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OnOff {
+    pub on: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpenClose {
+    pub open_percent: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, rename_all = "camelCase")]
+pub enum RotateAbsolute {
+    /// Rotation in degrees.
+    Degrees { rotation_degrees: f64 },
+    /// Rotation as a percentage of the available range.
+    Percent { rotation_percent: f64 },
+}
+
+/// Reboot the device.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reboot {
+    /// Whether to also reset the device to its factory settings.
+    #[serde(default)]
+    pub factory_reset: bool,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetHumidity {
+    /// The humidity setpoint to set, as a percentage.
+    pub humidity: u8,
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetModes {
+    /// Map of mode names to the setting to change them to.
+    pub update_mode_settings: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetTemperature {
+    /// The temperature to set, in degrees Celsius.
+    pub temperature: f64,
+}
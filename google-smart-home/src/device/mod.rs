@@ -0,0 +1,40 @@
+pub mod commands;
+mod traits;
+mod types;
+
+use serde::Deserialize;
+use serde::Serialize;
+pub use traits::Trait;
+pub use types::Type;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "command", content = "params", rename_all = "camelCase")]
+#[non_exhaustive]
+pub enum Command {
+    #[serde(rename = "action.devices.commands.ActivateScene")]
+    ActivateScene(commands::ActivateScene),
+    #[serde(rename = "action.devices.commands.BrightnessAbsolute")]
+    BrightnessAbsolute(commands::BrightnessAbsolute),
+    #[serde(rename = "action.devices.commands.BrightnessRelative")]
+    BrightnessRelative(commands::BrightnessRelative),
+    #[serde(rename = "action.devices.commands.ColorAbsolute")]
+    ColorAbsolute(commands::ColorAbsolute),
+    #[serde(rename = "action.devices.commands.Dock")]
+    Dock(commands::Dock),
+    #[serde(rename = "action.devices.commands.LockUnlock")]
+    LockUnlock(commands::LockUnlock),
+    #[serde(rename = "action.devices.commands.OnOff")]
+    OnOff(commands::OnOff),
+    #[serde(rename = "action.devices.commands.OpenClose")]
+    OpenClose(commands::OpenClose),
+    #[serde(rename = "action.devices.commands.Reboot")]
+    Reboot(commands::Reboot),
+    #[serde(rename = "action.devices.commands.RotateAbsolute")]
+    RotateAbsolute(commands::RotateAbsolute),
+    #[serde(rename = "action.devices.commands.SetHumidity")]
+    SetHumidity(commands::SetHumidity),
+    #[serde(rename = "action.devices.commands.SetModes")]
+    SetModes(commands::SetModes),
+    #[serde(rename = "action.devices.commands.SetTemperature")]
+    SetTemperature(commands::SetTemperature),
+}
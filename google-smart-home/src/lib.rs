@@ -0,0 +1,88 @@
+//! Types for [Google Smart Home API](https://developers.google.com/assistant/smarthome)
+
+pub mod device;
+pub mod execute;
+pub mod query;
+pub mod sync;
+
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Request {
+    pub request_id: String,
+    pub inputs: Vec<RequestInput>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "intent", content = "payload", try_from = "RawRequestInput")]
+pub enum RequestInput {
+    #[serde(rename = "action.devices.SYNC")]
+    Sync,
+    #[serde(rename = "action.devices.QUERY")]
+    Query(query::request::Payload),
+    #[serde(rename = "action.devices.EXECUTE")]
+    Execute(execute::request::Payload),
+    #[serde(rename = "action.devices.DISCONNECT")]
+    Disconnect,
+    /// An intent which isn't known, with its name.
+    #[serde(skip_serializing)]
+    Unsupported(String),
+}
+
+/// A request input with its payload not yet parsed, so that unknown intents can be deserialized
+/// rather than failing the whole request.
+#[derive(Deserialize)]
+struct RawRequestInput {
+    intent: String,
+    #[serde(default)]
+    payload: serde_json::Value,
+}
+
+impl TryFrom<RawRequestInput> for RequestInput {
+    type Error = serde_json::Error;
+
+    fn try_from(input: RawRequestInput) -> Result<Self, Self::Error> {
+        Ok(match input.intent.as_str() {
+            "action.devices.SYNC" => Self::Sync,
+            "action.devices.QUERY" => Self::Query(serde_json::from_value(input.payload)?),
+            "action.devices.EXECUTE" => Self::Execute(serde_json::from_value(input.payload)?),
+            "action.devices.DISCONNECT" => Self::Disconnect,
+            _ => Self::Unsupported(input.intent),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged, rename_all = "camelCase")]
+pub enum Response {
+    Sync(sync::response::Response),
+    Query(query::response::Response),
+    Execute(execute::response::Response),
+    Disconnect(DisconnectResponse),
+    Error(ErrorResponse),
+}
+
+/// Response to a DISCONNECT request, which is an empty JSON object.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct DisconnectResponse {}
+
+/// Response to a request which couldn't be handled at all, such as one with an unsupported intent.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorResponse {
+    pub request_id: String,
+    pub payload: ErrorPayload,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ErrorPayload {
+    /// An error code for the entire transaction, such as `notSupported`.
+    pub error_code: String,
+
+    /// Detailed error which will never be presented to users but may be logged or used during development.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub debug_string: Option<String>,
+}
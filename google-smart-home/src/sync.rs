@@ -0,0 +1,356 @@
+use crate::device;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Request types of the SYNC intent
+pub mod request {
+    use super::*;
+
+    /// SYNC request payload.
+    #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Payload {}
+}
+
+/// Response types of the SYNC intent
+pub mod response {
+    use super::*;
+
+    /// SYNC response
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Response {
+        pub request_id: String,
+        pub payload: Payload,
+    }
+
+    /// SYNC response payload.
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Payload {
+        /// Reflects the unique (and immutable) user ID on the agent's platform.
+        pub agent_user_id: String,
+        /// An error code for the entire transaction for auth failures and developer system unavailability.
+        /// For individual device errors, use the errorCode within the device object.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub error_code: Option<String>,
+        /// Detailed error which will never be presented to users but may be logged or used during development.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub debug_string: Option<String>,
+        /// Reflects the unique (and immutable) user ID on the agent's platform.
+        pub devices: Vec<PayloadDevice>,
+    }
+
+    /// Device execution result.
+    #[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct PayloadDevice {
+        /// The ID of the device in the developer's cloud.
+        /// This must be unique for the user and for the developer,
+        /// as in cases of sharing we may use this to dedupe multiple views of the same device.
+        /// It should be immutable for the device; if it changes, the Assistant will treat it as a new device.
+        pub id: String,
+        /// The hardware type of device.
+        #[serde(rename = "type")]
+        pub device_type: device::Type,
+        /// List of traits this device has. This defines the commands, attributes, and states that the device supports.
+        pub traits: Vec<device::Trait>,
+        /// Names of this device.
+        pub name: PayloadDeviceName,
+        /// Indicates whether this device will have its states updated by the Real Time Feed.
+        /// true to use the Real Time Feed for reporting state, and false to use the polling model.
+        pub will_report_state: bool,
+        /// Indicates whether notifications are enabled for the device.
+        #[serde(default)]
+        pub notification_supported_by_agent: bool,
+        /// Provides the current room of the device in the user's home to simplify setup.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub room_hint: Option<String>,
+        /// Contains fields describing the device for use in one-off logic if needed (e.g. 'broken firmware version X of light Y requires adjusting color', or 'security flaw requires notifying all users of firmware Z').
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub device_info: Option<PayloadDeviceInfo>,
+        /// Aligned with per-trait attributes described in each trait schema reference.
+        #[serde(default)]
+        pub attributes: Attributes,
+        /// Object defined by the developer which will be attached to future QUERY and EXECUTE requests, maximum of 512 bytes per device. Use this object to store additional information about the device your cloud service may need, such as the global region of the device. Data in this object has a few constraints: No sensitive information, including but not limited to Personally Identifiable Information.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        pub custom_data: Option<serde_json::Map<String, serde_json::Value>>,
+        /// List of alternate IDs used to identify a cloud synced device for local execution.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub other_device_ids: Option<Vec<PayloadOtherDeviceID>>,
+    }
+
+    #[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct Attributes {
+        // Attributes for OnOff trait.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub command_only_on_off: Option<bool>,
+
+        // Attributes for ColorSetting trait.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub color_model: Option<ColorModel>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub color_temperature_range: Option<ColorTemperatureRange>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub command_only_color_setting: Option<bool>,
+
+        // Attributes for TemperatureSetting trait.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub available_thermostat_modes: Option<Vec<String>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub buffer_range_celsius: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub command_only_temperature_setting: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub query_only_temperature_setting: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub thermostat_temperature_range: Option<ThermostatTemperatureRange>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub thermostat_temperature_unit: Option<ThermostatTemperatureUnit>,
+
+        // Attributes for TemperatureControl trait.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub temperature_range: Option<TemperatureRange>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub temperature_step_celsius: Option<f64>,
+        #[serde(
+            rename = "temperatureUnitForUX",
+            skip_serializing_if = "Option::is_none"
+        )]
+        pub temperature_unit_for_ux: Option<ThermostatTemperatureUnit>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub command_only_temperature_control: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub query_only_temperature_control: Option<bool>,
+
+        // Attributes for EnergyStorage trait.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub query_only_energy_storage: Option<bool>,
+
+        // Attributes for HumiditySetting trait.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub humidity_setpoint_range: Option<HumiditySetpointRange>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub command_only_humidity_setting: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub query_only_humidity_setting: Option<bool>,
+
+        // Attributes for Modes trait.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub available_modes: Option<Vec<Mode>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub command_only_modes: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub query_only_modes: Option<bool>,
+
+        // Attributes for Rotation trait.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub supports_degrees: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub supports_percent: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub rotation_degrees_range: Option<RotationDegreesRange>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub supports_continuous_rotation: Option<bool>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub command_only_rotation: Option<bool>,
+
+        // Attributes for Scene trait.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub scene_reversible: Option<bool>,
+
+        // Attributes for SensorState trait.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub sensor_states_supported: Option<Vec<SensorStateSupported>>,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ColorTemperatureRange {
+        pub temperature_min_k: u64,
+        pub temperature_max_k: u64,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub enum ColorModel {
+        Rgb,
+        Hsv,
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct ThermostatTemperatureRange {
+        pub min_threshold_celsius: f64,
+        pub max_threshold_celcius: f64,
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct TemperatureRange {
+        pub min_threshold_celsius: f64,
+        pub max_threshold_celsius: f64,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct HumiditySetpointRange {
+        pub min_percent: u8,
+        pub max_percent: u8,
+    }
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct RotationDegreesRange {
+        pub rotation_degrees_min: f64,
+        pub rotation_degrees_max: f64,
+    }
+
+    /// A sensor which a device supports, for the SensorState trait.
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct SensorStateSupported {
+        /// Supported sensor type, such as `AirQuality`.
+        pub name: String,
+        /// Descriptive states which the sensor may report, if any.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub descriptive_capabilities: Option<DescriptiveCapabilities>,
+        /// Numeric capabilities of the sensor, if it reports a raw value.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub numeric_capabilities: Option<NumericCapabilities>,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct DescriptiveCapabilities {
+        /// List of the possible descriptive states of the sensor.
+        pub available_states: Vec<String>,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct NumericCapabilities {
+        /// The unit of the raw value, such as `PERCENTAGE`.
+        pub raw_value_unit: String,
+    }
+
+    /// A mode which a device supports, for the Modes trait.
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    pub struct Mode {
+        /// Internal name of the mode, which will be used in commands and states.
+        pub name: String,
+        /// Synonyms of the mode name in each supported language.
+        pub name_values: Vec<ModeName>,
+        /// Possible settings for the mode.
+        pub settings: Vec<ModeSetting>,
+        /// Whether the settings are in a meaningful order, so that they can be increased or
+        /// decreased.
+        #[serde(default)]
+        pub ordered: bool,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    pub struct ModeName {
+        /// User-friendly names for the mode, in the given language.
+        pub name_synonym: Vec<String>,
+        /// Language code.
+        pub lang: String,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    pub struct ModeSetting {
+        /// Internal name of the setting, which will be used in commands and states.
+        pub setting_name: String,
+        /// Synonyms of the setting in each supported language.
+        pub setting_values: Vec<ModeSettingValue>,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    pub struct ModeSettingValue {
+        /// User-friendly names for the setting, in the given language.
+        pub setting_synonym: Vec<String>,
+        /// Language code.
+        pub lang: String,
+    }
+
+    #[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+    pub enum ThermostatTemperatureUnit {
+        C,
+        F,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct PayloadDeviceName {
+        /// List of names provided by the developer rather than the user, often manufacturer names, SKUs, etc.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub default_names: Option<Vec<String>>,
+        /// Primary name of the device, generally provided by the user.
+        /// This is also the name the Assistant will prefer to describe the device in responses.
+        pub name: String,
+        /// Additional names provided by the user for the device.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub nicknames: Option<Vec<String>>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct PayloadDeviceInfo {
+        /// Especially useful when the developer is a hub for other devices.
+        /// Google may provide a standard list of manufacturers here so that e.g. TP-Link and Smartthings both describe 'osram' the same way.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub manufacturer: Option<String>,
+        /// The model or SKU identifier of the particular device.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub model: Option<String>,
+        /// Specific version number attached to the hardware if available.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub hw_version: Option<String>,
+        /// Specific version number attached to the software/firmware, if available.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub sw_version: Option<String>,
+    }
+
+    #[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+    #[serde(rename_all = "camelCase")]
+    pub struct PayloadOtherDeviceID {
+        /// The agent's ID. Generally, this is the project ID in the Actions console.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub agent_id: Option<String>,
+        /// Device ID defined by the agent. The device ID must be unique.
+        pub device_id: String,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn color_setting_attributes() {
+        let attributes = response::Attributes {
+            color_model: Some(response::ColorModel::Rgb),
+            command_only_color_setting: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&attributes).unwrap(),
+            json!({"colorModel": "rgb", "commandOnlyColorSetting": true}).to_string()
+        );
+    }
+
+    #[test]
+    fn on_off_attributes() {
+        let attributes = response::Attributes {
+            command_only_on_off: Some(true),
+            ..Default::default()
+        };
+        assert_eq!(
+            serde_json::to_string(&attributes).unwrap(),
+            json!({"commandOnlyOnOff": true}).to_string()
+        );
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+use crate::config::server::Config;
+use crate::extractors::AdminUserID;
+use crate::types::errors::ServerError;
+use crate::State;
+use axum::extract::Extension;
+use axum::Json;
+use serde::Deserialize;
+use serde::Serialize;
+
+/// Returns the effective config of the server, with secrets redacted.
+#[tracing::instrument(name = "AdminConfig", skip(state))]
+pub async fn config(
+    Extension(state): Extension<State>,
+    AdminUserID(_user_id): AdminUserID,
+) -> Json<Config> {
+    Json(state.config.redacted())
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct RefreshResponse {
+    /// Whether the Homie controller re-subscribed to refresh its view of the devices.
+    pub resubscribed: bool,
+    /// Whether Google was asked to sync the user's devices.
+    pub sync_requested: bool,
+}
+
+/// Tries to recover from a broker losing retained messages, by refreshing the admin user's Homie
+/// devices and then asking Google to sync them.
+#[tracing::instrument(name = "AdminRefresh", skip(state))]
+pub async fn refresh(
+    Extension(state): Extension<State>,
+    AdminUserID(user_id): AdminUserID,
+) -> Result<Json<RefreshResponse>, ServerError> {
+    if !state.homie_controllers.contains_key(&user_id) {
+        return Err(ServerError::Validation(
+            "No Homie controller for user".to_string(),
+        ));
+    }
+
+    // The Homie controller doesn't currently support re-subscribing without reconnecting, so all we
+    // can do is ask Google to sync with the devices it has.
+    tracing::warn!("Homie controller doesn't support refreshing, only requesting sync.");
+    let sync_requested = if let Some(home_graph_client) = state.home_graph_clients.get(&user_id) {
+        home_graph_client
+            .request_sync(&state.config.get_agent_user_id(&user_id))
+            .await?;
+        true
+    } else {
+        false
+    };
+
+    Ok(Json(RefreshResponse {
+        resubscribed: false,
+        sync_requested,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::user;
+    use homie_controller::HomieController;
+    use rumqttc::{MqttOptions, QoS};
+    use std::collections::HashMap;
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    fn test_config() -> Config {
+        toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [[users]]
+            id = "861ccceaa3e349138ce2498768dbfe09"
+            email = "someone@example.com"
+            admin = true
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn refresh_without_home_graph() {
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let state = State::new(
+            test_config(),
+            [(user_id, Arc::new(controller))].into_iter().collect(),
+        );
+
+        let Json(response) = refresh(Extension(state), AdminUserID(user_id))
+            .await
+            .unwrap();
+        assert_eq!(
+            response,
+            RefreshResponse {
+                resubscribed: false,
+                sync_requested: false,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn refresh_without_controller() {
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let state = State::new(test_config(), HashMap::new());
+
+        assert_eq!(
+            refresh(Extension(state), AdminUserID(user_id))
+                .await
+                .unwrap_err(),
+            ServerError::Validation("No Homie controller for user".to_string())
+        );
+    }
+
+    #[test]
+    fn redacted_config_has_no_secrets() {
+        let config: Config = toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "hunter2-refresh-key"
+            access-key = "hunter2-access-key"
+            authorization-code-key = "hunter2-authorization-code-key"
+
+            [google]
+            client-id = "google-client-id"
+            client-secret = "hunter2-google-client-secret"
+            project-id = "google-project-id"
+            credentials-file = "google-credentials.json"
+            request-sync-rate-limit-seconds = 600
+
+            [health]
+            secret = "hunter2-health-secret"
+
+            [[users]]
+            id = "861ccceaa3e349138ce2498768dbfe09"
+            email = "someone@example.com"
+            homie = { host = "mqtt.example.com", port = 1883, username = "user", password = "hunter2-mqtt-password", client-id = "homieflow", reconnect-interval-seconds = 5 }
+            "#,
+        )
+        .unwrap();
+
+        let json = serde_json::to_string(&config.redacted()).unwrap();
+        assert!(!json.contains("hunter2"), "Found secret in {}", json);
+        assert!(json.contains("***"));
+        // Non-secret values are still included.
+        assert!(json.contains("google-client-id"));
+        assert!(json.contains("mqtt.example.com"));
+    }
+}
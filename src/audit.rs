@@ -0,0 +1,54 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+//! An audit log of commands executed on behalf of users, separate from the main log.
+
+use crate::types::user;
+use chrono::{DateTime, Utc};
+use google_smart_home::device::Command;
+use google_smart_home::execute::response::PayloadCommandStatus;
+use serde::Serialize;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use tokio::task;
+
+/// A single entry in the audit log, recording a command sent to a device.
+#[derive(Clone, Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AuditRecord<'a> {
+    pub timestamp: DateTime<Utc>,
+    pub user_id: user::ID,
+    /// The Google Home device ID, which is the Homie device and node ID with any prefix.
+    pub device_id: &'a str,
+    #[serde(flatten)]
+    pub command: &'a Command,
+    pub status: &'a PayloadCommandStatus,
+}
+
+/// Appends the given record to the audit log file at the given path as a line of JSON, creating the
+/// file if it doesn't already exist.
+///
+/// The file is written on a blocking thread, so as not to hold up other requests on slow disks.
+pub async fn append(path: &Path, record: &AuditRecord<'_>) -> io::Result<()> {
+    let mut line = serde_json::to_string(record)?;
+    line.push('\n');
+    let path = path.to_owned();
+    task::spawn_blocking(move || {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)?
+            .write_all(line.as_bytes())
+    })
+    .await?
+}
@@ -0,0 +1,141 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+//! Working out the real client IP address of a request which may have come via reverse proxies.
+
+use http::HeaderMap;
+use ipnet::IpNet;
+use std::net::IpAddr;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Returns the IP address of the client which made a request, given the address of the peer which
+/// connected to us and the request headers.
+///
+/// `X-Forwarded-For` is only used if the peer is one of the trusted proxies. In that case the
+/// addresses it lists are checked from the nearest hop backwards, and the first one which isn't
+/// itself a trusted proxy is the client. Anything before that could have been made up by the
+/// client, so is ignored.
+pub fn resolve_client_ip(peer: IpAddr, headers: &HeaderMap, trusted_proxies: &[IpNet]) -> IpAddr {
+    let is_trusted = |ip: &IpAddr| trusted_proxies.iter().any(|proxy| proxy.contains(ip));
+
+    let mut client_ip = peer;
+    if !is_trusted(&client_ip) {
+        return client_ip;
+    }
+    let forwarded_for: Vec<&str> = headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    for hop in forwarded_for.into_iter().rev() {
+        match hop.trim().parse() {
+            Ok(ip) => {
+                client_ip = ip;
+                if !is_trusted(&client_ip) {
+                    break;
+                }
+            }
+            Err(_) => {
+                tracing::warn!("Invalid address {:?} in {} header", hop, X_FORWARDED_FOR);
+                break;
+            }
+        }
+    }
+    client_ip
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trusted_proxies() -> Vec<IpNet> {
+        vec!["10.0.0.0/8".parse().unwrap(), "::1/128".parse().unwrap()]
+    }
+
+    fn forwarded_for(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(X_FORWARDED_FOR, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn untrusted_peer() {
+        let peer = "192.0.2.1".parse().unwrap();
+        assert_eq!(
+            resolve_client_ip(peer, &forwarded_for(&["198.51.100.7"]), &trusted_proxies()),
+            peer
+        );
+        assert_eq!(
+            resolve_client_ip(peer, &forwarded_for(&["198.51.100.7"]), &[]),
+            peer
+        );
+    }
+
+    #[test]
+    fn trusted_peer() {
+        let peer = "10.1.2.3".parse().unwrap();
+        let client: IpAddr = "198.51.100.7".parse().unwrap();
+        assert_eq!(
+            resolve_client_ip(peer, &forwarded_for(&["198.51.100.7"]), &trusted_proxies()),
+            client
+        );
+        // Without the header the proxy itself is the client.
+        assert_eq!(
+            resolve_client_ip(peer, &HeaderMap::new(), &trusted_proxies()),
+            peer
+        );
+        assert_eq!(
+            resolve_client_ip(
+                "::1".parse().unwrap(),
+                &forwarded_for(&["198.51.100.7"]),
+                &trusted_proxies()
+            ),
+            client
+        );
+    }
+
+    #[test]
+    fn spoofed_hops_ignored() {
+        let peer = "10.1.2.3".parse().unwrap();
+        let client: IpAddr = "198.51.100.7".parse().unwrap();
+        // The client may send its own X-Forwarded-For, which proxies append to.
+        assert_eq!(
+            resolve_client_ip(
+                peer,
+                &forwarded_for(&["203.0.113.9, 198.51.100.7, 10.4.5.6"]),
+                &trusted_proxies()
+            ),
+            client
+        );
+        assert_eq!(
+            resolve_client_ip(
+                peer,
+                &forwarded_for(&["203.0.113.9", "198.51.100.7"]),
+                &trusted_proxies()
+            ),
+            client
+        );
+        // Parsing stops at garbage, keeping the last valid hop.
+        assert_eq!(
+            resolve_client_ip(
+                peer,
+                &forwarded_for(&["198.51.100.7, not-an-ip, 10.4.5.6"]),
+                &trusted_proxies()
+            ),
+            "10.4.5.6".parse::<IpAddr>().unwrap()
+        );
+    }
+}
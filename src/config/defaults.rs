@@ -24,3 +24,15 @@ pub const fn server_port() -> u16 {
 pub const fn server_port_tls() -> u16 {
     6002
 }
+
+pub fn discovery_instance_name() -> String {
+    "homieflow".to_string()
+}
+
+pub const fn used_code_sweep_interval_seconds() -> u64 {
+    3600
+}
+
+pub const fn sync_cache_seconds() -> u64 {
+    5
+}
@@ -44,7 +44,7 @@ pub trait Config: DeserializeOwned + Serialize {
     }
 
     fn parse(s: &str) -> Result<Self, Error> {
-        let re = Regex::new(r"\$\{([a-zA-Z_]+)\}").unwrap();
+        let re = Regex::new(r"\$\{([a-zA-Z_][a-zA-Z0-9_]*)\}").unwrap();
         let s = re.replace_all(s, |caps: &regex::Captures| {
             let (pos, name) = {
                 let name_match = caps.get(1).unwrap();
@@ -78,14 +78,28 @@ pub trait Config: DeserializeOwned + Serialize {
         Self::parse(&content)
     }
 
+    /// Returns the default path of the config file, in the XDG config directory if there is one or
+    /// else under `/etc/homieflow`.
     fn default_path() -> PathBuf {
-        xdg::BaseDirectories::with_prefix("homieflow")
-            .unwrap()
-            .get_config_home()
-            .join(Self::DEFAULT_FILE)
+        let config_home = xdg::BaseDirectories::with_prefix("homieflow")
+            .ok()
+            .map(|base_directories| base_directories.get_config_home());
+        config_path_in(config_home, Self::DEFAULT_FILE)
     }
 }
 
+/// The directory to look for config files in if there is no XDG config directory, such as in a
+/// container without `$HOME` set.
+const FALLBACK_CONFIG_DIRECTORY: &str = "/etc/homieflow";
+
+/// Returns the path of the given config file in the given config directory, or the fallback
+/// directory if there is none.
+fn config_path_in(config_home: Option<PathBuf>, file: &str) -> PathBuf {
+    config_home
+        .unwrap_or_else(|| PathBuf::from(FALLBACK_CONFIG_DIRECTORY))
+        .join(file)
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error("io: {0}")]
@@ -121,3 +135,27 @@ pub fn init_logging(hide_timestamp: bool) {
         tracing_subscriber::fmt().with_max_level(level).init()
     };
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_path_with_xdg() {
+        assert_eq!(
+            config_path_in(
+                Some(PathBuf::from("/home/user/.config/homieflow")),
+                "server.toml"
+            ),
+            Path::new("/home/user/.config/homieflow/server.toml")
+        );
+    }
+
+    #[test]
+    fn config_path_fallback() {
+        assert_eq!(
+            config_path_in(None, "server.toml"),
+            Path::new("/etc/homieflow/server.toml")
+        );
+    }
+}
@@ -11,14 +11,24 @@
 // GNU General Public License for more details.
 
 use super::defaults;
+use ipnet::IpNet;
+use schemars::JsonSchema;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
+use crate::homie::filter::DeviceFilter;
 use crate::types::permission;
 use crate::types::room;
 use crate::types::structure;
 use crate::types::user;
+use google_smart_home::device::Trait;
+use google_smart_home::device::Type;
 
 use permission::Permission;
 use room::Room;
@@ -26,8 +36,8 @@ use structure::Structure;
 use url::Url;
 use user::User;
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct Config {
     /// Network configuration
     #[serde(default)]
@@ -40,9 +50,27 @@ pub struct Config {
     /// Configuration of the Google 3rd party client
     #[serde(default)]
     pub google: Option<Google>,
+    /// Additional Google clients, by name, for users who link through a different Google project
+    #[serde(default)]
+    pub google_clients: BTreeMap<String, Google>,
     /// Configuration for login options
     #[serde(default)]
     pub logins: Logins,
+    /// Options for how Homie devices are mapped to Google Home devices
+    #[serde(default)]
+    pub devices: Devices,
+    /// Advertisement of the server on the local network
+    #[serde(default)]
+    pub discovery: Discovery,
+    /// Options for the fulfillment endpoint
+    #[serde(default)]
+    pub fulfillment: Fulfillment,
+    /// Options for the OAuth endpoints
+    #[serde(default)]
+    pub oauth: OAuth,
+    /// Options for the health check endpoints
+    #[serde(default)]
+    pub health: Health,
     /// Structures
     #[serde(default)]
     pub structures: Vec<Structure>,
@@ -57,8 +85,8 @@ pub struct Config {
     pub permissions: Vec<Permission>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct Network {
     /// Server address
     #[serde(default = "defaults::server_listen_address")]
@@ -69,10 +97,37 @@ pub struct Network {
     /// Base public URL of server, if different to the listen address and port.
     #[serde(default)]
     pub base_url: Option<Url>,
+    /// Whether to use the `X-Forwarded-Proto` and `X-Forwarded-Host` headers from a reverse proxy
+    /// to construct the public URL for OAuth redirects. Only enable this behind a trusted proxy.
+    #[serde(default)]
+    pub trust_forwarded_headers: bool,
+    /// Whether to listen on both IPv4 and IPv6 with separate sockets, if `address` is unspecified
+    /// (`0.0.0.0` or `::`).
+    #[serde(default)]
+    pub dual_stack: bool,
+    /// Path prefix under which all routes are served, such as `/homieflow`, for a reverse proxy
+    /// which doesn't strip it.
+    #[serde(default)]
+    pub base_path: Option<String>,
+    /// Whether to compress responses with gzip or Brotli for clients which accept it.
+    #[serde(default)]
+    pub compression: bool,
+    /// Addresses of reverse proxies, in CIDR notation, whose `X-Forwarded-For` header is trusted to
+    /// give the real client IP address for logging.
+    #[serde(default)]
+    #[schemars(with = "Vec<String>")]
+    pub trusted_proxies: Vec<IpNet>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+impl Network {
+    /// Returns the configured base path, or an empty string if there is none.
+    pub fn base_path(&self) -> &str {
+        self.base_path.as_deref().unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct Secrets {
     /// Key used to sign refresh tokens. Must be secret and should be fairly random.
     pub refresh_key: String,
@@ -82,8 +137,8 @@ pub struct Secrets {
     pub authorization_code_key: String,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct Tls {
     /// Server address
     #[serde(default = "defaults::server_listen_address")]
@@ -95,10 +150,25 @@ pub struct Tls {
     pub certificate: PathBuf,
     /// Path to the TLS private key
     pub private_key: PathBuf,
+    /// What to do with plain HTTP requests to the non-TLS listener.
+    #[serde(default)]
+    pub plain_http: PlainHttp,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case")]
+pub enum PlainHttp {
+    /// Serve all routes over plain HTTP as well as HTTPS.
+    #[default]
+    Serve,
+    /// Redirect all plain HTTP requests to HTTPS.
+    Redirect,
+    /// Don't listen for plain HTTP at all.
+    Disabled,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct Google {
     /// OAuth2 Client ID identifying Google to your service
     pub client_id: String,
@@ -110,28 +180,408 @@ pub struct Google {
     pub credentials_file: PathBuf,
     /// The minimum time between two calls to request sync.
     pub request_sync_rate_limit_seconds: u64,
+    /// If set, the state of all devices is reported to Home Graph this long after startup, once
+    /// they have had time to be discovered.
+    #[serde(default)]
+    pub startup_report_delay_seconds: Option<u64>,
+    /// The startup state reports for different users are spread evenly over this much time after
+    /// the startup report delay, rather than all being sent at once.
+    #[serde(default)]
+    pub startup_report_stagger_seconds: u64,
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+impl Google {
+    /// Returns how long after startup to report the state of the given user's devices, or `None`
+    /// if startup reports are disabled. `index` is the position of the user among the
+    /// `user_count` users with Homie brokers.
+    pub fn startup_report_delay(&self, index: usize, user_count: usize) -> Option<Duration> {
+        let delay = Duration::from_secs(self.startup_report_delay_seconds?);
+        Some(delay + stagger_offset(self.startup_report_stagger_seconds, index, user_count))
+    }
+}
+
+/// Returns the offset within a stagger period of the given length at which the item with the given
+/// index should be scheduled, so that `count` items are spread evenly over the period.
+fn stagger_offset(stagger_seconds: u64, index: usize, count: usize) -> Duration {
+    if count == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_secs(stagger_seconds) * index as u32 / count as u32
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct Logins {
     /// Configuration for Google login.
     pub google: Option<GoogleLogin>,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct GoogleLogin {
     /// OAuth2 Client ID identifying your service to Google.
     pub client_id: String,
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Fulfillment {
+    /// Limit on the rate of fulfillment requests for each user, if any.
+    #[serde(default)]
+    pub rate_limit: Option<RateLimit>,
+    /// How long after startup to reject fulfillment requests with 503 Service Unavailable while
+    /// no Homie controller has discovered any devices yet, if at all.
+    #[serde(default)]
+    pub startup_grace_seconds: Option<u64>,
+    /// Whether to also report the state of queried devices to Home Graph after each QUERY, to keep
+    /// Google's cached state from drifting.
+    #[serde(default)]
+    pub report_on_query: bool,
+    /// Whether to refuse all EXECUTE commands for all users, while still answering SYNC and QUERY.
+    #[serde(default)]
+    pub read_only: bool,
+    /// A file to which to append a line of JSON for each command successfully sent to a device, if
+    /// any.
+    #[serde(default)]
+    pub audit_log: Option<PathBuf>,
+    /// How long to reuse the response to a SYNC request for further SYNC requests from the same
+    /// user. 0 disables this.
+    #[serde(default = "defaults::sync_cache_seconds")]
+    pub sync_cache_seconds: u64,
+    /// If set, log the raw body of each fulfillment request at trace level before parsing it,
+    /// truncated to this many bytes.
+    #[serde(default)]
+    pub log_request_body_limit: Option<usize>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Health {
+    /// A secret which must be sent in the `X-Health-Secret` header to get the detailed health
+    /// check, if any. The minimal liveness check is always unauthenticated.
+    #[serde(default)]
+    pub secret: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct RateLimit {
+    /// The average number of requests allowed per minute.
+    pub requests_per_minute: u32,
+    /// The maximum number of requests allowed in a burst.
+    pub burst: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct OAuth {
+    /// Whether to remember which authorization codes have been exchanged for tokens, so that each
+    /// can only be used once. Used codes are only kept in memory until they expire.
+    #[serde(default)]
+    pub one_time_authorization_codes: bool,
+    /// How often to forget used authorization codes which have expired.
+    #[serde(default = "defaults::used_code_sweep_interval_seconds")]
+    pub used_code_sweep_interval_seconds: u64,
+    /// The `iss` claim to put in issued tokens and require when they are used.
+    #[serde(default)]
+    pub issuer: Option<String>,
+    /// The `aud` claim to put in issued tokens and require when they are used.
+    #[serde(default)]
+    pub audience: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Discovery {
+    /// Whether to advertise the server via mDNS.
+    #[serde(default)]
+    pub enabled: bool,
+    /// The mDNS instance name to advertise.
+    #[serde(default = "defaults::discovery_instance_name")]
+    pub instance_name: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Devices {
+    /// The Google Home trait to use for nodes with a settable `temperature` property but no
+    /// `humidity`.
+    #[serde(default)]
+    pub temperature_trait: TemperatureTrait,
+    /// Whether to report devices in the Homie `alert` state as online rather than offline.
+    #[serde(default)]
+    pub alert_online: bool,
+    /// The ID of a boolean property to read the on/off state from, if a node has it, rather than the
+    /// settable `on` property. This is useful for devices with feedback of their actual state.
+    #[serde(default)]
+    pub on_status_property: Option<String>,
+    /// Homie device IDs, or `device_id/node_id` for individual nodes, which can be switched on and
+    /// off but can't report whether they are on, such as momentary relays or IR blasters.
+    #[serde(default)]
+    pub command_only_on_off: Vec<String>,
+    /// The minimum brightness percentage to set. Lower brightness requests are raised to this, so
+    /// that lights don't get stuck off.
+    #[serde(default)]
+    pub min_brightness: u8,
+    /// The number of decimal places to which to round values set on float properties, if not the
+    /// default of 6. Trailing zeros are removed.
+    #[serde(default)]
+    pub float_precision: Option<usize>,
+    /// How many times to retry setting a Homie property if publishing the value fails, before
+    /// returning an error to Google Home.
+    #[serde(default)]
+    pub set_retries: u32,
+    /// If set, devices which haven't sent any updates for this many seconds are reported as
+    /// offline, even if their Homie state is still `ready`.
+    #[serde(default)]
+    pub online_timeout_seconds: Option<u64>,
+    /// If set, devices which are still in the Homie `init` state are reported as online for this
+    /// many seconds after Google Home first asks about them, so that newly linked devices don't
+    /// briefly appear broken.
+    #[serde(default)]
+    pub init_grace_seconds: Option<u64>,
+    /// If set, devices which stop being online are still reported as online for this many seconds,
+    /// so that brief broker disconnections don't make everything appear offline in Google Home.
+    #[serde(default)]
+    pub offline_grace_seconds: Option<u64>,
+    /// Glob patterns matched against `device_id/node_id` for nodes to expose. If empty, all nodes
+    /// are included.
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// Glob patterns matched against `device_id/node_id` for nodes not to expose, even if they
+    /// match `include`.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Google Home traits which should never be exposed, even if a node has the properties for them.
+    #[serde(default)]
+    #[schemars(with = "Vec<String>")]
+    pub disabled_traits: Vec<Trait>,
+    /// String properties to expose as sensors with descriptive states.
+    #[serde(default)]
+    pub string_sensors: Vec<StringSensor>,
+    /// Manufacturer-style default names to give devices in SYNC responses. The first matching entry
+    /// is used.
+    #[serde(default)]
+    pub default_names: Vec<DefaultNames>,
+    /// Google Home device types to use for Homie nodes with the given `$type`, rather than
+    /// inferring the device type from their properties.
+    #[serde(default)]
+    #[schemars(with = "BTreeMap<String, String>")]
+    pub node_types: BTreeMap<String, Type>,
+    /// A delimiter on which to split Homie device and node names into several alternative names,
+    /// if any. The first is used as the primary name, and the node's others become nicknames.
+    #[serde(default)]
+    pub name_delimiter: Option<String>,
+    /// Whether to expose the CPU temperature and load reported in Homie device stats as sensors on
+    /// each of the device's nodes.
+    #[serde(default)]
+    pub diagnostic_sensors: bool,
+    /// The ID of a numeric Homie property with a cumulative energy reading in kWh, such as
+    /// `energy`, to expose with the query-only `EnergyStorage` trait.
+    #[serde(default)]
+    pub energy_property: Option<String>,
+    /// Whether to report a thermostat mode for thermostats without a `thermostat-mode` property,
+    /// based on boolean `heating`, `cooling` or `active` properties.
+    #[serde(default)]
+    pub synthesize_thermostat_mode: bool,
+    /// The maximum number of devices to return in a SYNC response, if any. Any more are dropped
+    /// with a warning, so that a misbehaving broker can't produce a response too large for Google.
+    #[serde(default)]
+    pub max_devices: Option<usize>,
+    /// Candidate Homie property IDs to try in order for each role, for devices which don't use the
+    /// standard property names.
+    #[serde(default)]
+    pub property_names: PropertyNames,
+}
+
+/// Lists of candidate Homie property IDs for Google Home roles. The first candidate which a node has
+/// is used. Roles without a list use the property with the role's standard ID.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct PropertyNames {
+    #[serde(default)]
+    pub on: Option<Vec<String>>,
+    #[serde(default)]
+    pub brightness: Option<Vec<String>>,
+    #[serde(default)]
+    pub color: Option<Vec<String>>,
+    #[serde(default)]
+    pub color_temperature: Option<Vec<String>>,
+    #[serde(default)]
+    pub temperature: Option<Vec<String>>,
+    #[serde(default)]
+    pub humidity: Option<Vec<String>>,
+}
+
+impl Devices {
+    /// Returns whether the given Homie node is configured to be command-only for on/off.
+    pub fn is_command_only_on_off(&self, device_id: &str, node_id: &str) -> bool {
+        self.command_only_on_off.iter().any(|id| {
+            id == device_id
+                || id
+                    .split_once('/')
+                    .is_some_and(|(device, node)| device == device_id && node == node_id)
+        })
+    }
+}
+
+impl PropertyNames {
+    /// Returns the configured candidate property IDs for the given role, if any.
+    pub fn candidates(&self, role: PropertyRole) -> Option<&[String]> {
+        match role {
+            PropertyRole::On => &self.on,
+            PropertyRole::Brightness => &self.brightness,
+            PropertyRole::Color => &self.color,
+            PropertyRole::ColorTemperature => &self.color_temperature,
+            PropertyRole::Temperature => &self.temperature,
+            PropertyRole::Humidity => &self.humidity,
+        }
+        .as_deref()
+    }
+}
+
+/// A role which a Homie property can play in the mapping to Google Home.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PropertyRole {
+    On,
+    Brightness,
+    Color,
+    ColorTemperature,
+    Temperature,
+    Humidity,
+}
+
+impl PropertyRole {
+    /// Returns the standard Homie property ID for the role.
+    pub fn default_id(self) -> &'static str {
+        match self {
+            Self::On => "on",
+            Self::Brightness => "brightness",
+            Self::Color => "color",
+            Self::ColorTemperature => "color-temperature",
+            Self::Temperature => "temperature",
+            Self::Humidity => "humidity",
+        }
+    }
+}
+
+/// A mapping from a string Homie property to a Google Home sensor with descriptive states.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct StringSensor {
+    /// The ID of the Homie property.
+    pub property: String,
+    /// The Google Home sensor name, such as `AirQuality`.
+    pub name: String,
+    /// The possible values of the property. Other values aren't reported.
+    pub states: Vec<String>,
+}
+
+/// Default names for the Google Home devices matching some criteria.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DefaultNames {
+    /// The `device_id/node_id` of the Homie node to apply to, if only one.
+    #[serde(default)]
+    pub node: Option<String>,
+    /// The Google Home device type to apply to, such as `action.devices.types.LIGHT`, if only one.
+    #[serde(default)]
+    #[schemars(with = "Option<String>")]
+    pub device_type: Option<Type>,
+    /// The default names to give the matching devices.
+    pub names: Vec<String>,
+}
+
+impl DefaultNames {
+    /// Returns whether these default names apply to the given Homie node with the given Google
+    /// Home device type.
+    pub fn matches(&self, device_id: &str, node_id: &str, device_type: &Type) -> bool {
+        self.node
+            .as_ref()
+            .is_none_or(|node| *node == format!("{}/{}", device_id, node_id))
+            && self
+                .device_type
+                .as_ref()
+                .is_none_or(|matching_type| matching_type == device_type)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum TemperatureTrait {
+    /// Expose the node as a thermostat.
+    #[default]
+    TemperatureSetting,
+    /// Expose the node as an appliance such as a fridge or freezer.
+    TemperatureControl,
+    /// Expose the temperature and humidity as numeric sensors, without a thermostat.
+    SensorState,
+}
+
 impl super::Config for Config {
     const DEFAULT_TOML: &'static str = include_str!("../../default.toml");
 
     const DEFAULT_FILE: &'static str = "server.toml";
 
     fn validate(&self) -> Result<(), String> {
+        DeviceFilter::new(&self.devices.include, &self.devices.exclude)
+            .map_err(|e| format!("Invalid device filter pattern: {}", e))?;
+
+        let mut shared_connections = HashMap::new();
+        for user in &self.users {
+            if let Some(homie) = &user.homie {
+                if let Some(key) = homie.connection_key() {
+                    let shared = shared_connections.entry(key).or_insert(homie);
+                    if !shared.same_connection_options(homie) {
+                        return Err(format!(
+                            "User {} shares an MQTT connection to {}:{} with different connection \
+                             options",
+                            user.id, homie.host, homie.port
+                        ));
+                    }
+                }
+                if homie.password.is_some() && homie.password_file.is_some() {
+                    return Err(format!(
+                        "Both password and password-file are set for user {}",
+                        user.id
+                    ));
+                }
+                if homie.inflight == Some(0) || homie.request_channel_capacity == Some(0) {
+                    return Err(format!(
+                        "MQTT inflight and request-channel-capacity must be positive for user {}",
+                        user.id
+                    ));
+                }
+                if homie.max_reconnect_attempts == Some(0) {
+                    return Err(format!(
+                        "max-reconnect-attempts must be positive for user {}",
+                        user.id
+                    ));
+                }
+            }
+            if let Some(google_client) = &user.google_client {
+                if !self.google_clients.contains_key(google_client) {
+                    return Err(format!(
+                        "Couldn't find Google client {:?} for user {}",
+                        google_client, user.id
+                    ));
+                }
+            }
+        }
+
+        if self.oauth.used_code_sweep_interval_seconds == 0 {
+            return Err("used-code-sweep-interval-seconds must be positive".to_string());
+        }
+
+        if let Some(base_path) = &self.network.base_path {
+            if !base_path.starts_with('/') || base_path.ends_with('/') {
+                return Err(format!(
+                    "Invalid base-path {:?}, must start with '/' and not end with '/'",
+                    base_path
+                ));
+            }
+        }
+
         for room in &self.rooms {
             if !self
                 .structures
@@ -189,11 +639,163 @@ impl Default for Network {
             address: defaults::server_listen_address(),
             port: defaults::server_port(),
             base_url: None,
+            trust_forwarded_headers: false,
+            dual_stack: false,
+            base_path: None,
+            compression: false,
+            trusted_proxies: vec![],
+        }
+    }
+}
+
+impl Default for Fulfillment {
+    fn default() -> Self {
+        Self {
+            rate_limit: None,
+            startup_grace_seconds: None,
+            report_on_query: false,
+            read_only: false,
+            audit_log: None,
+            sync_cache_seconds: defaults::sync_cache_seconds(),
+            log_request_body_limit: None,
+        }
+    }
+}
+
+impl Default for OAuth {
+    fn default() -> Self {
+        Self {
+            one_time_authorization_codes: false,
+            used_code_sweep_interval_seconds: defaults::used_code_sweep_interval_seconds(),
+            issuer: None,
+            audience: None,
         }
     }
 }
 
+impl Default for Discovery {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            instance_name: defaults::discovery_instance_name(),
+        }
+    }
+}
+
+/// Placeholder for redacted secrets.
+const REDACTED: &str = "***";
+
 impl Config {
+    /// Returns a copy of the config with all secrets replaced by a placeholder, so that it can
+    /// safely be shown.
+    pub fn redacted(&self) -> Self {
+        let mut config = self.clone();
+        config.secrets = Secrets {
+            refresh_key: REDACTED.to_string(),
+            access_key: REDACTED.to_string(),
+            authorization_code_key: REDACTED.to_string(),
+        };
+        if let Some(google) = &mut config.google {
+            google.client_secret = REDACTED.to_string();
+        }
+        for google in config.google_clients.values_mut() {
+            google.client_secret = REDACTED.to_string();
+        }
+        if config.health.secret.is_some() {
+            config.health.secret = Some(REDACTED.to_string());
+        }
+        for user in &mut config.users {
+            if let Some(homie) = &mut user.homie {
+                if homie.password.is_some() {
+                    homie.password = Some(REDACTED.to_string());
+                }
+            }
+        }
+        config
+    }
+
+    /// Returns a one-line summary of what the server is configured to do, to log on startup.
+    pub fn summary(&self) -> String {
+        let homie_configs: Vec<_> = self
+            .users
+            .iter()
+            .filter_map(|user| user.homie.as_ref())
+            .collect();
+        let mut brokers: Vec<_> = homie_configs
+            .iter()
+            .map(|homie| (&homie.host, homie.port))
+            .collect();
+        brokers.sort();
+        brokers.dedup();
+        format!(
+            "{} users, {} with Homie configured, {} distinct MQTT brokers, Google Home Graph {}, TLS {}",
+            self.users.len(),
+            homie_configs.len(),
+            brokers.len(),
+            if self.google_configs().next().is_some() {
+                "configured"
+            } else {
+                "not configured"
+            },
+            if self.tls.is_some() { "on" } else { "off" },
+        )
+    }
+
+    /// Serializes the config as TOML, with secrets replaced by `${VAR}` placeholders for environment
+    /// variables, so that it can be used as a template without leaking them.
+    ///
+    /// The MQTT password for each user uses `MQTT_PASSWORD_` followed by their ID in upper case
+    /// without hyphens, so that it stays with the same user if they are reordered. The client
+    /// secret for each entry in `google-clients` uses `GOOGLE_CLIENT_SECRET_` followed by its name
+    /// in upper case, with any characters other than letters, digits and underscores replaced by
+    /// underscores.
+    pub fn to_toml_with_env_placeholders(&self) -> Result<String, super::Error> {
+        let placeholder = |name: &str| format!("${{{}}}", name);
+        let mut config = self.clone();
+        config.secrets = Secrets {
+            refresh_key: placeholder("REFRESH_KEY"),
+            access_key: placeholder("ACCESS_KEY"),
+            authorization_code_key: placeholder("AUTHORIZATION_CODE_KEY"),
+        };
+        if let Some(google) = &mut config.google {
+            google.client_secret = placeholder("GOOGLE_CLIENT_SECRET");
+        }
+        for (name, google) in &mut config.google_clients {
+            let name: String = name
+                .to_uppercase()
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+                .collect();
+            google.client_secret = placeholder(&format!("GOOGLE_CLIENT_SECRET_{}", name));
+        }
+        if config.health.secret.is_some() {
+            config.health.secret = Some(placeholder("HEALTH_SECRET"));
+        }
+        for user in &mut config.users {
+            if let Some(homie) = &mut user.homie {
+                if homie.password.is_some() {
+                    let id = user.id.to_string().replace('-', "").to_uppercase();
+                    homie.password = Some(placeholder(&format!("MQTT_PASSWORD_{}", id)));
+                }
+            }
+        }
+        Ok(toml::to_string(&config)?)
+    }
+
+    /// Writes the config to the given file, with secrets replaced by `${VAR}` placeholders as for
+    /// [`Config::to_toml_with_env_placeholders`].
+    pub fn write_with_env_placeholders(&self, path: impl AsRef<Path>) -> Result<(), super::Error> {
+        let mut file = std::fs::File::create(path)?;
+        file.write_all(self.to_toml_with_env_placeholders()?.as_bytes())?;
+        Ok(())
+    }
+
+    /// Returns a JSON Schema describing the config file format, for use by editors.
+    pub fn json_schema() -> String {
+        serde_json::to_string_pretty(&schemars::schema_for!(Config))
+            .expect("Failed to serialize config schema")
+    }
+
     pub fn get_user(&self, user_id: &user::ID) -> Option<User> {
         self.users.iter().find(|user| user.id == *user_id).cloned()
     }
@@ -205,19 +807,77 @@ impl Config {
             .cloned()
     }
 
+    /// Returns the Google client config which applies to the given user: the named client they
+    /// reference if any, or else the global one.
+    pub fn get_google(&self, user_id: &user::ID) -> Option<&Google> {
+        let user = self.users.iter().find(|user| user.id == *user_id);
+        match user.and_then(|user| user.google_client.as_ref()) {
+            Some(google_client) => self.google_clients.get(google_client),
+            None => self.google.as_ref(),
+        }
+    }
+
+    /// Returns all configured Google client configs, starting with the global one if any.
+    pub fn google_configs(&self) -> impl Iterator<Item = &Google> {
+        self.google.iter().chain(self.google_clients.values())
+    }
+
+    /// Returns the Google client config with the given OAuth2 client ID, if any.
+    pub fn get_google_by_client_id(&self, client_id: &str) -> Option<&Google> {
+        self.google_configs()
+            .find(|google| google.client_id == client_id)
+    }
+
+    /// Gets the device ID prefix configured for the given user's Homie broker, if any.
+    pub fn get_device_id_prefix(&self, user_id: &user::ID) -> Option<&str> {
+        self.users
+            .iter()
+            .find(|user| user.id == *user_id)?
+            .homie
+            .as_ref()?
+            .device_id_prefix
+            .as_deref()
+    }
+
+    /// Returns whether EXECUTE commands should be refused for the given user, either because of the
+    /// global `read-only` option or the user's own.
+    pub fn is_read_only(&self, user_id: &user::ID) -> bool {
+        self.fulfillment.read_only
+            || self
+                .users
+                .iter()
+                .any(|user| user.id == *user_id && user.read_only)
+    }
+
+    /// Returns the ID by which the given user is identified to Google Home. This is the configured
+    /// `agent-user-id` if any, or else the user's ID.
+    pub fn get_agent_user_id(&self, user_id: &user::ID) -> String {
+        self.users
+            .iter()
+            .find(|user| user.id == *user_id)
+            .and_then(|user| user.agent_user_id.clone())
+            .unwrap_or_else(|| user_id.to_string())
+    }
+
     pub fn get_room(&self, room_id: &room::ID) -> Option<Room> {
         self.rooms.iter().find(|room| room.id == *room_id).cloned()
     }
 
+    /// Returns the public base URL of the server, including the base path if any, and always ending
+    /// with `/`.
     pub fn get_base_url(&self) -> Url {
-        self.network.base_url.clone().unwrap_or_else(|| {
+        let mut base_url = self.network.base_url.clone().unwrap_or_else(|| {
             let (scheme, address, port) = if let Some(tls) = &self.tls {
                 ("https", &tls.address, &tls.port)
             } else {
                 ("http", &self.network.address, &self.network.port)
             };
             Url::parse(&format!("{}://{}:{}", scheme, address, port)).unwrap()
-        })
+        });
+        if self.network.base_path.is_some() {
+            base_url.set_path(&format!("{}/", self.network.base_path()));
+        }
+        base_url
     }
 }
 
@@ -246,6 +906,14 @@ mod tests {
                 address: std::net::IpAddr::V4(std::net::Ipv4Addr::new(0, 0, 0, 0)),
                 port: 1234,
                 base_url: Some(Url::from_str("http://localhost:1234").unwrap()),
+                trust_forwarded_headers: false,
+                dual_stack: true,
+                base_path: Some("/homieflow".to_string()),
+                compression: true,
+                trusted_proxies: vec![
+                    "127.0.0.1/32".parse().unwrap(),
+                    "10.0.0.0/8".parse().unwrap(),
+                ],
             },
             secrets: Secrets {
                 refresh_key: String::from("some-refresh-key"),
@@ -257,6 +925,7 @@ mod tests {
                 private_key: PathBuf::from_str("/etc/private-key").unwrap(),
                 address: std::net::IpAddr::V4(std::net::Ipv4Addr::new(1, 2, 3, 4)),
                 port: 4321,
+                plain_http: PlainHttp::Redirect,
             }),
             google: Some(Google {
                 client_id: String::from("google-client-id"),
@@ -264,12 +933,85 @@ mod tests {
                 project_id: String::from("google-project-id"),
                 credentials_file: PathBuf::from_str("google-credentials.json").unwrap(),
                 request_sync_rate_limit_seconds: 600,
+                startup_report_delay_seconds: Some(60),
+                startup_report_stagger_seconds: 30,
             }),
+            google_clients: [(
+                "tenant".to_string(),
+                Google {
+                    client_id: String::from("tenant-client-id"),
+                    client_secret: String::from("tenant-client-secret"),
+                    project_id: String::from("tenant-project-id"),
+                    credentials_file: PathBuf::from_str("tenant-credentials.json").unwrap(),
+                    request_sync_rate_limit_seconds: 600,
+                    startup_report_delay_seconds: None,
+                    startup_report_stagger_seconds: 0,
+                },
+            )]
+            .into_iter()
+            .collect(),
             logins: Logins {
                 google: Some(GoogleLogin {
                     client_id: String::from("google-login-client-id"),
                 }),
             },
+            devices: Devices {
+                temperature_trait: TemperatureTrait::TemperatureControl,
+                alert_online: false,
+                on_status_property: Some("state".to_string()),
+                command_only_on_off: vec!["ir-blaster".to_string(), "relays/doorbell".to_string()],
+                min_brightness: 5,
+                float_precision: Some(2),
+                set_retries: 2,
+                online_timeout_seconds: None,
+                init_grace_seconds: Some(30),
+                offline_grace_seconds: Some(60),
+                include: vec![],
+                exclude: vec!["*/debug".to_string()],
+                disabled_traits: vec![Trait::Modes],
+                string_sensors: vec![StringSensor {
+                    property: "cycle".to_string(),
+                    name: "WashCycle".to_string(),
+                    states: vec!["washing".to_string(), "spinning".to_string()],
+                }],
+                default_names: vec![DefaultNames {
+                    node: None,
+                    device_type: Some(Type::Washer),
+                    names: vec!["Smart washer".to_string()],
+                }],
+                node_types: [("socket".to_string(), Type::Outlet)].into_iter().collect(),
+                name_delimiter: Some(",".to_string()),
+                diagnostic_sensors: true,
+                energy_property: Some("energy".to_string()),
+                synthesize_thermostat_mode: true,
+                max_devices: Some(500),
+                property_names: PropertyNames {
+                    on: Some(vec!["on".to_string(), "power".to_string()]),
+                    ..Default::default()
+                },
+            },
+            discovery: Discovery::default(),
+            fulfillment: Fulfillment {
+                rate_limit: Some(RateLimit {
+                    requests_per_minute: 60,
+                    burst: 20,
+                }),
+                startup_grace_seconds: Some(30),
+                report_on_query: true,
+                read_only: false,
+                audit_log: Some(PathBuf::from("/var/log/homieflow/audit.log")),
+                sync_cache_seconds: 10,
+                log_request_body_limit: Some(4096),
+            },
+            oauth: OAuth {
+                one_time_authorization_codes: true,
+                used_code_sweep_interval_seconds: 600,
+                issuer: Some("https://homieflow.example.com".to_string()),
+                audience: Some("google-home".to_string()),
+            },
+            health: Health {
+                secret: Some("health-secret".to_string()),
+            },
             structures: [Structure {
                 id: structure::ID::from_str("bd7feab5033940e296ed7fcdc700ba65").unwrap(),
                 name: String::from("Zukago"),
@@ -284,6 +1026,10 @@ mod tests {
             users: [User {
                 id: user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap(),
                 email: String::from("root@gbaranski.com"),
+                agent_user_id: Some(String::from("root")),
+                google_client: None,
+                admin: true,
+                read_only: false,
                 homie: None,
             }]
             .to_vec(),
@@ -308,4 +1054,521 @@ mod tests {
         assert_eq!(config, expected);
         crate::Config::validate(&config).unwrap();
     }
+
+    #[test]
+    fn env_placeholders_round_trip() {
+        let config: Config = toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "some-refresh-key"
+            access-key = "some-access-key"
+            authorization-code-key = "some-authorization-code-key"
+
+            [google]
+            client-id = "google-client-id"
+            client-secret = "some-google-client-secret"
+            project-id = "google-project-id"
+            credentials-file = "google-credentials.json"
+            request-sync-rate-limit-seconds = 600
+
+            [google-clients."tenant-a.example"]
+            client-id = "tenant-client-id"
+            client-secret = "some-tenant-client-secret"
+            project-id = "tenant-project-id"
+            credentials-file = "tenant-credentials.json"
+            request-sync-rate-limit-seconds = 600
+
+            [health]
+            secret = "some-health-secret"
+
+            [[users]]
+            id = "861ccceaa3e349138ce2498768dbfe09"
+            email = "a@example.com"
+            homie = { host = "mqtt.example.com", port = 1883, username = "a", password = "some-mqtt-password", client-id = "a", reconnect-interval-seconds = "500ms" }
+
+            [[users]]
+            id = "bd7feab5033940e296ed7fcdc700ba65"
+            email = "b@example.com"
+            homie = { host = "mqtt.example.com", port = 1883, username = "b", password = "some-other-mqtt-password", client-id = "b", reconnect-interval-seconds = 5 }
+            "#,
+        )
+        .unwrap();
+
+        let template = config.to_toml_with_env_placeholders().unwrap();
+        assert!(!template.contains("some-"), "Found secret in {}", template);
+        assert!(template.contains("${REFRESH_KEY}"));
+        assert!(template.contains("${MQTT_PASSWORD_861CCCEAA3E349138CE2498768DBFE09}"));
+        assert!(template.contains("${MQTT_PASSWORD_BD7FEAB5033940E296ED7FCDC700BA65}"));
+        assert!(template.contains("${HEALTH_SECRET}"));
+        assert!(template.contains("${GOOGLE_CLIENT_SECRET_TENANT_A_EXAMPLE}"));
+
+        // Rename the variables so they don't clash with other tests running in parallel.
+        let template = template.replace("${", "${ROUND_TRIP_");
+        std::env::set_var("ROUND_TRIP_REFRESH_KEY", "some-refresh-key");
+        std::env::set_var("ROUND_TRIP_ACCESS_KEY", "some-access-key");
+        std::env::set_var(
+            "ROUND_TRIP_AUTHORIZATION_CODE_KEY",
+            "some-authorization-code-key",
+        );
+        std::env::set_var(
+            "ROUND_TRIP_GOOGLE_CLIENT_SECRET",
+            "some-google-client-secret",
+        );
+        std::env::set_var(
+            "ROUND_TRIP_MQTT_PASSWORD_861CCCEAA3E349138CE2498768DBFE09",
+            "some-mqtt-password",
+        );
+        std::env::set_var(
+            "ROUND_TRIP_MQTT_PASSWORD_BD7FEAB5033940E296ED7FCDC700BA65",
+            "some-other-mqtt-password",
+        );
+        std::env::set_var("ROUND_TRIP_HEALTH_SECRET", "some-health-secret");
+        std::env::set_var(
+            "ROUND_TRIP_GOOGLE_CLIENT_SECRET_TENANT_A_EXAMPLE",
+            "some-tenant-client-secret",
+        );
+        assert_eq!(Config::parse(&template).unwrap(), config);
+    }
+
+    #[test]
+    fn json_schema() {
+        let schema: serde_json::Value = serde_json::from_str(&Config::json_schema()).unwrap();
+        let properties = schema["properties"].as_object().unwrap();
+        for field in [
+            "network",
+            "secrets",
+            "tls",
+            "google",
+            "logins",
+            "devices",
+            "discovery",
+            "fulfillment",
+            "oauth",
+            "structures",
+            "rooms",
+            "users",
+            "permissions",
+        ] {
+            assert!(properties.contains_key(field), "Missing {}", field);
+        }
+        assert_eq!(schema["required"], serde_json::json!(["secrets"]));
+    }
+
+    #[test]
+    fn unknown_field() {
+        let error = Config::parse(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [[users]]
+            id = "861ccceaa3e349138ce2498768dbfe09"
+            email = "a@example.com"
+            homie = { host = "mqtt.example.com", port = 1883, client-id = "a", reconect-interval-seconds = 5 }
+            "#,
+        )
+        .unwrap_err();
+        assert!(
+            matches!(error, crate::config::Error::TomlDeserialize(_)),
+            "Unexpected error {:?}",
+            error
+        );
+        let message = error.to_string();
+        assert!(
+            message.contains("unknown field `reconect-interval-seconds`"),
+            "Unexpected error message {}",
+            message
+        );
+    }
+
+    #[test]
+    fn agent_user_id() {
+        let config = Config::parse(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [[users]]
+            id = "861ccceaa3e349138ce2498768dbfe09"
+            email = "a@example.com"
+            agent-user-id = "old-id"
+
+            [[users]]
+            id = "bd7feab5033940e296ed7fcdc700ba65"
+            email = "b@example.com"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.get_agent_user_id(
+                &user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap()
+            ),
+            "old-id"
+        );
+        assert_eq!(
+            config.get_agent_user_id(
+                &user::ID::from_str("bd7feab5033940e296ed7fcdc700ba65").unwrap()
+            ),
+            "bd7feab5-0339-40e2-96ed-7fcdc700ba65"
+        );
+    }
+
+    #[test]
+    fn per_user_google_client() {
+        let parse = |google_client: &str| {
+            Config::parse(&format!(
+                r#"
+                [secrets]
+                refresh-key = "refresh-key"
+                access-key = "access-key"
+                authorization-code-key = "authorization-code-key"
+
+                [google]
+                client-id = "google-client-id"
+                client-secret = "google-client-secret"
+                project-id = "google-project-id"
+                credentials-file = "google-credentials.json"
+                request-sync-rate-limit-seconds = 600
+
+                [google-clients.tenant]
+                client-id = "tenant-client-id"
+                client-secret = "tenant-client-secret"
+                project-id = "tenant-project-id"
+                credentials-file = "tenant-credentials.json"
+                request-sync-rate-limit-seconds = 600
+
+                [[users]]
+                id = "861ccceaa3e349138ce2498768dbfe09"
+                email = "a@example.com"
+
+                [[users]]
+                id = "bd7feab5033940e296ed7fcdc700ba65"
+                email = "b@example.com"
+                google-client = "{}"
+                "#,
+                google_client
+            ))
+        };
+
+        let config = parse("tenant").unwrap();
+        let project_id = |user_id| {
+            config
+                .get_google(&user::ID::from_str(user_id).unwrap())
+                .map(|google| google.project_id.as_str())
+        };
+        assert_eq!(
+            project_id("861ccceaa3e349138ce2498768dbfe09"),
+            Some("google-project-id")
+        );
+        assert_eq!(
+            project_id("bd7feab5033940e296ed7fcdc700ba65"),
+            Some("tenant-project-id")
+        );
+        assert_eq!(
+            config
+                .get_google_by_client_id("tenant-client-id")
+                .map(|google| google.project_id.as_str()),
+            Some("tenant-project-id")
+        );
+        assert!(config.get_google_by_client_id("other-client-id").is_none());
+
+        assert!(parse("missing").is_err());
+    }
+
+    #[test]
+    fn base_path() {
+        let config = |network: &str| {
+            Config::parse(&format!(
+                r#"
+                [network]
+                {}
+
+                [secrets]
+                refresh-key = "refresh-key"
+                access-key = "access-key"
+                authorization-code-key = "authorization-code-key"
+                "#,
+                network
+            ))
+        };
+
+        assert_eq!(
+            config("base-path = \"/homieflow\"")
+                .unwrap()
+                .get_base_url()
+                .as_str(),
+            "http://127.0.0.1:6001/homieflow/"
+        );
+        assert_eq!(
+            config("base-url = \"https://home.example.com\"\nbase-path = \"/a/b\"")
+                .unwrap()
+                .get_base_url()
+                .as_str(),
+            "https://home.example.com/a/b/"
+        );
+        assert_eq!(
+            config("").unwrap().get_base_url().as_str(),
+            "http://127.0.0.1:6001/"
+        );
+        assert!(config("base-path = \"homieflow\"").is_err());
+        assert!(config("base-path = \"/homieflow/\"").is_err());
+    }
+
+    #[test]
+    fn startup_report_stagger() {
+        let google = |delay: Option<u64>, stagger: u64| Google {
+            client_id: String::from("google-client-id"),
+            client_secret: String::from("google-client-secret"),
+            project_id: String::from("google-project-id"),
+            credentials_file: PathBuf::from("google-credentials.json"),
+            request_sync_rate_limit_seconds: 600,
+            startup_report_delay_seconds: delay,
+            startup_report_stagger_seconds: stagger,
+        };
+        let delays = |google: &Google, user_count: usize| -> Vec<Option<Duration>> {
+            (0..user_count)
+                .map(|index| google.startup_report_delay(index, user_count))
+                .collect()
+        };
+
+        assert_eq!(delays(&google(None, 30), 2), vec![None, None]);
+        assert_eq!(
+            delays(&google(Some(60), 30), 1),
+            vec![Some(Duration::from_secs(60))]
+        );
+        assert_eq!(
+            delays(&google(Some(60), 0), 3),
+            vec![Some(Duration::from_secs(60)); 3]
+        );
+        assert_eq!(
+            delays(&google(Some(10), 20), 4),
+            vec![
+                Some(Duration::from_secs(10)),
+                Some(Duration::from_secs(15)),
+                Some(Duration::from_secs(20)),
+                Some(Duration::from_secs(25)),
+            ]
+        );
+        assert_eq!(
+            delays(&google(Some(0), 9), 3),
+            vec![
+                Some(Duration::ZERO),
+                Some(Duration::from_secs(3)),
+                Some(Duration::from_secs(6)),
+            ]
+        );
+    }
+
+    #[test]
+    fn password_and_password_file() {
+        let result = Config::parse(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [[users]]
+            id = "861ccceaa3e349138ce2498768dbfe09"
+            email = "a@example.com"
+
+            [users.homie]
+            host = "localhost"
+            port = 1883
+            client-id = "homieflow"
+            reconnect-interval-seconds = 5
+            username = "user"
+            password = "password"
+            password-file = "/etc/homieflow/mqtt_password"
+            "#,
+        );
+        assert!(matches!(result, Err(crate::config::Error::Validation(_))));
+    }
+
+    #[test]
+    fn command_only_on_off() {
+        let devices = Devices {
+            command_only_on_off: vec!["ir-blaster".to_string(), "relays/doorbell".to_string()],
+            ..Default::default()
+        };
+        assert!(devices.is_command_only_on_off("ir-blaster", "power"));
+        assert!(devices.is_command_only_on_off("relays", "doorbell"));
+        assert!(!devices.is_command_only_on_off("relays", "light"));
+        assert!(!devices.is_command_only_on_off("ir-blaster-2", "power"));
+    }
+
+    #[test]
+    fn shared_connection_different_prefix() {
+        let config = Config::parse(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [[users]]
+            id = "861ccceaa3e349138ce2498768dbfe09"
+            email = "a@example.com"
+
+            [users.homie]
+            host = "localhost"
+            port = 1883
+            client-id = "homieflow"
+            reconnect-interval-seconds = 5
+            share-connection = true
+
+            [[users]]
+            id = "bd7feab5033940e296ed7fcdc700ba65"
+            email = "b@example.com"
+
+            [users.homie]
+            host = "localhost"
+            port = 1883
+            client-id = "homieflow"
+            reconnect-interval-seconds = 5
+            homie-prefix = "other"
+            share-connection = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.users.len(), 2);
+    }
+
+    #[test]
+    fn shared_connection_different_password() {
+        let result = Config::parse(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [[users]]
+            id = "861ccceaa3e349138ce2498768dbfe09"
+            email = "a@example.com"
+
+            [users.homie]
+            host = "localhost"
+            port = 1883
+            client-id = "homieflow"
+            reconnect-interval-seconds = 5
+            username = "homieflow"
+            password = "password"
+            share-connection = true
+
+            [[users]]
+            id = "bd7feab5033940e296ed7fcdc700ba65"
+            email = "b@example.com"
+
+            [users.homie]
+            host = "localhost"
+            port = 1883
+            client-id = "homieflow"
+            reconnect-interval-seconds = 5
+            username = "homieflow"
+            password = "other-password"
+            homie-prefix = "other"
+            share-connection = true
+            "#,
+        );
+        assert!(matches!(result, Err(crate::config::Error::Validation(_))));
+    }
+
+    #[test]
+    fn zero_inflight() {
+        let result = Config::parse(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [[users]]
+            id = "861ccceaa3e349138ce2498768dbfe09"
+            email = "a@example.com"
+
+            [users.homie]
+            host = "localhost"
+            port = 1883
+            client-id = "homieflow"
+            reconnect-interval-seconds = 5
+            inflight = 0
+            "#,
+        );
+        assert!(matches!(result, Err(crate::config::Error::Validation(_))));
+    }
+
+    #[test]
+    fn zero_used_code_sweep_interval() {
+        let result = Config::parse(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [oauth]
+            one-time-authorization-codes = true
+            used-code-sweep-interval-seconds = 0
+            "#,
+        );
+        assert!(matches!(result, Err(crate::config::Error::Validation(_))));
+    }
+
+    #[test]
+    fn invalid_device_filter() {
+        let config: Config = toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [devices]
+            exclude = ["light-["]
+            "#,
+        )
+        .unwrap();
+        let error = config.validate().unwrap_err();
+        assert!(
+            error.starts_with("Invalid device filter pattern"),
+            "Unexpected error {}",
+            error
+        );
+    }
+
+    #[test]
+    fn summary() {
+        let config: Config = toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [[users]]
+            id = "861ccceaa3e349138ce2498768dbfe09"
+            email = "a@example.com"
+            homie = { host = "mqtt.example.com", port = 1883, client-id = "a", reconnect-interval-seconds = 5 }
+
+            [[users]]
+            id = "bd7feab5033940e296ed7fcdc700ba65"
+            email = "b@example.com"
+            homie = { host = "mqtt.example.com", port = 1883, client-id = "b", reconnect-interval-seconds = 5 }
+
+            [[users]]
+            id = "baafebaa0708441782cf17470dd98392"
+            email = "c@example.com"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.summary(),
+            "3 users, 2 with Homie configured, 1 distinct MQTT brokers, Google Home Graph not configured, TLS off"
+        );
+    }
 }
@@ -0,0 +1,216 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+//! Advertisement of the server on the local network via mDNS.
+
+use crate::config::server::{Config, PlainHttp};
+use mdns_sd::{Error, ServiceDaemon, ServiceInfo};
+use std::net::IpAddr;
+
+pub const HTTP_SERVICE_TYPE: &str = "_http._tcp.local.";
+pub const HTTPS_SERVICE_TYPE: &str = "_https._tcp.local.";
+
+/// Starts advertising the HTTP and HTTPS servers via mDNS, if enabled in the config.
+///
+/// The returned daemon must be kept alive for as long as the advertisement should continue.
+pub fn advertise(config: &Config) -> Result<Option<ServiceDaemon>, Error> {
+    if !config.discovery.enabled {
+        return Ok(None);
+    }
+
+    let daemon = ServiceDaemon::new()?;
+    for service_info in service_infos(config)? {
+        tracing::info!(
+            "Advertising {} on port {} via mDNS",
+            service_info.get_fullname(),
+            service_info.get_port()
+        );
+        daemon.register(service_info)?;
+    }
+    Ok(Some(daemon))
+}
+
+/// Builds the mDNS service advertisements for the servers configured in the given config.
+///
+/// Plain HTTP is only advertised if it actually serves requests, rather than being disabled or
+/// redirecting to HTTPS.
+pub fn service_infos(config: &Config) -> Result<Vec<ServiceInfo>, Error> {
+    let mut service_infos = Vec::new();
+    if config
+        .tls
+        .as_ref()
+        .is_none_or(|tls| tls.plain_http == PlainHttp::Serve)
+    {
+        service_infos.push(service_info(
+            config,
+            HTTP_SERVICE_TYPE,
+            config.network.address,
+            config.network.port,
+        )?);
+    }
+    if let Some(tls) = &config.tls {
+        service_infos.push(service_info(
+            config,
+            HTTPS_SERVICE_TYPE,
+            tls.address,
+            tls.port,
+        )?);
+    }
+    Ok(service_infos)
+}
+
+fn service_info(
+    config: &Config,
+    service_type: &str,
+    address: IpAddr,
+    port: u16,
+) -> Result<ServiceInfo, Error> {
+    let instance_name = &config.discovery.instance_name;
+    let host_name = format!("{}.local.", instance_name);
+    // The path under which the routes are served.
+    let path = match config.network.base_path() {
+        "" => "/",
+        base_path => base_path,
+    };
+    let properties = [("path", path)];
+    if address.is_unspecified() {
+        // Listening on all interfaces, so advertise all of them.
+        Ok(ServiceInfo::new(
+            service_type,
+            instance_name,
+            &host_name,
+            (),
+            port,
+            &properties[..],
+        )?
+        .enable_addr_auto())
+    } else {
+        ServiceInfo::new(
+            service_type,
+            instance_name,
+            &host_name,
+            address,
+            port,
+            &properties[..],
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(discovery: &str) -> Config {
+        toml::from_str(&format!(
+            r#"
+            [network]
+            address = "0.0.0.0"
+            port = 1234
+
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [tls]
+            address = "1.2.3.4"
+            port = 4321
+            certificate = "/etc/certificate"
+            private-key = "/etc/private-key"
+
+            [discovery]
+            {}
+            "#,
+            discovery
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn advertise_http_and_https() {
+        let config = test_config("enabled = true");
+
+        let service_infos = service_infos(&config).unwrap();
+        assert_eq!(service_infos.len(), 2);
+
+        assert_eq!(service_infos[0].get_type(), HTTP_SERVICE_TYPE);
+        assert_eq!(service_infos[0].get_port(), 1234);
+        assert_eq!(
+            service_infos[0].get_fullname(),
+            "homieflow._http._tcp.local."
+        );
+        assert!(service_infos[0].is_addr_auto());
+
+        assert_eq!(service_infos[1].get_type(), HTTPS_SERVICE_TYPE);
+        assert_eq!(service_infos[1].get_port(), 4321);
+        assert!(!service_infos[1].is_addr_auto());
+
+        for service_info in &service_infos {
+            assert_eq!(service_info.get_property_val_str("path"), Some("/"));
+        }
+    }
+
+    #[test]
+    fn advertise_base_path() {
+        let mut config = test_config("enabled = true");
+        config.network.base_path = Some("/homieflow".to_string());
+
+        for service_info in &service_infos(&config).unwrap() {
+            assert_eq!(
+                service_info.get_property_val_str("path"),
+                Some("/homieflow")
+            );
+        }
+    }
+
+    #[test]
+    fn advertise_http_only_without_tls() {
+        let mut config = test_config("enabled = true");
+        config.tls = None;
+
+        let service_infos = service_infos(&config).unwrap();
+        assert_eq!(service_infos.len(), 1);
+        assert_eq!(service_infos[0].get_type(), HTTP_SERVICE_TYPE);
+    }
+
+    #[test]
+    fn plain_http_modes() {
+        let advertised_types = |plain_http| {
+            let mut config = test_config("enabled = true");
+            config.tls.as_mut().unwrap().plain_http = plain_http;
+            service_infos(&config)
+                .unwrap()
+                .iter()
+                .map(|service_info| service_info.get_type().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            advertised_types(PlainHttp::Serve),
+            vec![HTTP_SERVICE_TYPE, HTTPS_SERVICE_TYPE]
+        );
+        assert_eq!(
+            advertised_types(PlainHttp::Redirect),
+            vec![HTTPS_SERVICE_TYPE]
+        );
+        assert_eq!(
+            advertised_types(PlainHttp::Disabled),
+            vec![HTTPS_SERVICE_TYPE]
+        );
+    }
+
+    #[test]
+    fn disabled_by_default() {
+        let config = test_config("");
+        assert!(advertise(&config).unwrap().is_none());
+    }
+}
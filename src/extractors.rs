@@ -10,20 +10,28 @@
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU General Public License for more details.
 
+use crate::client_ip::resolve_client_ip;
 use crate::config::server::Secrets;
 use crate::types::errors::AuthError;
+use crate::types::errors::InternalError;
 use crate::types::errors::ServerError;
 use crate::types::errors::TokenError;
 use crate::types::token::AccessTokenPayload;
-use crate::types::token::RefreshTokenPayload;
 use crate::types::token::Token;
 use crate::types::user;
 use crate::State;
 use async_trait::async_trait;
-use axum::body::Body;
+use axum::body::{boxed, Body, BoxBody, Full};
+use axum::extract::rejection::JsonRejection;
+use axum::extract::ConnectInfo;
+use http::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use http::{HeaderValue, Response, StatusCode};
 use jsonwebtoken::TokenData;
 use serde::de;
 use serde::ser;
+use std::convert::Infallible;
+use std::fmt::{self, Display, Formatter};
+use std::net::{IpAddr, SocketAddr};
 
 pub struct UserID(pub user::ID);
 
@@ -39,7 +47,127 @@ impl axum::extract::FromRequest<Body> for UserID {
     }
 }
 
-pub struct RefreshToken(pub TokenData<RefreshTokenPayload>);
+/// The ID of an authenticated user who is configured as an admin.
+pub struct AdminUserID(pub user::ID);
+
+#[async_trait]
+impl axum::extract::FromRequest<Body> for AdminUserID {
+    type Rejection = ServerError;
+
+    async fn from_request(
+        req: &mut axum::extract::RequestParts<Body>,
+    ) -> Result<Self, Self::Rejection> {
+        let UserID(user_id) = UserID::from_request(req).await?;
+        let state: &State = req.extensions().unwrap().get().unwrap();
+        match state.config.get_user(&user_id) {
+            Some(user) if user.admin => Ok(Self(user_id)),
+            _ => Err(AuthError::NotAdmin.into()),
+        }
+    }
+}
+
+/// The IP address of the client which made the request, taking trusted reverse proxies into
+/// account. This is `None` if the address of the peer isn't known.
+pub struct ClientIp(pub Option<IpAddr>);
+
+#[async_trait]
+impl axum::extract::FromRequest<Body> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request(
+        req: &mut axum::extract::RequestParts<Body>,
+    ) -> Result<Self, Self::Rejection> {
+        let extensions = req.extensions().unwrap();
+        let state: &State = extensions.get().unwrap();
+        let client_ip = extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| {
+                resolve_client_ip(
+                    peer.ip(),
+                    req.headers().unwrap(),
+                    &state.config.network.trusted_proxies,
+                )
+            });
+        Ok(Self(client_ip))
+    }
+}
+
+impl Display for ClientIp {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self.0 {
+            Some(ip) => ip.fmt(f),
+            None => f.write_str("unknown"),
+        }
+    }
+}
+
+/// Like [`axum::Json`], but rejects requests with a [`ServerError`] so that the response body is
+/// consistent with other errors.
+pub struct JsonBody<T>(pub T);
+
+#[async_trait]
+impl<T> axum::extract::FromRequest<Body> for JsonBody<T>
+where
+    T: de::DeserializeOwned,
+{
+    type Rejection = ServerError;
+
+    async fn from_request(
+        req: &mut axum::extract::RequestParts<Body>,
+    ) -> Result<Self, Self::Rejection> {
+        match axum::Json::<T>::from_request(req).await {
+            Ok(axum::Json(value)) => Ok(Self(value)),
+            Err(JsonRejection::MissingJsonContentType(err)) => {
+                Err(ServerError::UnsupportedMediaType(err.to_string()))
+            }
+            Err(err) => Err(ServerError::Validation(err.to_string())),
+        }
+    }
+}
+
+/// Handler for routes which exist but don't support the request method.
+pub async fn method_not_allowed() -> ServerError {
+    ServerError::MethodNotAllowed
+}
+
+/// Converts error responses which aren't already JSON, such as axum's own extractor rejections and
+/// unknown routes, into the same JSON shape as [`ServerError`] so that clients can handle all errors
+/// the same way. The status code and other headers are kept.
+pub async fn json_error_response(
+    response: Response<BoxBody>,
+) -> Result<Response<BoxBody>, Infallible> {
+    let status = response.status();
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"));
+    if is_json || !(status.is_client_error() || status.is_server_error()) {
+        return Ok(response);
+    }
+
+    let (mut parts, body) = response.into_parts();
+    let description = match hyper::body::to_bytes(body).await {
+        Ok(bytes) => String::from_utf8_lossy(&bytes).into_owned(),
+        Err(e) => e.to_string(),
+    };
+    let error = match status {
+        StatusCode::BAD_REQUEST | StatusCode::UNPROCESSABLE_ENTITY => {
+            ServerError::Validation(description)
+        }
+        StatusCode::UNSUPPORTED_MEDIA_TYPE => ServerError::UnsupportedMediaType(description),
+        StatusCode::METHOD_NOT_ALLOWED => ServerError::MethodNotAllowed,
+        StatusCode::NOT_FOUND => ServerError::NotFound(description),
+        _ => ServerError::Internal(InternalError::Other(description)),
+    };
+    let body = serde_json::to_vec(&error).expect("ServerError should serialize to JSON");
+    parts.headers.remove(CONTENT_LENGTH);
+    parts
+        .headers
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    Ok(Response::from_parts(parts, boxed(Full::from(body))))
+}
+
 pub struct AccessToken(pub TokenData<AccessTokenPayload>);
 
 async fn from_request<P>(
@@ -71,22 +199,11 @@ where
     Ok(Token::<P>::decode(
         get_key_fn(&state.config.secrets).as_bytes(),
         token,
+        state.config.oauth.issuer.as_deref(),
+        state.config.oauth.audience.as_deref(),
     )?)
 }
 
-#[async_trait]
-impl axum::extract::FromRequest<Body> for RefreshToken {
-    type Rejection = ServerError;
-
-    async fn from_request(
-        req: &mut axum::extract::RequestParts<Body>,
-    ) -> Result<Self, Self::Rejection> {
-        Ok(Self(
-            from_request(req, |secrets| &secrets.refresh_key).await?,
-        ))
-    }
-}
-
 #[async_trait]
 impl axum::extract::FromRequest<Body> for AccessToken {
     type Rejection = ServerError;
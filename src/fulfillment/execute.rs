@@ -11,22 +11,51 @@
 // GNU General Public License for more details.
 
 use super::homie::get_homie_device_by_id;
+use crate::audit::{self, AuditRecord};
+use crate::config::server::{Devices, PropertyRole};
+use crate::homie::device_times::DeviceTimes;
+use crate::homie::node_report_state;
 use crate::homie::state::color_absolute_to_property_value;
+use crate::homie::state::is_mode_property;
+use crate::homie::state::is_percent_property;
+use crate::homie::state::is_temperature_control;
+use crate::homie::state::is_valid_value;
+use crate::homie::state::number_to_property_value;
+use crate::homie::state::on_to_property_value;
 use crate::homie::state::percentage_to_property_value;
+use crate::homie::state::property_range;
+use crate::homie::state::role_property;
+use crate::homie::state::target_humidity_property;
+use crate::retry::retry;
 use crate::types::errors::InternalError;
 use crate::types::user;
 use crate::State;
+use chrono::Utc;
+use google_smart_home::device::commands::Color;
+use google_smart_home::device::commands::ColorAbsolute;
+use google_smart_home::device::commands::ColorValue;
+use google_smart_home::device::commands::RotateAbsolute;
 use google_smart_home::device::Command as GHomeCommand;
 use google_smart_home::execute::request;
 use google_smart_home::execute::request::PayloadCommandDevice;
 use google_smart_home::execute::request::PayloadCommandExecution;
 use google_smart_home::execute::response;
+use google_smart_home::query::response::State as QueryState;
 use homie_controller::Datatype;
 use homie_controller::Device;
 use homie_controller::HomieController;
 use homie_controller::Node;
+use homie_controller::Property;
 use homie_controller::Value;
+use rumqttc::ClientError;
 use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+/// How long to wait before retrying a failed set.
+const SET_RETRY_DELAY: Duration = Duration::from_millis(100);
 
 #[tracing::instrument(name = "Execute", skip(state), err)]
 pub async fn handle(
@@ -34,13 +63,50 @@ pub async fn handle(
     user_id: user::ID,
     payload: &request::Payload,
 ) -> Result<response::Payload, InternalError> {
-    if let Some(homie_controller) = state.homie_controllers.get(&user_id) {
-        let commands = execute_homie_devices(
+    if let (Some(homie_controller), Some(homie_devices)) = (
+        state.homie_controllers.get(&user_id),
+        state.devices(&user_id),
+    ) {
+        if state.config.is_read_only(&user_id) {
+            tracing::info!("Refusing commands for read-only user {}", user_id);
+            return Ok(response::Payload {
+                error_code: None,
+                debug_string: None,
+                commands: refuse_commands(&payload.commands),
+            });
+        }
+        let (commands, reports) = execute_homie_devices(
             homie_controller,
-            &homie_controller.devices(),
+            user_id,
+            state.config.fulfillment.audit_log.as_deref(),
+            &state.config.devices,
+            state.config.get_device_id_prefix(&user_id),
+            state.last_seen.get(&user_id).map(AsRef::as_ref),
+            state.offline_since.get(&user_id).map(AsRef::as_ref),
+            &homie_devices,
             &payload.commands,
         )
         .await;
+        if let Some(home_graph_client) = state.home_graph_clients.get(&user_id) {
+            if !reports.is_empty() {
+                let home_graph_client = home_graph_client.clone();
+                let agent_user_id = state.config.get_agent_user_id(&user_id);
+                tokio::spawn(async move {
+                    for (device_id, device_state) in reports {
+                        if let Err(e) = home_graph_client
+                            .report_state(&agent_user_id, device_id.clone(), device_state)
+                            .await
+                        {
+                            tracing::error!(
+                                "Error reporting state of {} after execute: {:?}",
+                                device_id,
+                                e
+                            );
+                        }
+                    }
+                });
+            }
+        }
         Ok(response::Payload {
             error_code: None,
             debug_string: None,
@@ -55,54 +121,343 @@ pub async fn handle(
     }
 }
 
-async fn execute_homie_devices<'a>(
+/// Returns an `actionNotAvailable` error for every device in the given commands.
+fn refuse_commands(commands: &[request::PayloadCommand]) -> Vec<response::PayloadCommand> {
+    commands
+        .iter()
+        .map(|command| {
+            command_error(
+                command
+                    .devices
+                    .iter()
+                    .map(|device| device.id.clone())
+                    .collect(),
+                "actionNotAvailable",
+            )
+        })
+        .collect()
+}
+
+/// Executes the given commands, returning the responses along with the states to report to Home
+/// Graph for any Google Home devices whose setpoint was changed.
+#[allow(clippy::too_many_arguments)]
+async fn execute_homie_devices(
     controller: &HomieController,
+    user_id: user::ID,
+    audit_log: Option<&Path>,
+    config: &Devices,
+    device_id_prefix: Option<&str>,
+    last_seen: Option<&DeviceTimes>,
+    offline_since: Option<&DeviceTimes>,
     devices: &HashMap<String, Device>,
     commands: &[request::PayloadCommand],
-) -> Vec<response::PayloadCommand> {
+) -> (Vec<response::PayloadCommand>, Vec<(String, QueryState)>) {
     let mut responses = vec![];
+    let mut reports = vec![];
 
     for command in commands {
         for device in &command.devices {
             for execution in &command.execution {
-                responses.push(execute_homie_device(controller, devices, execution, device).await);
+                let response = execute_homie_device(
+                    controller,
+                    config,
+                    device_id_prefix,
+                    devices,
+                    execution,
+                    device,
+                )
+                .await;
+                if let Some(audit_log) = audit_log {
+                    if response.status != response::PayloadCommandStatus::Error {
+                        let record = AuditRecord {
+                            timestamp: Utc::now(),
+                            user_id,
+                            device_id: &device.id,
+                            command: &execution.command,
+                            status: &response.status,
+                        };
+                        if let Err(e) = audit::append(audit_log, &record).await {
+                            tracing::error!(
+                                "Error writing {:?} to audit log {:?}: {}",
+                                record,
+                                audit_log,
+                                e
+                            );
+                        }
+                    }
+                }
+                if response.status != response::PayloadCommandStatus::Error {
+                    if let Some(state) = setpoint_report(
+                        config,
+                        device_id_prefix,
+                        last_seen,
+                        offline_since,
+                        devices,
+                        &execution.command,
+                        &device.id,
+                    ) {
+                        reports.push((device.id.clone(), state));
+                    }
+                }
+                responses.push(response);
             }
         }
     }
-    responses
+    (responses, reports)
+}
+
+/// Returns the state to report to Home Graph after the given command was successfully executed on
+/// the Google Home device with the given ID, if it changed a temperature setpoint. This lets the
+/// Google Home app show the new setpoint without waiting for the Homie device to publish it.
+fn setpoint_report(
+    config: &Devices,
+    device_id_prefix: Option<&str>,
+    last_seen: Option<&DeviceTimes>,
+    offline_since: Option<&DeviceTimes>,
+    devices: &HashMap<String, Device>,
+    command: &GHomeCommand,
+    device_id: &str,
+) -> Option<QueryState> {
+    if let GHomeCommand::SetTemperature(set_temperature) = command {
+        let (device, node) = get_homie_device_by_id(devices, device_id_prefix, device_id)?;
+        if !is_temperature_control(node, config) {
+            return None;
+        }
+        let temperature = role_property(node, config, PropertyRole::Temperature)?;
+        let value = number_to_property_value(
+            temperature,
+            set_temperature.temperature,
+            config.float_precision,
+        )?;
+        // Assume that the set will succeed, and report the state the node will then be in.
+        let mut node = node.clone();
+        node.properties.get_mut(&temperature.id)?.value = Some(value);
+        Some(node_report_state(
+            device,
+            &node,
+            config,
+            last_seen,
+            offline_since,
+        ))
+    } else {
+        None
+    }
 }
 
 async fn execute_homie_device(
     controller: &HomieController,
+    config: &Devices,
+    device_id_prefix: Option<&str>,
     devices: &HashMap<String, Device>,
     execution: &PayloadCommandExecution,
     command_device: &PayloadCommandDevice,
 ) -> response::PayloadCommand {
     let ids = vec![command_device.id.to_owned()];
 
-    if let Some((device, node)) = get_homie_device_by_id(devices, &command_device.id) {
+    if let Some((device, node)) =
+        get_homie_device_by_id(devices, device_id_prefix, &command_device.id)
+    {
         // TODO: Check if device is offline?
         match &execution.command {
             GHomeCommand::OnOff(onoff) => {
-                if let Some(on) = node.properties.get("on") {
-                    if on.datatype == Some(Datatype::Boolean) {
-                        return set_value(controller, device, node, "on", onoff.on, ids).await;
+                if let Some(on) = role_property(node, config, PropertyRole::On) {
+                    if let Some(value) = on_to_property_value(on, onoff.on) {
+                        return set_value(controller, config, device, node, &on.id, value, ids)
+                            .await;
                     }
                 }
             }
             GHomeCommand::BrightnessAbsolute(brightness_absolute) => {
-                if let Some(brightness) = node.properties.get("brightness") {
-                    if let Some(value) =
-                        percentage_to_property_value(brightness, brightness_absolute.brightness)
-                    {
-                        return set_value(controller, device, node, "brightness", value, ids).await;
+                if let Some(brightness) = role_property(node, config, PropertyRole::Brightness) {
+                    if let Some(value) = percentage_to_property_value(
+                        brightness,
+                        brightness_absolute.brightness,
+                        config.min_brightness,
+                        config.float_precision,
+                    ) {
+                        return set_value(
+                            controller,
+                            config,
+                            device,
+                            node,
+                            &brightness.id,
+                            value,
+                            ids,
+                        )
+                        .await;
+                    }
+                }
+            }
+            GHomeCommand::ColorAbsolute(ColorAbsolute {
+                color:
+                    Color {
+                        value: Some(ColorValue::Temperature { temperature }),
+                        ..
+                    },
+            }) => {
+                if let Some(color_temperature) =
+                    role_property(node, config, PropertyRole::ColorTemperature)
+                {
+                    if let Some(value) = color_temperature_to_property_value(
+                        color_temperature,
+                        (*temperature).into(),
+                        config.float_precision,
+                    ) {
+                        return set_value(
+                            controller,
+                            config,
+                            device,
+                            node,
+                            &color_temperature.id,
+                            value,
+                            ids,
+                        )
+                        .await;
                     }
                 }
             }
             GHomeCommand::ColorAbsolute(color_absolute) => {
-                if let Some(color) = node.properties.get("color") {
+                if let Some(color) = role_property(node, config, PropertyRole::Color) {
                     if let Some(value) = color_absolute_to_property_value(color, color_absolute) {
-                        return set_value(controller, device, node, "color", value, ids).await;
+                        return set_value(controller, config, device, node, &color.id, value, ids)
+                            .await;
+                    }
+                }
+            }
+            GHomeCommand::Dock(_) => {
+                if let Some(dock) = node.properties.get("dock") {
+                    if dock.settable && dock.datatype == Some(Datatype::Boolean) {
+                        return set_value(controller, config, device, node, "dock", true, ids)
+                            .await;
+                    }
+                }
+            }
+            GHomeCommand::LockUnlock(lock_unlock) => {
+                if let Some(locked) = node.properties.get("locked") {
+                    if locked.settable && locked.datatype == Some(Datatype::Boolean) {
+                        return set_value(
+                            controller,
+                            config,
+                            device,
+                            node,
+                            "locked",
+                            lock_unlock.lock,
+                            ids,
+                        )
+                        .await;
+                    }
+                }
+            }
+            GHomeCommand::OpenClose(open_close) => {
+                if let Some(position) = node.properties.get("position") {
+                    if let Some(value) = percentage_to_property_value(
+                        position,
+                        open_close.open_percent,
+                        0,
+                        config.float_precision,
+                    ) {
+                        return set_value(controller, config, device, node, "position", value, ids)
+                            .await;
+                    }
+                }
+            }
+            // Factory resets aren't supported, only rebooting.
+            GHomeCommand::Reboot(reboot) if !reboot.factory_reset => {
+                if let Some(reboot) = node.properties.get("reboot") {
+                    if reboot.settable && reboot.datatype == Some(Datatype::Boolean) {
+                        return set_value(controller, config, device, node, "reboot", true, ids)
+                            .await;
+                    }
+                }
+            }
+            GHomeCommand::RotateAbsolute(rotate_absolute) => {
+                if let Some(tilt) = node.properties.get("tilt") {
+                    if let Some(value) = rotate_absolute_to_property_value(
+                        tilt,
+                        rotate_absolute,
+                        config.float_precision,
+                    ) {
+                        return set_value(controller, config, device, node, "tilt", value, ids)
+                            .await;
+                    }
+                }
+            }
+            GHomeCommand::SetModes(set_modes)
+                if set_modes
+                    .update_mode_settings
+                    .iter()
+                    .all(|(mode, setting)| is_valid_mode_setting(node, mode, setting)) =>
+            {
+                let mut response = None;
+                for (mode, setting) in &set_modes.update_mode_settings {
+                    let result = set_value(
+                        controller,
+                        config,
+                        device,
+                        node,
+                        mode,
+                        setting.to_owned(),
+                        ids.clone(),
+                    )
+                    .await;
+                    if result.status == response::PayloadCommandStatus::Error {
+                        return result;
+                    }
+                    response = Some(result);
+                }
+                // If there were no settings to update then there is nothing to do.
+                return response.unwrap_or_else(|| command_error(ids, "actionNotAvailable"));
+            }
+            GHomeCommand::SetTemperature(set_temperature) => {
+                if let Some(temperature) = role_property(node, config, PropertyRole::Temperature) {
+                    if temperature.settable {
+                        if let Some(value) = number_to_property_value(
+                            temperature,
+                            set_temperature.temperature,
+                            config.float_precision,
+                        ) {
+                            return set_value(
+                                controller,
+                                config,
+                                device,
+                                node,
+                                &temperature.id,
+                                value,
+                                ids,
+                            )
+                            .await;
+                        }
+                    }
+                }
+            }
+            GHomeCommand::SetHumidity(set_humidity) => {
+                if let Some(target_humidity) = target_humidity_property(node) {
+                    if target_humidity.settable {
+                        if let Some(value) = number_to_property_value(
+                            target_humidity,
+                            set_humidity.humidity.into(),
+                            config.float_precision,
+                        ) {
+                            return set_value(
+                                controller,
+                                config,
+                                device,
+                                node,
+                                "target-humidity",
+                                value,
+                                ids,
+                            )
+                            .await;
+                        }
+                    }
+                }
+            }
+            // Scenes are not reversible, so there is nothing to do for deactivation.
+            GHomeCommand::ActivateScene(activate_scene) if !activate_scene.deactivate => {
+                if let Some(activate) = node.properties.get("activate") {
+                    if activate.datatype == Some(Datatype::Boolean) {
+                        return set_value(controller, config, device, node, "activate", true, ids)
+                            .await;
                     }
                 }
             }
@@ -114,20 +469,90 @@ async fn execute_homie_device(
     }
 }
 
+/// Converts a `RotateAbsolute` command to the value to set on the given `tilt` property, if it is
+/// settable and the command is in the units it uses.
+fn rotate_absolute_to_property_value(
+    tilt: &Property,
+    rotate_absolute: &RotateAbsolute,
+    float_precision: Option<usize>,
+) -> Option<String> {
+    if !tilt.settable {
+        return None;
+    }
+    match rotate_absolute {
+        RotateAbsolute::Percent { rotation_percent } if is_percent_property(tilt) => {
+            percentage_to_property_value(tilt, rotation_percent.round() as u8, 0, float_precision)
+        }
+        RotateAbsolute::Degrees { rotation_degrees } if !is_percent_property(tilt) => {
+            number_to_property_value(tilt, *rotation_degrees, float_precision)
+        }
+        _ => None,
+    }
+}
+
+/// Converts a colour temperature in Kelvin to the value to set on the given `color-temperature`
+/// property, if it is settable. The temperature is clamped to the property's range.
+fn color_temperature_to_property_value(
+    color_temperature: &Property,
+    temperature: f64,
+    float_precision: Option<usize>,
+) -> Option<String> {
+    if !color_temperature.settable {
+        return None;
+    }
+    let range = property_range(color_temperature)?;
+    number_to_property_value(
+        color_temperature,
+        temperature.max(*range.start()).min(*range.end()),
+        float_precision,
+    )
+}
+
+/// Returns whether the node has a mode property with the given ID which accepts the given setting.
+fn is_valid_mode_setting(node: &Node, mode: &str, setting: &str) -> bool {
+    if let Some(property) = node.properties.get(mode) {
+        is_mode_property(property)
+            && property
+                .enum_values()
+                .is_ok_and(|values| values.contains(&setting))
+    } else {
+        false
+    }
+}
+
 async fn set_value(
     controller: &HomieController,
+    config: &Devices,
     device: &Device,
     node: &Node,
     property_id: &str,
     value: impl Value,
     ids: Vec<String>,
 ) -> response::PayloadCommand {
-    if controller
-        .set(&device.id, &node.id, property_id, value)
+    let value = value.to_string();
+    let result = match node.properties.get(property_id) {
+        Some(property) => check_value(property, &value),
+        None => Ok(()),
+    };
+    let result = match result {
+        Ok(()) => retry(config.set_retries, SET_RETRY_DELAY, || {
+            controller.set(&device.id, &node.id, property_id, value.clone())
+        })
         .await
-        .is_err()
-    {
-        command_error(ids, "transientError")
+        .map_err(SetError::from),
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        tracing::warn!(
+            "Error setting {}/{}/{} to {:?}: {}",
+            device.id,
+            node.id,
+            property_id,
+            value,
+            e
+        );
+        command_error(ids, e.error_code())
     } else {
         response::PayloadCommand {
             ids,
@@ -138,6 +563,49 @@ async fn set_value(
     }
 }
 
+/// An error setting a Homie property.
+#[derive(Debug, Error)]
+enum SetError {
+    #[error("property is not settable")]
+    NotSettable,
+    #[error("value doesn't match datatype {0:?}")]
+    InvalidValue(Option<Datatype>),
+    #[error("value is outside the range {0:?}")]
+    OutOfRange(RangeInclusive<f64>),
+    #[error("{0}")]
+    Client(#[from] ClientError),
+}
+
+impl SetError {
+    /// Returns the Google Home error code for the error. Only failures to send the value to the
+    /// MQTT broker are transient; trying again with the same value won't fix the others.
+    fn error_code(&self) -> &'static str {
+        match self {
+            Self::NotSettable => "actionNotAvailable",
+            Self::InvalidValue(_) => "protocolError",
+            Self::OutOfRange(_) => "valueOutOfRange",
+            Self::Client(ClientError::Mqtt4(_)) => "hardError",
+            Self::Client(_) => "transientError",
+        }
+    }
+}
+
+/// Checks that the given value can be set on the property, before sending it to the device.
+fn check_value(property: &Property, value: &str) -> Result<(), SetError> {
+    if !property.settable {
+        return Err(SetError::NotSettable);
+    }
+    if !is_valid_value(property, value) {
+        return Err(SetError::InvalidValue(property.datatype));
+    }
+    if let (Some(range), Ok(number)) = (property_range(property), value.parse::<f64>()) {
+        if !range.contains(&number) {
+            return Err(SetError::OutOfRange(range));
+        }
+    }
+    Ok(())
+}
+
 fn command_error(ids: Vec<String>, error_code: &str) -> response::PayloadCommand {
     response::PayloadCommand {
         ids,
@@ -146,3 +614,1395 @@ fn command_error(ids: Vec<String>, error_code: &str) -> response::PayloadCommand
         error_code: Some(error_code.to_string()),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::config::server::{Config, TemperatureTrait};
+    use google_smart_home::device::commands::{
+        ActivateScene, Dock, OnOff, Reboot, SetHumidity, SetModes, SetTemperature,
+    };
+    use google_smart_home::query;
+    use homie_controller::State;
+    use rumqttc::{MqttOptions, QoS};
+    use std::str::FromStr;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn activate_scene() {
+        let activate_property = Property {
+            id: "activate".to_string(),
+            name: Some("Activate".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: false,
+            unit: None,
+            format: None,
+            value: None,
+        };
+        let node = Node {
+            id: "movie".to_string(),
+            name: Some("Movie night".to_string()),
+            node_type: Some("scene".to_string()),
+            properties: property_set(vec![activate_property]),
+        };
+        let device = Device {
+            id: "scenes".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Scenes".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let command_device = PayloadCommandDevice {
+            id: "scenes/movie".to_string(),
+            custom_data: Default::default(),
+        };
+
+        assert_eq!(
+            execute_homie_device(
+                &controller,
+                &Devices::default(),
+                None,
+                &devices,
+                &PayloadCommandExecution {
+                    command: GHomeCommand::ActivateScene(ActivateScene { deactivate: false }),
+                },
+                &command_device,
+            )
+            .await,
+            response::PayloadCommand {
+                ids: vec!["scenes/movie".to_string()],
+                status: response::PayloadCommandStatus::Pending,
+                states: Default::default(),
+                error_code: None,
+            }
+        );
+        assert_eq!(
+            execute_homie_device(
+                &controller,
+                &Devices::default(),
+                None,
+                &devices,
+                &PayloadCommandExecution {
+                    command: GHomeCommand::ActivateScene(ActivateScene { deactivate: true }),
+                },
+                &command_device,
+            )
+            .await,
+            command_error(vec!["scenes/movie".to_string()], "actionNotAvailable")
+        );
+    }
+
+    #[tokio::test]
+    async fn audit_log() {
+        let activate_property = Property {
+            id: "activate".to_string(),
+            name: Some("Activate".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: false,
+            unit: None,
+            format: None,
+            value: None,
+        };
+        let node = Node {
+            id: "movie".to_string(),
+            name: Some("Movie night".to_string()),
+            node_type: Some("scene".to_string()),
+            properties: property_set(vec![activate_property]),
+        };
+        let device = Device {
+            id: "scenes".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Scenes".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let audit_log =
+            std::env::temp_dir().join(format!("homieflow-audit-{}.log", uuid::Uuid::new_v4()));
+        let commands = [request::PayloadCommand {
+            devices: vec![
+                PayloadCommandDevice {
+                    id: "scenes/movie".to_string(),
+                    custom_data: Default::default(),
+                },
+                PayloadCommandDevice {
+                    id: "scenes/missing".to_string(),
+                    custom_data: Default::default(),
+                },
+            ],
+            execution: vec![PayloadCommandExecution {
+                command: GHomeCommand::ActivateScene(ActivateScene { deactivate: false }),
+            }],
+        }];
+
+        let (responses, _) = execute_homie_devices(
+            &controller,
+            user_id,
+            Some(&audit_log),
+            &Devices::default(),
+            None,
+            None,
+            None,
+            &devices,
+            &commands,
+        )
+        .await;
+        assert_eq!(responses[0].status, response::PayloadCommandStatus::Pending);
+        assert_eq!(responses[1].status, response::PayloadCommandStatus::Error);
+
+        // Only the successful command is recorded.
+        let log = std::fs::read_to_string(&audit_log).unwrap();
+        std::fs::remove_file(&audit_log).unwrap();
+        let records: Vec<serde_json::Value> = log
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(records.len(), 1);
+        let record = records[0].as_object().unwrap();
+        assert!(record.contains_key("timestamp"));
+        assert_eq!(record["user-id"], "861cccea-a3e3-4913-8ce2-498768dbfe09");
+        assert_eq!(record["device-id"], "scenes/movie");
+        assert_eq!(record["command"], "action.devices.commands.ActivateScene");
+        assert_eq!(record["params"], serde_json::json!({ "deactivate": false }));
+        assert_eq!(record["status"], "PENDING");
+    }
+
+    #[tokio::test]
+    async fn dock() {
+        let dock_property = Property {
+            id: "dock".to_string(),
+            name: Some("Docked".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("false".to_string()),
+        };
+        let node = Node {
+            id: "robot".to_string(),
+            name: Some("Robot".to_string()),
+            node_type: None,
+            properties: property_set(vec![dock_property.clone()]),
+        };
+        let read_only_node = Node {
+            id: "read-only".to_string(),
+            name: Some("Read-only robot".to_string()),
+            node_type: None,
+            properties: property_set(vec![Property {
+                settable: false,
+                ..dock_property
+            }]),
+        };
+        let device = Device {
+            id: "vacuum".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Vacuum".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node, read_only_node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let execution = PayloadCommandExecution {
+            command: GHomeCommand::Dock(Dock {}),
+        };
+        let execute = |node_id: &str| {
+            let command_device = PayloadCommandDevice {
+                id: format!("vacuum/{}", node_id),
+                custom_data: Default::default(),
+            };
+            let controller = &controller;
+            let devices = &devices;
+            let execution = &execution;
+            async move {
+                execute_homie_device(
+                    controller,
+                    &Devices::default(),
+                    None,
+                    devices,
+                    execution,
+                    &command_device,
+                )
+                .await
+            }
+        };
+
+        assert_eq!(
+            execute("robot").await,
+            response::PayloadCommand {
+                ids: vec!["vacuum/robot".to_string()],
+                status: response::PayloadCommandStatus::Pending,
+                states: Default::default(),
+                error_code: None,
+            }
+        );
+        // The dock property must be settable.
+        assert_eq!(
+            execute("read-only").await,
+            command_error(vec!["vacuum/read-only".to_string()], "actionNotAvailable")
+        );
+    }
+
+    #[tokio::test]
+    async fn reboot() {
+        let reboot_property = Property {
+            id: "reboot".to_string(),
+            name: Some("Reboot".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: false,
+            unit: None,
+            format: None,
+            value: None,
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Sensor".to_string()),
+            node_type: None,
+            properties: property_set(vec![reboot_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let command_device = PayloadCommandDevice {
+            id: "device/node".to_string(),
+            custom_data: Default::default(),
+        };
+        let execute = |factory_reset| {
+            let execution = PayloadCommandExecution {
+                command: GHomeCommand::Reboot(Reboot { factory_reset }),
+            };
+            let controller = &controller;
+            let devices = &devices;
+            let command_device = &command_device;
+            async move {
+                execute_homie_device(
+                    controller,
+                    &Devices::default(),
+                    None,
+                    devices,
+                    &execution,
+                    command_device,
+                )
+                .await
+            }
+        };
+
+        assert_eq!(
+            execute(false).await,
+            response::PayloadCommand {
+                ids: vec!["device/node".to_string()],
+                status: response::PayloadCommandStatus::Pending,
+                states: Default::default(),
+                error_code: None,
+            }
+        );
+        // Factory resets aren't supported.
+        assert_eq!(
+            execute(true).await,
+            command_error(vec!["device/node".to_string()], "actionNotAvailable")
+        );
+    }
+
+    #[tokio::test]
+    async fn set_humidity() {
+        let target_humidity_property = Property {
+            id: "target-humidity".to_string(),
+            name: Some("Target humidity".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("%".to_string()),
+            format: Some("30:70".to_string()),
+            value: Some("50".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Air conditioner".to_string()),
+            node_type: None,
+            properties: property_set(vec![target_humidity_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let command_device = PayloadCommandDevice {
+            id: "device/node".to_string(),
+            custom_data: Default::default(),
+        };
+        let execute = |humidity| {
+            let execution = PayloadCommandExecution {
+                command: GHomeCommand::SetHumidity(SetHumidity { humidity }),
+            };
+            let controller = &controller;
+            let devices = &devices;
+            let command_device = &command_device;
+            async move {
+                execute_homie_device(
+                    controller,
+                    &Devices::default(),
+                    None,
+                    devices,
+                    &execution,
+                    command_device,
+                )
+                .await
+            }
+        };
+
+        assert_eq!(
+            execute(45).await,
+            response::PayloadCommand {
+                ids: vec!["device/node".to_string()],
+                status: response::PayloadCommandStatus::Pending,
+                states: Default::default(),
+                error_code: None,
+            }
+        );
+        // Values outside the property's range are rejected.
+        assert_eq!(
+            execute(90).await,
+            command_error(vec!["device/node".to_string()], "valueOutOfRange")
+        );
+    }
+
+    #[tokio::test]
+    async fn setpoint_report() {
+        let temperature_property = Property {
+            id: "temperature".to_string(),
+            name: Some("Temperature".to_string()),
+            datatype: Some(Datatype::Float),
+            settable: true,
+            retained: true,
+            unit: Some("°C".to_string()),
+            format: Some("10:30".to_string()),
+            value: Some("18".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Kettle".to_string()),
+            node_type: None,
+            properties: property_set(vec![temperature_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let config = Devices {
+            temperature_trait: TemperatureTrait::TemperatureControl,
+            ..Default::default()
+        };
+        let commands = |temperature| {
+            [request::PayloadCommand {
+                devices: vec![PayloadCommandDevice {
+                    id: "device/node".to_string(),
+                    custom_data: Default::default(),
+                }],
+                execution: vec![PayloadCommandExecution {
+                    command: GHomeCommand::SetTemperature(SetTemperature { temperature }),
+                }],
+            }]
+        };
+
+        let (responses, reports) = execute_homie_devices(
+            &controller,
+            user_id,
+            None,
+            &config,
+            None,
+            None,
+            None,
+            &devices,
+            &commands(21.5),
+        )
+        .await;
+        assert_eq!(responses[0].status, response::PayloadCommandStatus::Pending);
+        assert_eq!(
+            reports,
+            vec![(
+                "device/node".to_string(),
+                query::response::State {
+                    online: true,
+                    temperature_setpoint_celsius: Some(21.5),
+                    ..Default::default()
+                }
+            )]
+        );
+
+        // Nothing is reported if the set fails.
+        let (responses, reports) = execute_homie_devices(
+            &controller,
+            user_id,
+            None,
+            &config,
+            None,
+            None,
+            None,
+            &devices,
+            &commands(40.0),
+        )
+        .await;
+        assert_eq!(responses[0].status, response::PayloadCommandStatus::Error);
+        assert_eq!(reports, vec![]);
+    }
+
+    #[tokio::test]
+    async fn set_modes() {
+        let mode_property = Property {
+            id: "mode".to_string(),
+            name: Some("Wash mode".to_string()),
+            datatype: Some(Datatype::Enum),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("delicate,normal,heavy".to_string()),
+            value: Some("normal".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![mode_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let command_device = PayloadCommandDevice {
+            id: "device/node".to_string(),
+            custom_data: Default::default(),
+        };
+        let set_modes = |mode: &str, setting: &str| PayloadCommandExecution {
+            command: GHomeCommand::SetModes(SetModes {
+                update_mode_settings: [(mode.to_string(), setting.to_string())]
+                    .into_iter()
+                    .collect(),
+            }),
+        };
+
+        assert_eq!(
+            execute_homie_device(
+                &controller,
+                &Devices::default(),
+                None,
+                &devices,
+                &set_modes("mode", "heavy"),
+                &command_device,
+            )
+            .await,
+            response::PayloadCommand {
+                ids: vec!["device/node".to_string()],
+                status: response::PayloadCommandStatus::Pending,
+                states: Default::default(),
+                error_code: None,
+            }
+        );
+        // Settings which aren't in the enum are rejected.
+        assert_eq!(
+            execute_homie_device(
+                &controller,
+                &Devices::default(),
+                None,
+                &devices,
+                &set_modes("mode", "spin"),
+                &command_device,
+            )
+            .await,
+            command_error(vec!["device/node".to_string()], "actionNotAvailable")
+        );
+        // As are unknown modes.
+        assert_eq!(
+            execute_homie_device(
+                &controller,
+                &Devices::default(),
+                None,
+                &devices,
+                &set_modes("speed", "heavy"),
+                &command_device,
+            )
+            .await,
+            command_error(vec!["device/node".to_string()], "actionNotAvailable")
+        );
+    }
+
+    #[tokio::test]
+    async fn on_off_enum() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Enum),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("on,off".to_string()),
+            value: Some("off".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let command_device = PayloadCommandDevice {
+            id: "device/node".to_string(),
+            custom_data: Default::default(),
+        };
+
+        assert_eq!(
+            execute_homie_device(
+                &controller,
+                &Devices::default(),
+                None,
+                &devices,
+                &PayloadCommandExecution {
+                    command: GHomeCommand::OnOff(OnOff { on: true }),
+                },
+                &command_device,
+            )
+            .await,
+            response::PayloadCommand {
+                ids: vec!["device/node".to_string()],
+                status: response::PayloadCommandStatus::Pending,
+                states: Default::default(),
+                error_code: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn on_off_integer() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("0:1".to_string()),
+            value: Some("0".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Relay".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let command_device = PayloadCommandDevice {
+            id: "device/node".to_string(),
+            custom_data: Default::default(),
+        };
+
+        for on in [true, false] {
+            assert_eq!(
+                execute_homie_device(
+                    &controller,
+                    &Devices::default(),
+                    None,
+                    &devices,
+                    &PayloadCommandExecution {
+                        command: GHomeCommand::OnOff(OnOff { on }),
+                    },
+                    &command_device,
+                )
+                .await,
+                response::PayloadCommand {
+                    ids: vec!["device/node".to_string()],
+                    status: response::PayloadCommandStatus::Pending,
+                    states: Default::default(),
+                    error_code: None,
+                }
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn read_only() {
+        let config: Config = toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [[users]]
+            id = "861ccceaa3e349138ce2498768dbfe09"
+            email = "a@example.com"
+            read-only = true
+            "#,
+        )
+        .unwrap();
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let state = crate::State::new(config, HashMap::from([(user_id, Arc::new(controller))]));
+
+        let payload = request::Payload {
+            commands: vec![request::PayloadCommand {
+                devices: vec![
+                    PayloadCommandDevice {
+                        id: "light/node".to_string(),
+                        custom_data: Default::default(),
+                    },
+                    PayloadCommandDevice {
+                        id: "switch/node".to_string(),
+                        custom_data: Default::default(),
+                    },
+                ],
+                execution: vec![PayloadCommandExecution {
+                    command: GHomeCommand::OnOff(OnOff { on: true }),
+                }],
+            }],
+        };
+        assert_eq!(
+            handle(state.clone(), user_id, &payload).await.unwrap(),
+            response::Payload {
+                error_code: None,
+                debug_string: None,
+                commands: vec![command_error(
+                    vec!["light/node".to_string(), "switch/node".to_string()],
+                    "actionNotAvailable"
+                )],
+            }
+        );
+
+        // Queries are still answered.
+        let query_payload = query::request::Payload {
+            devices: vec![query::request::PayloadDevice {
+                id: "light/node".to_string(),
+                custom_data: None,
+            }],
+        };
+        let query_response = crate::fulfillment::query::handle(state, user_id, &query_payload)
+            .await
+            .unwrap();
+        assert_eq!(query_response.error_code, None);
+        assert!(query_response.devices.contains_key("light/node"));
+    }
+
+    #[tokio::test]
+    async fn set_mismatched_value() {
+        let brightness_property = Property {
+            id: "brightness".to_string(),
+            name: Some("Brightness".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("0:100".to_string()),
+            value: Some("50".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![brightness_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node.clone()]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let ids = vec!["device/node".to_string()];
+
+        assert_eq!(
+            set_value(
+                &controller,
+                &Devices::default(),
+                &device,
+                &node,
+                "brightness",
+                "255,255,0".to_string(),
+                ids.clone()
+            )
+            .await,
+            command_error(ids.clone(), "protocolError")
+        );
+        assert_eq!(
+            set_value(
+                &controller,
+                &Devices::default(),
+                &device,
+                &node,
+                "brightness",
+                42,
+                ids.clone()
+            )
+            .await,
+            response::PayloadCommand {
+                ids,
+                status: response::PayloadCommandStatus::Pending,
+                states: Default::default(),
+                error_code: None,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn set_error_classification() {
+        let brightness_property = Property {
+            id: "brightness".to_string(),
+            name: Some("Brightness".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("0:100".to_string()),
+            value: Some("50".to_string()),
+        };
+        let status_property = Property {
+            id: "status".to_string(),
+            name: Some("Status".to_string()),
+            datatype: Some(Datatype::String),
+            settable: false,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("fine".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![brightness_property, status_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node.clone()]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let (controller, event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let ids = vec!["device/node".to_string()];
+        let config = Devices::default();
+        let set = |property_id, value: &str| {
+            set_value(
+                &controller,
+                &config,
+                &device,
+                &node,
+                property_id,
+                value.to_owned(),
+                ids.clone(),
+            )
+        };
+
+        // Permanent errors are caught before sending anything.
+        assert_eq!(
+            set("status", "broken").await,
+            command_error(ids.clone(), "actionNotAvailable")
+        );
+        assert_eq!(
+            set("brightness", "bright").await,
+            command_error(ids.clone(), "protocolError")
+        );
+        assert_eq!(
+            set("brightness", "101").await,
+            command_error(ids.clone(), "valueOutOfRange")
+        );
+
+        // Without an event loop the value can't be sent to the broker, which may be temporary.
+        drop(event_loop);
+        assert_eq!(
+            set("brightness", "42").await,
+            command_error(ids.clone(), "transientError")
+        );
+    }
+
+    #[tokio::test]
+    async fn rotate_blinds() {
+        let position_property = Property {
+            id: "position".to_string(),
+            name: Some("Position".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("%".to_string()),
+            format: Some("0:100".to_string()),
+            value: Some("40".to_string()),
+        };
+        let tilt_property = Property {
+            id: "tilt".to_string(),
+            name: Some("Tilt".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("°".to_string()),
+            format: Some("-90:90".to_string()),
+            value: Some("30".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![position_property, tilt_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let command_device = PayloadCommandDevice {
+            id: "device/node".to_string(),
+            custom_data: Default::default(),
+        };
+
+        assert_eq!(
+            execute_homie_device(
+                &controller,
+                &Devices::default(),
+                None,
+                &devices,
+                &PayloadCommandExecution {
+                    command: GHomeCommand::RotateAbsolute(RotateAbsolute::Degrees {
+                        rotation_degrees: 45.0
+                    }),
+                },
+                &command_device,
+            )
+            .await,
+            response::PayloadCommand {
+                ids: vec!["device/node".to_string()],
+                status: response::PayloadCommandStatus::Pending,
+                states: Default::default(),
+                error_code: None,
+            }
+        );
+        // The tilt is in degrees, so percentages aren't supported.
+        assert_eq!(
+            execute_homie_device(
+                &controller,
+                &Devices::default(),
+                None,
+                &devices,
+                &PayloadCommandExecution {
+                    command: GHomeCommand::RotateAbsolute(RotateAbsolute::Percent {
+                        rotation_percent: 50.0
+                    }),
+                },
+                &command_device,
+            )
+            .await,
+            command_error(vec!["device/node".to_string()], "actionNotAvailable")
+        );
+    }
+
+    #[tokio::test]
+    async fn color_and_temperature() {
+        let color_property = Property {
+            id: "color".to_string(),
+            name: Some("Colour".to_string()),
+            datatype: Some(Datatype::Color),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("rgb".to_string()),
+            value: Some("255,255,0".to_string()),
+        };
+        let color_temperature_property = Property {
+            id: "color-temperature".to_string(),
+            name: Some("Colour temperature".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("K".to_string()),
+            format: Some("2700:6500".to_string()),
+            value: Some("3000".to_string()),
+        };
+        let both = Node {
+            id: "both".to_string(),
+            name: Some("Both".to_string()),
+            node_type: None,
+            properties: property_set(vec![
+                color_property.clone(),
+                color_temperature_property.clone(),
+            ]),
+        };
+        let color_only = Node {
+            id: "color".to_string(),
+            name: Some("Colour only".to_string()),
+            node_type: None,
+            properties: property_set(vec![color_property]),
+        };
+        let temperature_only = Node {
+            id: "temperature".to_string(),
+            name: Some("Temperature only".to_string()),
+            node_type: None,
+            properties: property_set(vec![color_temperature_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![both, color_only, temperature_only]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let spectrum = PayloadCommandExecution {
+            command: GHomeCommand::ColorAbsolute(ColorAbsolute {
+                color: Color {
+                    name: None,
+                    value: Some(ColorValue::Rgb {
+                        spectrum_rgb: 0xff0000,
+                    }),
+                },
+            }),
+        };
+        let temperature = PayloadCommandExecution {
+            command: GHomeCommand::ColorAbsolute(ColorAbsolute {
+                color: Color {
+                    name: None,
+                    value: Some(ColorValue::Temperature { temperature: 4000 }),
+                },
+            }),
+        };
+        // Google may send only a colour name, without a spectrum value.
+        let named: PayloadCommandExecution = serde_json::from_str(
+            r#"{
+                "command": "action.devices.commands.ColorAbsolute",
+                "params": { "color": { "name": "red" } }
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            named.command,
+            GHomeCommand::ColorAbsolute(ColorAbsolute {
+                color: Color {
+                    name: Some("red".to_string()),
+                    value: None,
+                },
+            })
+        );
+        let execute = |execution, node_id: &str| {
+            let command_device = PayloadCommandDevice {
+                id: format!("device/{}", node_id),
+                custom_data: Default::default(),
+            };
+            let controller = &controller;
+            let devices = &devices;
+            async move {
+                execute_homie_device(
+                    controller,
+                    &Devices::default(),
+                    None,
+                    devices,
+                    execution,
+                    &command_device,
+                )
+                .await
+                .status
+            }
+        };
+
+        // With both properties, each form of command is supported.
+        assert_eq!(
+            execute(&spectrum, "both").await,
+            response::PayloadCommandStatus::Pending
+        );
+        assert_eq!(
+            execute(&temperature, "both").await,
+            response::PayloadCommandStatus::Pending
+        );
+        // Otherwise only the form for the property which is present is.
+        assert_eq!(
+            execute(&spectrum, "color").await,
+            response::PayloadCommandStatus::Pending
+        );
+        assert_eq!(
+            execute(&temperature, "color").await,
+            response::PayloadCommandStatus::Error
+        );
+        assert_eq!(
+            execute(&spectrum, "temperature").await,
+            response::PayloadCommandStatus::Error
+        );
+        assert_eq!(
+            execute(&temperature, "temperature").await,
+            response::PayloadCommandStatus::Pending
+        );
+        // A colour name is looked up for the colour property.
+        assert_eq!(
+            execute(&named, "color").await,
+            response::PayloadCommandStatus::Pending
+        );
+        assert_eq!(
+            execute(&named, "temperature").await,
+            response::PayloadCommandStatus::Error
+        );
+    }
+
+    #[test]
+    fn color_temperature_clamped() {
+        let color_temperature = Property {
+            id: "color-temperature".to_string(),
+            name: Some("Colour temperature".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("K".to_string()),
+            format: Some("2700:6500".to_string()),
+            value: Some("3000".to_string()),
+        };
+
+        assert_eq!(
+            color_temperature_to_property_value(&color_temperature, 4000.0, None),
+            Some("4000".to_string())
+        );
+        assert_eq!(
+            color_temperature_to_property_value(&color_temperature, 2000.0, None),
+            Some("2700".to_string())
+        );
+        assert_eq!(
+            color_temperature_to_property_value(&color_temperature, 9000.0, None),
+            Some("6500".to_string())
+        );
+    }
+
+    #[test]
+    fn rotate_absolute_percent() {
+        let tilt = Property {
+            id: "tilt".to_string(),
+            name: Some("Tilt".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("%".to_string()),
+            format: Some("0:200".to_string()),
+            value: Some("30".to_string()),
+        };
+
+        assert_eq!(
+            rotate_absolute_to_property_value(
+                &tilt,
+                &RotateAbsolute::Percent {
+                    rotation_percent: 25.0
+                },
+                None
+            ),
+            Some("50".to_string())
+        );
+        assert_eq!(
+            rotate_absolute_to_property_value(
+                &tilt,
+                &RotateAbsolute::Degrees {
+                    rotation_degrees: 25.0
+                },
+                None
+            ),
+            None
+        );
+    }
+
+    fn property_set(properties: Vec<Property>) -> HashMap<String, Property> {
+        properties
+            .into_iter()
+            .map(|property| (property.id.clone(), property))
+            .collect()
+    }
+
+    fn node_set(nodes: Vec<Node>) -> HashMap<String, Node> {
+        nodes
+            .into_iter()
+            .map(|node| (node.id.clone(), node))
+            .collect()
+    }
+
+    fn device_set(devices: Vec<Device>) -> HashMap<String, Device> {
+        devices
+            .into_iter()
+            .map(|device| (device.id.clone(), device))
+            .collect()
+    }
+}
@@ -14,8 +14,28 @@ use crate::homie::get_homie_node;
 use homie_controller::{Device, Node};
 use std::collections::HashMap;
 
-/// Given an ID of the form `"device_id/node_id"`, looks up the corresponding Homie node (if any).
+/// Given an ID of the form `"device_id/node_id"` or `"prefix/device_id/node_id"`, looks up the
+/// corresponding Homie node (if any).
+///
+/// IDs without the prefix are also accepted, so that devices synced before the prefix was configured
+/// keep working.
 pub fn get_homie_device_by_id<'a>(
+    devices: &'a HashMap<String, Device>,
+    device_id_prefix: Option<&str>,
+    id: &str,
+) -> Option<(&'a Device, &'a Node)> {
+    if let Some(unprefixed_id) = device_id_prefix
+        .and_then(|prefix| id.strip_prefix(prefix))
+        .and_then(|id| id.strip_prefix('/'))
+    {
+        if let Some(found) = get_homie_device_by_unprefixed_id(devices, unprefixed_id) {
+            return Some(found);
+        }
+    }
+    get_homie_device_by_unprefixed_id(devices, id)
+}
+
+fn get_homie_device_by_unprefixed_id<'a>(
     devices: &'a HashMap<String, Device>,
     id: &str,
 ) -> Option<(&'a Device, &'a Node)> {
@@ -26,3 +46,69 @@ pub fn get_homie_device_by_id<'a>(
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::homie::google_device_id;
+    use homie_controller::State;
+
+    #[test]
+    fn round_trip_ids() {
+        let devices = test_devices();
+
+        for prefix in [None, Some("broker"), Some("nested/prefix")] {
+            let id = google_device_id(prefix, "device", "node");
+            let (device, node) = get_homie_device_by_id(&devices, prefix, &id).unwrap();
+            assert_eq!(device.id, "device");
+            assert_eq!(node.id, "node");
+        }
+    }
+
+    #[test]
+    fn prefixed_ids() {
+        let devices = test_devices();
+
+        assert_eq!(google_device_id(None, "device", "node"), "device/node");
+        assert_eq!(
+            google_device_id(Some("broker"), "device", "node"),
+            "broker/device/node"
+        );
+        // Unprefixed IDs are still accepted when a prefix is configured.
+        assert!(get_homie_device_by_id(&devices, Some("broker"), "device/node").is_some());
+        // Prefixed IDs aren't accepted if no prefix or a different prefix is configured.
+        assert!(get_homie_device_by_id(&devices, None, "broker/device/node").is_none());
+        assert!(get_homie_device_by_id(&devices, Some("other"), "broker/device/node").is_none());
+    }
+
+    fn test_devices() -> HashMap<String, Device> {
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: HashMap::new(),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: [(node.id.clone(), node)].into_iter().collect(),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        [(device.id.clone(), device)].into_iter().collect()
+    }
+}
@@ -14,43 +14,590 @@ mod execute;
 mod homie;
 mod query;
 mod sync;
+pub mod sync_cache;
 
+use crate::extractors::ClientIp;
+use crate::extractors::JsonBody;
 use crate::extractors::UserID;
 use crate::types::errors::ServerError;
+use crate::types::user;
 use crate::State;
+use async_trait::async_trait;
+use axum::body::Body;
 use axum::extract::Extension;
+use axum::extract::FromRequest;
+use axum::extract::Path;
+use axum::extract::Query;
 use axum::Json;
+use google_smart_home::DisconnectResponse;
+use google_smart_home::ErrorPayload;
+use google_smart_home::ErrorResponse;
 use google_smart_home::Request;
 use google_smart_home::RequestInput;
 use google_smart_home::Response;
+use http::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::borrow::Cow;
+use std::mem;
+use std::mem::discriminant;
 
-#[tracing::instrument(name = "GHome", skip(state), err)]
+/// The maximum length of request IDs to include in logs.
+const MAX_LOGGED_REQUEST_ID_LENGTH: usize = 64;
+
+#[tracing::instrument(
+    name = "GHome",
+    skip(state, client_ip, request),
+    fields(
+        request_id = %request_id_for_log(&request.request_id),
+        client_ip = %client_ip,
+    ),
+    err
+)]
 pub async fn handle(
+    Extension(state): Extension<State>,
+    client_ip: ClientIp,
+    UserID(user_id): UserID,
+    LoggedJsonBody(request): LoggedJsonBody<Request>,
+) -> Result<Json<Response>, ServerError> {
+    handle_request(state, user_id, request).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TestQuery {
+    user_id: user::ID,
+}
+
+/// Handles a fulfillment request for the user given in the query string, without any
+/// authentication. This must only be used for development.
+#[tracing::instrument(
+    name = "GHomeTest",
+    skip(state, request),
+    fields(request_id = %request_id_for_log(&request.request_id)),
+    err
+)]
+pub async fn handle_test(
+    Extension(state): Extension<State>,
+    Query(query): Query<TestQuery>,
+    LoggedJsonBody(request): LoggedJsonBody<Request>,
+) -> Result<Json<Response>, ServerError> {
+    tracing::warn!("Handling unauthenticated test fulfillment request.");
+    handle_request(state, query.user_id, request).await
+}
+
+/// Returns the state of a single one of the user's devices as Google Home would see it, for
+/// debugging. The ID is the Google Home device ID, such as `device/node`.
+#[tracing::instrument(name = "DebugDevice", skip(state))]
+pub async fn handle_debug_device(
     Extension(state): Extension<State>,
     UserID(user_id): UserID,
-    Json(request): Json<Request>,
+    Path(device_id): Path<String>,
+) -> Result<(StatusCode, Json<query::DebugDevice>), ServerError> {
+    // The wildcard path segment includes the leading slash.
+    let device_id = device_id.trim_start_matches('/');
+    let device = query::debug_device(&state, user_id, device_id)
+        .ok_or_else(|| ServerError::Validation("No Homie controller for user".to_string()))?;
+    let status = if device.found {
+        StatusCode::OK
+    } else {
+        StatusCode::NOT_FOUND
+    };
+    Ok((status, Json(device)))
+}
+
+/// Like [`JsonBody`], but first logs the raw request body at trace level if
+/// `fulfillment.log-request-body-limit` is configured, to help diagnose mismatches between what
+/// Google sends and what we expect.
+pub struct LoggedJsonBody<T>(pub T);
+
+#[async_trait]
+impl<T> FromRequest<Body> for LoggedJsonBody<T>
+where
+    T: DeserializeOwned,
+{
+    type Rejection = ServerError;
+
+    async fn from_request(
+        req: &mut axum::extract::RequestParts<Body>,
+    ) -> Result<Self, Self::Rejection> {
+        let limit = req
+            .extensions()
+            .and_then(|extensions| extensions.get::<State>())
+            .and_then(|state| state.config.fulfillment.log_request_body_limit);
+        if let (Some(limit), Some(body)) = (limit, req.body_mut()) {
+            // Buffer the whole body so it can be logged, then put it back for the JSON extractor.
+            let bytes = hyper::body::to_bytes(mem::take(body))
+                .await
+                .map_err(|err| ServerError::Validation(err.to_string()))?;
+            tracing::trace!("Request body: {}", body_for_log(&bytes, limit));
+            *body = Body::from(bytes);
+        }
+        let JsonBody(value) = JsonBody::from_request(req).await?;
+        Ok(Self(value))
+    }
+}
+
+/// Returns the given request body as a string for logging, truncated to at most `limit` bytes.
+fn body_for_log(body: &[u8], limit: usize) -> String {
+    if body.len() > limit {
+        format!("{}...", String::from_utf8_lossy(&body[..limit]))
+    } else {
+        String::from_utf8_lossy(body).into_owned()
+    }
+}
+
+/// Returns the given request ID truncated to a reasonable length, so that a very long ID doesn't
+/// bloat the logs.
+fn request_id_for_log(request_id: &str) -> Cow<'_, str> {
+    match request_id.char_indices().nth(MAX_LOGGED_REQUEST_ID_LENGTH) {
+        Some((end, _)) => format!("{}...", &request_id[..end]).into(),
+        None => request_id.into(),
+    }
+}
+
+async fn handle_request(
+    state: State,
+    user_id: user::ID,
+    request: Request,
 ) -> Result<Json<Response>, ServerError> {
-    let input = request.inputs.first().unwrap();
+    if let Some(rate_limiter) = &state.fulfillment_rate_limiter {
+        if !rate_limiter.try_acquire(user_id) {
+            tracing::warn!("Rate limit exceeded for user {}", user_id);
+            return Err(ServerError::TooManyRequests);
+        }
+    }
+    if state.in_startup_grace() {
+        tracing::warn!("Rejecting fulfillment request as no devices have been discovered yet.");
+        return Err(ServerError::ServiceUnavailable);
+    }
+
+    // Google normally only sends a single input, but the protocol allows several. They can only
+    // be combined into a single response if they are all for the same intent.
+    let first_input = request
+        .inputs
+        .first()
+        .ok_or_else(|| ServerError::Validation("Request has no inputs".to_string()))?;
+    if request
+        .inputs
+        .iter()
+        .any(|input| discriminant(input) != discriminant(first_input))
+    {
+        return Err(ServerError::Validation(
+            "Request has inputs for different intents".to_string(),
+        ));
+    }
 
-    let body: Response = match input {
+    let body: Response = match first_input {
         RequestInput::Sync => Response::Sync(google_smart_home::sync::response::Response {
             request_id: request.request_id,
             payload: sync::handle(state, user_id).await?,
         }),
-        RequestInput::Query(payload) => {
+        RequestInput::Query(_) => {
+            let mut combined: Option<google_smart_home::query::response::Payload> = None;
+            for input in &request.inputs {
+                if let RequestInput::Query(payload) = input {
+                    let response = query::handle(state.clone(), user_id, payload).await?;
+                    if let Some(combined) = &mut combined {
+                        combined.error_code = combined.error_code.take().or(response.error_code);
+                        combined.debug_string =
+                            combined.debug_string.take().or(response.debug_string);
+                        combined.devices.extend(response.devices);
+                    } else {
+                        combined = Some(response);
+                    }
+                }
+            }
             Response::Query(google_smart_home::query::response::Response {
                 request_id: request.request_id,
-                payload: query::handle(state, user_id, payload).await?,
+                payload: combined.unwrap(),
             })
         }
-        RequestInput::Execute(payload) => {
+        RequestInput::Execute(_) => {
+            let mut combined: Option<google_smart_home::execute::response::Payload> = None;
+            for input in &request.inputs {
+                if let RequestInput::Execute(payload) = input {
+                    let response = execute::handle(state.clone(), user_id, payload).await?;
+                    if let Some(combined) = &mut combined {
+                        combined.error_code = combined.error_code.take().or(response.error_code);
+                        combined.debug_string =
+                            combined.debug_string.take().or(response.debug_string);
+                        combined.commands.extend(response.commands);
+                    } else {
+                        combined = Some(response);
+                    }
+                }
+            }
             Response::Execute(google_smart_home::execute::response::Response {
                 request_id: request.request_id,
-                payload: execute::handle(state, user_id, payload).await?,
+                payload: combined.unwrap(),
             })
         }
-        RequestInput::Disconnect => todo!(),
+        RequestInput::Disconnect => {
+            tracing::info!("User {} unlinked their account", user_id);
+            Response::Disconnect(DisconnectResponse::default())
+        }
+        RequestInput::Unsupported(intent) => unsupported_intent(request.request_id, intent),
     };
 
     Ok(Json(body))
 }
+
+/// Returns an error response for an intent which homieflow doesn't handle. This is still a
+/// successful HTTP response, so that Google gets a well-formed error rather than a server error.
+fn unsupported_intent(request_id: String, intent: &str) -> Response {
+    tracing::warn!("Unsupported intent {}", intent);
+    Response::Error(ErrorResponse {
+        request_id,
+        payload: ErrorPayload {
+            error_code: "notSupported".to_string(),
+            debug_string: Some(format!("Unsupported intent {}", intent)),
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::server::Config;
+    use axum::response::IntoResponse;
+    use homie_controller::HomieController;
+    use http::StatusCode;
+    use rumqttc::{MqttOptions, QoS};
+    use std::collections::HashMap;
+    use std::io::{self, Write};
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use tracing::instrument::WithSubscriber;
+
+    fn test_config() -> Config {
+        toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn multiple_query_inputs() {
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let state = State::new(
+            test_config(),
+            [(user_id, Arc::new(controller))].into_iter().collect(),
+        );
+        let request: Request = serde_json::from_str(
+            r#"{
+                "requestId": "request",
+                "inputs": [
+                    {"intent": "action.devices.QUERY", "payload": {"devices": [{"id": "a/b"}]}},
+                    {"intent": "action.devices.QUERY", "payload": {"devices": [{"id": "c/d"}]}}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let Json(response) = handle_request(state, user_id, request).await.unwrap();
+        if let Response::Query(response) = response {
+            assert_eq!(response.request_id, "request");
+            let mut ids: Vec<_> = response.payload.devices.keys().collect();
+            ids.sort();
+            assert_eq!(ids, vec!["a/b", "c/d"]);
+        } else {
+            panic!("Unexpected response {:?}", response);
+        }
+    }
+
+    #[tokio::test]
+    async fn request_id_in_span() {
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let state = State::new(test_config(), HashMap::new());
+        let request: Request =
+            serde_json::from_str(r#"{"requestId": "request-1234", "inputs": []}"#).unwrap();
+
+        // The request has no inputs, so an error is logged within the span.
+        handle(
+            Extension(state),
+            ClientIp(Some("192.0.2.1".parse().unwrap())),
+            UserID(user_id),
+            LoggedJsonBody(request),
+        )
+        .with_subscriber(subscriber)
+        .await
+        .unwrap_err();
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            logs.contains("request_id=request-1234"),
+            "Unexpected logs: {}",
+            logs
+        );
+        assert!(
+            logs.contains("client_ip=192.0.2.1"),
+            "Unexpected logs: {}",
+            logs
+        );
+    }
+
+    #[test]
+    fn long_request_id_truncated() {
+        assert_eq!(request_id_for_log("request"), "request");
+        let long_id = "x".repeat(10_000);
+        assert_eq!(
+            request_id_for_log(&long_id),
+            format!("{}...", &long_id[..MAX_LOGGED_REQUEST_ID_LENGTH])
+        );
+        // Multi-byte characters aren't split.
+        let long_id = "é".repeat(100);
+        assert_eq!(
+            request_id_for_log(&long_id),
+            format!("{}...", "é".repeat(MAX_LOGGED_REQUEST_ID_LENGTH))
+        );
+    }
+
+    #[tokio::test]
+    async fn logged_body_still_parsed() {
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_ansi(false)
+            .with_max_level(tracing::Level::TRACE)
+            .with_writer(move || writer.clone())
+            .finish();
+        let mut config = test_config();
+        config.fulfillment.log_request_body_limit = Some(30);
+        let state = State::new(config, HashMap::new());
+        let body =
+            r#"{"requestId": "request-1234", "inputs": [{"intent": "action.devices.SYNC"}]}"#;
+        let request = http::Request::builder()
+            .method("POST")
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .extension(state)
+            .body(Body::from(body))
+            .unwrap();
+        let mut parts = axum::extract::RequestParts::new(request);
+
+        let LoggedJsonBody(request) = LoggedJsonBody::<Request>::from_request(&mut parts)
+            .with_subscriber(subscriber)
+            .await
+            .unwrap();
+        assert_eq!(request.request_id, "request-1234");
+        assert_eq!(request.inputs.len(), 1);
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            logs.contains(r#"Request body: {"requestId": "request-1234", ..."#),
+            "Unexpected logs: {}",
+            logs
+        );
+    }
+
+    #[test]
+    fn body_truncated_for_log() {
+        assert_eq!(body_for_log(b"{}", 10), "{}");
+        assert_eq!(body_for_log(b"0123456789abc", 10), "0123456789...");
+    }
+
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn mixed_inputs() {
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let state = State::new(test_config(), HashMap::new());
+        let request: Request = serde_json::from_str(
+            r#"{
+                "requestId": "request",
+                "inputs": [
+                    {"intent": "action.devices.SYNC"},
+                    {"intent": "action.devices.QUERY", "payload": {"devices": [{"id": "c/d"}]}}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            handle_request(state, user_id, request).await.unwrap_err(),
+            ServerError::Validation("Request has inputs for different intents".to_string())
+        );
+    }
+
+    #[tokio::test]
+    async fn disconnect() {
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let state = State::new(test_config(), HashMap::new());
+        let request: Request = serde_json::from_str(
+            r#"{
+                "requestId": "request",
+                "inputs": [{"intent": "action.devices.DISCONNECT"}]
+            }"#,
+        )
+        .unwrap();
+
+        let response = handle_request(state, user_id, request).await.unwrap();
+        let Json(body) = &response;
+        assert_eq!(serde_json::to_value(body).unwrap(), serde_json::json!({}));
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn unsupported_intent() {
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let state = State::new(test_config(), HashMap::new());
+        let request: Request = serde_json::from_str(
+            r#"{
+                "requestId": "request",
+                "inputs": [{"intent": "action.devices.UNKNOWN", "payload": {"foo": 42}}]
+            }"#,
+        )
+        .unwrap();
+
+        let response = handle_request(state, user_id, request).await.unwrap();
+        let Json(body) = &response;
+        assert_eq!(
+            serde_json::to_value(body).unwrap(),
+            serde_json::json!({
+                "requestId": "request",
+                "payload": {
+                    "errorCode": "notSupported",
+                    "debugString": "Unsupported intent action.devices.UNKNOWN",
+                },
+            })
+        );
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn rate_limit_exceeded() {
+        let config: Config = toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [fulfillment]
+            rate-limit = { requests-per-minute = 1, burst = 2 }
+            "#,
+        )
+        .unwrap();
+        let state = State::new(config, HashMap::new());
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let other_user_id = user::ID::from_str("bd7feab5033940e296ed7fcdc700ba65").unwrap();
+        let request: Request = serde_json::from_str(
+            r#"{"requestId": "request", "inputs": [{"intent": "action.devices.SYNC"}]}"#,
+        )
+        .unwrap();
+
+        assert!(handle_request(state.clone(), user_id, request.clone())
+            .await
+            .is_ok());
+        assert!(handle_request(state.clone(), user_id, request.clone())
+            .await
+            .is_ok());
+        let error = handle_request(state.clone(), user_id, request.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(error, ServerError::TooManyRequests);
+        assert_eq!(
+            error.into_response().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+
+        // Other users are limited separately.
+        assert!(handle_request(state, other_user_id, request).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn startup_grace() {
+        let config: Config = toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [fulfillment]
+            startup-grace-seconds = 60
+            "#,
+        )
+        .unwrap();
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        let mut state = State::new(
+            config,
+            [(user_id, Arc::new(controller))].into_iter().collect(),
+        );
+        let request: Request = serde_json::from_str(
+            r#"{"requestId": "request", "inputs": [{"intent": "action.devices.SYNC"}]}"#,
+        )
+        .unwrap();
+
+        let error = handle_request(state.clone(), user_id, request.clone())
+            .await
+            .unwrap_err();
+        assert_eq!(error, ServerError::ServiceUnavailable);
+        assert_eq!(
+            error.into_response().status(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+
+        // Once the grace period has passed, requests are handled even without any devices.
+        state.started_at = Instant::now() - Duration::from_secs(61);
+        let response = handle_request(state, user_id, request).await.unwrap();
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn startup_grace_without_controllers() {
+        let config: Config = toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [fulfillment]
+            startup-grace-seconds = 60
+            "#,
+        )
+        .unwrap();
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let state = State::new(config, HashMap::new());
+        let request: Request = serde_json::from_str(
+            r#"{"requestId": "request", "inputs": [{"intent": "action.devices.SYNC"}]}"#,
+        )
+        .unwrap();
+
+        // There are no controllers to discover devices, so there is no point waiting for them.
+        assert!(!state.in_startup_grace());
+        let response = handle_request(state, user_id, request).await.unwrap();
+        assert_eq!(response.into_response().status(), StatusCode::OK);
+    }
+}
@@ -11,14 +11,18 @@
 // GNU General Public License for more details.
 
 use super::homie::get_homie_device_by_id;
-use crate::homie::state::homie_node_to_state;
+use crate::config::server::Devices;
+use crate::homie::device_times::DeviceTimes;
+use crate::homie::state::{homie_node_to_state, is_online_with_grace, is_stale};
 use crate::types::errors::InternalError;
 use crate::types::user;
 use crate::State;
 use google_smart_home::query::request;
 use google_smart_home::query::response;
-use homie_controller::Device;
+use homie_controller::{Device, State as DeviceState};
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::time::Duration;
 
 #[tracing::instrument(name = "Query", skip(state), err)]
 pub async fn handle(
@@ -26,8 +30,37 @@ pub async fn handle(
     user_id: user::ID,
     payload: &request::Payload,
 ) -> Result<response::Payload, InternalError> {
-    if let Some(homie_controller) = state.homie_controllers.get(&user_id) {
-        let devices = get_homie_devices(&homie_controller.devices(), &payload.devices);
+    if let Some(homie_devices) = state.devices(&user_id) {
+        let devices = get_homie_devices(
+            &state.config.devices,
+            state.config.get_device_id_prefix(&user_id),
+            state.last_seen.get(&user_id).map(AsRef::as_ref),
+            state.first_seen.get(&user_id).map(AsRef::as_ref),
+            state.offline_since.get(&user_id).map(AsRef::as_ref),
+            &homie_devices,
+            &payload.devices,
+        );
+        if let Some(home_graph_client) = state.home_graph_clients.get(&user_id) {
+            let reports = states_to_report(state.config.fulfillment.report_on_query, &devices);
+            if !reports.is_empty() {
+                let home_graph_client = home_graph_client.clone();
+                let agent_user_id = state.config.get_agent_user_id(&user_id);
+                tokio::spawn(async move {
+                    for (device_id, device_state) in reports {
+                        if let Err(e) = home_graph_client
+                            .report_state(&agent_user_id, device_id.clone(), device_state)
+                            .await
+                        {
+                            tracing::error!(
+                                "Error reporting state of {} after query: {:?}",
+                                device_id,
+                                e
+                            );
+                        }
+                    }
+                });
+            }
+        }
         Ok(response::Payload {
             error_code: None,
             debug_string: None,
@@ -42,33 +75,153 @@ pub async fn handle(
     }
 }
 
+/// The state of a single device as Google Home would see it, for debugging.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct DebugDevice {
+    /// Whether the device was found.
+    pub found: bool,
+    /// Whether the device would be reported to Google Home as online.
+    pub online: bool,
+    /// The state which would be returned for a QUERY.
+    pub state: response::State,
+}
+
+/// Returns the state of the device with the given Google Home ID as it would be returned for a
+/// QUERY, or `None` if the user doesn't have a Homie controller.
+pub fn debug_device(state: &State, user_id: user::ID, device_id: &str) -> Option<DebugDevice> {
+    let homie_devices = state.devices(&user_id)?;
+    Some(get_debug_device(
+        &state.config.devices,
+        state.config.get_device_id_prefix(&user_id),
+        state.last_seen.get(&user_id).map(AsRef::as_ref),
+        state.first_seen.get(&user_id).map(AsRef::as_ref),
+        state.offline_since.get(&user_id).map(AsRef::as_ref),
+        &homie_devices,
+        device_id,
+    ))
+}
+
+fn get_debug_device(
+    config: &Devices,
+    device_id_prefix: Option<&str>,
+    last_seen: Option<&DeviceTimes>,
+    first_seen: Option<&DeviceTimes>,
+    offline_since: Option<&DeviceTimes>,
+    devices: &HashMap<String, Device>,
+    device_id: &str,
+) -> DebugDevice {
+    // Don't record the device as seen by Google, as this is only for debugging.
+    let device = homie_device_state(
+        config,
+        device_id_prefix,
+        last_seen,
+        first_seen,
+        offline_since,
+        devices,
+        &request::PayloadDevice {
+            id: device_id.to_owned(),
+            custom_data: None,
+        },
+    );
+    DebugDevice {
+        found: device.error_code.as_deref() != Some("deviceNotFound"),
+        online: device.state.online,
+        state: device.state,
+    }
+}
+
 fn get_homie_devices(
+    config: &Devices,
+    device_id_prefix: Option<&str>,
+    last_seen: Option<&DeviceTimes>,
+    first_seen: Option<&DeviceTimes>,
+    offline_since: Option<&DeviceTimes>,
     devices: &HashMap<String, Device>,
     request_devices: &[request::PayloadDevice],
 ) -> HashMap<String, response::PayloadDevice> {
     request_devices
         .iter()
         .map(|device| {
-            let response = get_homie_device(devices, device);
+            let response = get_homie_device(
+                config,
+                device_id_prefix,
+                last_seen,
+                first_seen,
+                offline_since,
+                devices,
+                device,
+            );
             (device.id.to_owned(), response)
         })
         .collect()
 }
 
+/// Returns the QUERY response for the given device, after recording that Google has asked about it.
 fn get_homie_device(
+    config: &Devices,
+    device_id_prefix: Option<&str>,
+    last_seen: Option<&DeviceTimes>,
+    first_seen: Option<&DeviceTimes>,
+    offline_since: Option<&DeviceTimes>,
+    devices: &HashMap<String, Device>,
+    request_device: &request::PayloadDevice,
+) -> response::PayloadDevice {
+    if let (Some(first_seen), Some((device, _))) = (
+        first_seen,
+        get_homie_device_by_id(devices, device_id_prefix, &request_device.id),
+    ) {
+        first_seen.record_first(&device.id);
+    }
+    homie_device_state(
+        config,
+        device_id_prefix,
+        last_seen,
+        first_seen,
+        offline_since,
+        devices,
+        request_device,
+    )
+}
+
+/// Returns the QUERY response for the given device, without any side effects.
+fn homie_device_state(
+    config: &Devices,
+    device_id_prefix: Option<&str>,
+    last_seen: Option<&DeviceTimes>,
+    first_seen: Option<&DeviceTimes>,
+    offline_since: Option<&DeviceTimes>,
     devices: &HashMap<String, Device>,
     request_device: &request::PayloadDevice,
 ) -> response::PayloadDevice {
-    if let Some((device, node)) = get_homie_device_by_id(devices, &request_device.id) {
-        if device.state == homie_controller::State::Ready
-            || device.state == homie_controller::State::Sleeping
+    if let Some((device, node)) =
+        get_homie_device_by_id(devices, device_id_prefix, &request_device.id)
+    {
+        if is_online_with_grace(device, config, offline_since)
+            && !is_stale(device, config, last_seen)
         {
-            let state = homie_node_to_state(node, true);
+            let state = homie_node_to_state(device, node, true, config);
+            if state.is_jammed == Some(true) {
+                // Google expects the state to still be reported along with the error.
+                return response::PayloadDevice {
+                    status: response::PayloadDeviceStatus::Error,
+                    error_code: Some("deviceJammed".to_string()),
+                    state,
+                };
+            }
             response::PayloadDevice {
                 status: response::PayloadDeviceStatus::Success,
                 error_code: None,
                 state,
             }
+        } else if in_init_grace(device, config, first_seen) {
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    ..Default::default()
+                },
+            }
         } else {
             response::PayloadDevice {
                 status: response::PayloadDeviceStatus::Offline,
@@ -85,12 +238,42 @@ fn get_homie_device(
     }
 }
 
+/// Returns whether the device is still initialising but was first queried within the configured
+/// grace period, if any.
+fn in_init_grace(device: &Device, config: &Devices, first_seen: Option<&DeviceTimes>) -> bool {
+    if let (Some(grace), Some(first_seen)) = (config.init_grace_seconds, first_seen) {
+        device.state == DeviceState::Init
+            && first_seen.within(&device.id, Duration::from_secs(grace))
+    } else {
+        false
+    }
+}
+
+/// Returns the states of successfully queried devices to report to Home Graph, if reporting on
+/// query is enabled.
+fn states_to_report(
+    report_on_query: bool,
+    devices: &HashMap<String, response::PayloadDevice>,
+) -> Vec<(String, response::State)> {
+    if !report_on_query {
+        return vec![];
+    }
+    devices
+        .iter()
+        .filter(|(_, device)| device.status == response::PayloadDeviceStatus::Success)
+        .map(|(device_id, device)| (device_id.to_owned(), device.state.clone()))
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use google_smart_home::query::response::Color;
+    use crate::config::server::StringSensor;
+    use crate::homie::node_report_state;
+    use google_smart_home::query::response::{Color, CurrentSensorStateData};
     use homie_controller::{Datatype, Node, Property, State};
+    use std::time::Instant;
 
     #[test]
     fn light_with_brightness() {
@@ -149,7 +332,15 @@ mod tests {
         };
 
         assert_eq!(
-            get_homie_device(&devices, &request_device),
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                &devices,
+                &request_device
+            ),
             response::PayloadDevice {
                 status: response::PayloadDeviceStatus::Success,
                 error_code: None,
@@ -220,7 +411,15 @@ mod tests {
         };
 
         assert_eq!(
-            get_homie_device(&devices, &request_device),
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                &devices,
+                &request_device
+            ),
             response::PayloadDevice {
                 status: response::PayloadDeviceStatus::Success,
                 error_code: None,
@@ -234,6 +433,178 @@ mod tests {
         );
     }
 
+    #[test]
+    fn light_with_unreadable_color() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let color_property = Property {
+            id: "color".to_string(),
+            name: Some("Colour".to_string()),
+            datatype: Some(Datatype::Color),
+            settable: true,
+            retained: false,
+            unit: None,
+            format: Some("rgb".to_string()),
+            value: None,
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property, color_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+
+        let request_device = request::PayloadDevice {
+            id: "device/node".to_string(),
+            custom_data: None,
+        };
+
+        // The colour is omitted, but the rest of the state is still reported.
+        assert_eq!(
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                &devices,
+                &request_device
+            ),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    on: Some(true),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn string_sensor() {
+        let cycle_property = Property {
+            id: "cycle".to_string(),
+            name: Some("Cycle".to_string()),
+            datatype: Some(Datatype::String),
+            settable: false,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("spinning".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![cycle_property]),
+        };
+        let mut device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let config = Devices {
+            string_sensors: vec![StringSensor {
+                property: "cycle".to_string(),
+                name: "WashCycle".to_string(),
+                states: vec!["washing".to_string(), "spinning".to_string()],
+            }],
+            ..Default::default()
+        };
+        let request_device = request::PayloadDevice {
+            id: "device/node".to_string(),
+            custom_data: None,
+        };
+
+        let devices = device_set(vec![device.clone()]);
+        assert_eq!(
+            get_homie_device(&config, None, None, None, None, &devices, &request_device),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    current_sensor_state_data: Some(vec![CurrentSensorStateData {
+                        name: "WashCycle".to_string(),
+                        current_sensor_state: Some("spinning".to_string()),
+                        raw_value: None,
+                    }]),
+                    ..Default::default()
+                },
+            }
+        );
+
+        // A value which wasn't advertised isn't reported.
+        device
+            .nodes
+            .get_mut("node")
+            .unwrap()
+            .properties
+            .get_mut("cycle")
+            .unwrap()
+            .value = Some("exploding".to_string());
+        let devices = device_set(vec![device]);
+        assert_eq!(
+            get_homie_device(&config, None, None, None, None, &devices, &request_device),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
     #[test]
     fn temperature_sensor() {
         let temperature_property = Property {
@@ -291,7 +662,15 @@ mod tests {
         };
 
         assert_eq!(
-            get_homie_device(&devices, &request_device),
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                &devices,
+                &request_device
+            ),
             response::PayloadDevice {
                 status: response::PayloadDeviceStatus::Success,
                 error_code: None,
@@ -305,6 +684,1285 @@ mod tests {
         );
     }
 
+    #[test]
+    fn blinds_with_tilt() {
+        let position_property = Property {
+            id: "position".to_string(),
+            name: Some("Position".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("%".to_string()),
+            format: Some("0:100".to_string()),
+            value: Some("40".to_string()),
+        };
+        let tilt_property = Property {
+            id: "tilt".to_string(),
+            name: Some("Tilt".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("°".to_string()),
+            format: Some("-90:90".to_string()),
+            value: Some("30".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![position_property, tilt_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+
+        let request_device = request::PayloadDevice {
+            id: "device/node".to_string(),
+            custom_data: None,
+        };
+
+        assert_eq!(
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                &devices,
+                &request_device
+            ),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    open_percent: Some(40),
+                    rotation_degrees: Some(30.0),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn jammed_lock() {
+        let locked_property = Property {
+            id: "locked".to_string(),
+            name: Some("Locked".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("false".to_string()),
+        };
+        let jammed_property = Property {
+            id: "jammed".to_string(),
+            name: Some("Jammed".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: false,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![locked_property, jammed_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let mut devices = device_set(vec![device]);
+
+        let request_device = request::PayloadDevice {
+            id: "device/node".to_string(),
+            custom_data: None,
+        };
+
+        assert_eq!(
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                &devices,
+                &request_device
+            ),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Error,
+                error_code: Some("deviceJammed".to_string()),
+                state: response::State {
+                    online: true,
+                    is_locked: Some(false),
+                    is_jammed: Some(true),
+                    ..Default::default()
+                },
+            }
+        );
+
+        // Once it is no longer jammed, it is reported normally.
+        devices
+            .get_mut("device")
+            .unwrap()
+            .nodes
+            .get_mut("node")
+            .unwrap()
+            .properties
+            .get_mut("jammed")
+            .unwrap()
+            .value = Some("false".to_string());
+        assert_eq!(
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                &devices,
+                &request_device
+            ),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    is_locked: Some(false),
+                    is_jammed: Some(false),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn vacuum_docked() {
+        let dock_property = Property {
+            id: "dock".to_string(),
+            name: Some("Docked".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let node = Node {
+            id: "robot".to_string(),
+            name: Some("Robot".to_string()),
+            node_type: None,
+            properties: property_set(vec![dock_property]),
+        };
+        let device = Device {
+            id: "vacuum".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Vacuum".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+
+        assert_eq!(
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                &devices,
+                &request::PayloadDevice {
+                    id: "vacuum/robot".to_string(),
+                    custom_data: None,
+                }
+            ),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    is_docked: Some(true),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn washer_with_modes() {
+        let mode_property = Property {
+            id: "mode".to_string(),
+            name: Some("Wash mode".to_string()),
+            datatype: Some(Datatype::Enum),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("delicate,normal,heavy".to_string()),
+            value: Some("normal".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![mode_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+
+        let request_device = request::PayloadDevice {
+            id: "device/node".to_string(),
+            custom_data: None,
+        };
+
+        assert_eq!(
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                &devices,
+                &request_device
+            ),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    current_mode_settings: Some(
+                        [("mode".to_string(), "normal".to_string())]
+                            .into_iter()
+                            .collect()
+                    ),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn switch_with_enum() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Enum),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("on,off".to_string()),
+            value: Some("on".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+
+        let request_device = request::PayloadDevice {
+            id: "device/node".to_string(),
+            custom_data: None,
+        };
+
+        assert_eq!(
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                &devices,
+                &request_device
+            ),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    on: Some(true),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn switch_with_status_property() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let state_property = Property {
+            id: "state".to_string(),
+            name: Some("State".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: false,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("false".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property, state_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+
+        let request_device = request::PayloadDevice {
+            id: "device/node".to_string(),
+            custom_data: None,
+        };
+
+        // Without the config option the command property is used.
+        assert_eq!(
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                &devices,
+                &request_device
+            )
+            .state
+            .on,
+            Some(true)
+        );
+
+        let config = Devices {
+            on_status_property: Some("state".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(
+            get_homie_device(&config, None, None, None, None, &devices, &request_device),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    on: Some(false),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn init_grace() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: None,
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property]),
+        };
+        let make_device = |id: &str| Device {
+            id: id.to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Init,
+            implementation: None,
+            nodes: node_set(vec![node.clone()]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![make_device("new"), make_device("old")]);
+        let request_device = |id: &str| request::PayloadDevice {
+            id: format!("{}/node", id),
+            custom_data: None,
+        };
+        let config = Devices {
+            init_grace_seconds: Some(60),
+            ..Default::default()
+        };
+        let first_seen = DeviceTimes::default();
+        first_seen.record_first_at("old", Instant::now() - Duration::from_secs(120));
+
+        // A device first seen within the grace period is reported as online with default state.
+        assert_eq!(
+            get_homie_device(
+                &config,
+                None,
+                None,
+                Some(&first_seen),
+                None,
+                &devices,
+                &request_device("new")
+            ),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    ..Default::default()
+                },
+            }
+        );
+
+        // A device first seen before the grace period is reported as offline.
+        let offline = response::PayloadDevice {
+            status: response::PayloadDeviceStatus::Offline,
+            error_code: Some("offline".to_string()),
+            state: Default::default(),
+        };
+        assert_eq!(
+            get_homie_device(
+                &config,
+                None,
+                None,
+                Some(&first_seen),
+                None,
+                &devices,
+                &request_device("old")
+            ),
+            offline
+        );
+
+        // Without a grace period configured it is offline.
+        assert_eq!(
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                Some(&first_seen),
+                None,
+                &devices,
+                &request_device("new")
+            ),
+            offline
+        );
+    }
+
+    #[test]
+    fn offline_grace() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property]),
+        };
+        let make_device = |id: &str| Device {
+            id: id.to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Lost,
+            implementation: None,
+            nodes: node_set(vec![node.clone()]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![make_device("recent"), make_device("old")]);
+        let request_device = |id: &str| request::PayloadDevice {
+            id: format!("{}/node", id),
+            custom_data: None,
+        };
+        let config = Devices {
+            offline_grace_seconds: Some(60),
+            ..Default::default()
+        };
+        let offline_since = DeviceTimes::default();
+        offline_since.record_first_at("recent", Instant::now() - Duration::from_secs(10));
+        offline_since.record_first_at("old", Instant::now() - Duration::from_secs(120));
+
+        // A device which went offline within the grace period is still reported as online.
+        assert_eq!(
+            get_homie_device(
+                &config,
+                None,
+                None,
+                None,
+                Some(&offline_since),
+                &devices,
+                &request_device("recent")
+            ),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    on: Some(true),
+                    ..Default::default()
+                },
+            }
+        );
+
+        // Once the grace period has passed it is offline.
+        let offline = response::PayloadDevice {
+            status: response::PayloadDeviceStatus::Offline,
+            error_code: Some("offline".to_string()),
+            state: Default::default(),
+        };
+        assert_eq!(
+            get_homie_device(
+                &config,
+                None,
+                None,
+                None,
+                Some(&offline_since),
+                &devices,
+                &request_device("old")
+            ),
+            offline
+        );
+
+        // Without a grace period configured it is offline straight away.
+        assert_eq!(
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                Some(&offline_since),
+                &devices,
+                &request_device("recent")
+            ),
+            offline
+        );
+    }
+
+    #[test]
+    fn stale_device_offline() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+
+        let request_device = request::PayloadDevice {
+            id: "device/node".to_string(),
+            custom_data: None,
+        };
+        let config = Devices {
+            online_timeout_seconds: Some(60),
+            ..Default::default()
+        };
+        let last_seen = DeviceTimes::default();
+
+        last_seen.record("device");
+        assert_eq!(
+            get_homie_device(
+                &config,
+                None,
+                Some(&last_seen),
+                None,
+                None,
+                &devices,
+                &request_device
+            )
+            .status,
+            response::PayloadDeviceStatus::Success
+        );
+
+        last_seen.record_at("device", Instant::now() - Duration::from_secs(120));
+        assert_eq!(
+            get_homie_device(
+                &config,
+                None,
+                Some(&last_seen),
+                None,
+                None,
+                &devices,
+                &request_device
+            ),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Offline,
+                error_code: Some("offline".to_string()),
+                state: Default::default(),
+            }
+        );
+
+        // Without a timeout configured it is still online.
+        assert_eq!(
+            get_homie_device(
+                &Devices::default(),
+                None,
+                Some(&last_seen),
+                None,
+                None,
+                &devices,
+                &request_device
+            )
+            .status,
+            response::PayloadDeviceStatus::Success
+        );
+    }
+
+    #[test]
+    fn online_for_device_states() {
+        let alert_online = Devices {
+            alert_online: true,
+            ..Default::default()
+        };
+        for (state, online, online_with_alert_online) in [
+            (State::Init, false, false),
+            (State::Ready, true, true),
+            (State::Disconnected, false, false),
+            (State::Sleeping, true, true),
+            (State::Lost, false, false),
+            (State::Alert, false, true),
+        ] {
+            let on_property = Property {
+                id: "on".to_string(),
+                name: Some("On".to_string()),
+                datatype: Some(Datatype::Boolean),
+                settable: true,
+                retained: true,
+                unit: None,
+                format: None,
+                value: Some("true".to_string()),
+            };
+            let node = Node {
+                id: "node".to_string(),
+                name: Some("Node name".to_string()),
+                node_type: None,
+                properties: property_set(vec![on_property]),
+            };
+            let device = Device {
+                id: "device".to_string(),
+                homie_version: "4.0".to_string(),
+                name: Some("Device name".to_string()),
+                state,
+                implementation: None,
+                nodes: node_set(vec![node]),
+                extensions: vec![],
+                local_ip: None,
+                mac: None,
+                firmware_name: None,
+                firmware_version: None,
+                stats_interval: None,
+                stats_uptime: None,
+                stats_signal: None,
+                stats_cputemp: None,
+                stats_cpuload: None,
+                stats_battery: None,
+                stats_freeheap: None,
+                stats_supply: None,
+            };
+            let devices = device_set(vec![device]);
+
+            let request_device = request::PayloadDevice {
+                id: "device/node".to_string(),
+                custom_data: None,
+            };
+
+            for (config, expected_online) in [
+                (Devices::default(), online),
+                (alert_online.clone(), online_with_alert_online),
+            ] {
+                let response =
+                    get_homie_device(&config, None, None, None, None, &devices, &request_device);
+                let expected_status = if expected_online {
+                    response::PayloadDeviceStatus::Success
+                } else {
+                    response::PayloadDeviceStatus::Offline
+                };
+                assert_eq!(
+                    response.status, expected_status,
+                    "Device in state {:?} with {:?}",
+                    state, config
+                );
+                assert_eq!(response.state.online, expected_online);
+            }
+        }
+    }
+
+    #[test]
+    fn report_on_query() {
+        let devices: HashMap<String, response::PayloadDevice> = [
+            (
+                "device/light".to_string(),
+                response::PayloadDevice {
+                    status: response::PayloadDeviceStatus::Success,
+                    error_code: None,
+                    state: response::State {
+                        online: true,
+                        on: Some(true),
+                        ..Default::default()
+                    },
+                },
+            ),
+            (
+                "device/offline".to_string(),
+                response::PayloadDevice {
+                    status: response::PayloadDeviceStatus::Offline,
+                    error_code: Some("offline".to_string()),
+                    state: Default::default(),
+                },
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        assert_eq!(states_to_report(false, &devices), vec![]);
+        assert_eq!(
+            states_to_report(true, &devices),
+            vec![(
+                "device/light".to_string(),
+                response::State {
+                    online: true,
+                    on: Some(true),
+                    ..Default::default()
+                }
+            )]
+        );
+    }
+
+    #[test]
+    fn debug_device() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let mut devices = device_set(vec![device]);
+        let debug_device = |devices: &HashMap<String, Device>, device_id: &str| {
+            get_debug_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                devices,
+                device_id,
+            )
+        };
+
+        assert_eq!(
+            debug_device(&devices, "device/node"),
+            DebugDevice {
+                found: true,
+                online: true,
+                state: response::State {
+                    online: true,
+                    on: Some(true),
+                    ..Default::default()
+                },
+            }
+        );
+        assert_eq!(
+            debug_device(&devices, "device/other"),
+            DebugDevice {
+                found: false,
+                online: false,
+                state: Default::default(),
+            }
+        );
+
+        devices.get_mut("device").unwrap().state = State::Lost;
+        assert_eq!(
+            debug_device(&devices, "device/node"),
+            DebugDevice {
+                found: true,
+                online: false,
+                state: Default::default(),
+            }
+        );
+
+        // Debugging a device doesn't start its init grace period.
+        let first_seen = DeviceTimes::default();
+        get_debug_device(
+            &Devices::default(),
+            None,
+            None,
+            Some(&first_seen),
+            None,
+            &devices,
+            "device/node",
+        );
+        assert_eq!(first_seen.elapsed("device"), None);
+    }
+
+    fn property(id: &str, datatype: Datatype, format: Option<&str>, value: &str) -> Property {
+        Property {
+            id: id.to_string(),
+            name: Some(id.to_string()),
+            datatype: Some(datatype),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: format.map(ToOwned::to_owned),
+            value: Some(value.to_string()),
+        }
+    }
+
+    /// Asserts that the state reported to Home Graph for a ready device with the given properties
+    /// is the same as the QUERY response for it, so that the two can't drift apart.
+    fn assert_report_matches_query(properties: Vec<Property>) {
+        let config = Devices::default();
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(properties),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node.clone()]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let report_state = node_report_state(&device, &node, &config, None, None);
+        let devices = device_set(vec![device]);
+        let request_device = request::PayloadDevice {
+            id: "device/node".to_string(),
+            custom_data: None,
+        };
+
+        let query_device =
+            get_homie_device(&config, None, None, None, None, &devices, &request_device);
+        assert_eq!(query_device.status, response::PayloadDeviceStatus::Success);
+        assert_ne!(query_device.state, response::State::default());
+        assert_eq!(report_state, query_device.state);
+    }
+
+    #[test]
+    fn switch_with_integer_on() {
+        let config = Devices::default();
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Relay".to_string()),
+            node_type: None,
+            properties: property_set(vec![property("on", Datatype::Integer, Some("0:1"), "1")]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let request_device = request::PayloadDevice {
+            id: "device/node".to_string(),
+            custom_data: None,
+        };
+
+        assert_eq!(
+            get_homie_device(&config, None, None, None, None, &devices, &request_device),
+            response::PayloadDevice {
+                status: response::PayloadDeviceStatus::Success,
+                error_code: None,
+                state: response::State {
+                    online: true,
+                    on: Some(true),
+                    ..Default::default()
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn synthesized_thermostat_mode() {
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Thermostat".to_string()),
+            node_type: None,
+            properties: property_set(vec![
+                property("temperature", Datatype::Float, None, "19.5"),
+                property("heating", Datatype::Boolean, None, "true"),
+            ]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let devices = device_set(vec![device]);
+        let request_device = request::PayloadDevice {
+            id: "device/node".to_string(),
+            custom_data: None,
+        };
+
+        let config = Devices {
+            synthesize_thermostat_mode: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            get_homie_device(&config, None, None, None, None, &devices, &request_device).state,
+            response::State {
+                online: true,
+                thermostat_temperature_ambient: Some(19.5),
+                thermostat_mode: Some("heat".to_string()),
+                ..Default::default()
+            }
+        );
+
+        // Without the flag no mode is reported.
+        assert_eq!(
+            get_homie_device(
+                &Devices::default(),
+                None,
+                None,
+                None,
+                None,
+                &devices,
+                &request_device
+            )
+            .state
+            .thermostat_mode,
+            None
+        );
+    }
+
+    #[test]
+    fn command_only_on_off() {
+        let config = Devices {
+            command_only_on_off: vec!["device/node".to_string()],
+            ..Default::default()
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Relay".to_string()),
+            node_type: None,
+            properties: property_set(vec![property("on", Datatype::Boolean, None, "true")]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node.clone()]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let expected_state = response::State {
+            online: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            node_report_state(&device, &node, &config, None, None),
+            expected_state
+        );
+        let devices = device_set(vec![device]);
+        let request_device = request::PayloadDevice {
+            id: "device/node".to_string(),
+            custom_data: None,
+        };
+
+        let query_device =
+            get_homie_device(&config, None, None, None, None, &devices, &request_device);
+        assert_eq!(query_device.status, response::PayloadDeviceStatus::Success);
+        assert_eq!(query_device.state, expected_state);
+    }
+
+    #[test]
+    fn report_matches_query_brightness() {
+        assert_report_matches_query(vec![
+            property("on", Datatype::Boolean, None, "true"),
+            property("brightness", Datatype::Integer, Some("0:100"), "42"),
+        ]);
+    }
+
+    #[test]
+    fn report_matches_query_color() {
+        assert_report_matches_query(vec![
+            property("on", Datatype::Boolean, None, "true"),
+            property("color", Datatype::Color, Some("rgb"), "255,0,128"),
+        ]);
+        assert_report_matches_query(vec![property(
+            "color-temperature",
+            Datatype::Integer,
+            Some("2000:6500"),
+            "2700",
+        )]);
+    }
+
+    #[test]
+    fn report_matches_query_thermostat() {
+        assert_report_matches_query(vec![
+            property("temperature", Datatype::Float, None, "21.3"),
+            property("humidity", Datatype::Integer, Some("0:100"), "40"),
+            property("target-humidity", Datatype::Integer, Some("0:100"), "45"),
+        ]);
+    }
+
     fn property_set(properties: Vec<Property>) -> HashMap<String, Property> {
         properties
             .into_iter()
@@ -11,7 +11,22 @@
 // GNU General Public License for more details.
 
 use std::collections::HashMap;
+use std::time::Duration;
 
+use crate::config::server::{Devices, PropertyRole, TemperatureTrait};
+use crate::homie::google_device_id;
+use crate::homie::state::climate_sensors;
+use crate::homie::state::diagnostic_sensors;
+use crate::homie::state::energy_property;
+use crate::homie::state::is_mode_property;
+use crate::homie::state::is_percent_property;
+use crate::homie::state::is_temperature_control;
+use crate::homie::state::number_to_percent;
+use crate::homie::state::property_range;
+use crate::homie::state::role_property;
+use crate::homie::state::string_sensor_properties;
+use crate::homie::state::synthesized_thermostat_modes;
+use crate::homie::state::target_humidity_property;
 use crate::types::errors::ServerError;
 use crate::types::user;
 use crate::State;
@@ -20,17 +35,39 @@ use google_smart_home::device::Type as GHomeDeviceType;
 use google_smart_home::sync::response;
 use google_smart_home::sync::response::Attributes;
 use google_smart_home::sync::response::ColorModel;
+use google_smart_home::sync::response::ColorTemperatureRange;
+use google_smart_home::sync::response::DescriptiveCapabilities;
+use google_smart_home::sync::response::HumiditySetpointRange;
+use google_smart_home::sync::response::Mode;
+use google_smart_home::sync::response::ModeName;
+use google_smart_home::sync::response::ModeSetting;
+use google_smart_home::sync::response::ModeSettingValue;
+use google_smart_home::sync::response::NumericCapabilities;
 use google_smart_home::sync::response::PayloadDevice;
+use google_smart_home::sync::response::PayloadOtherDeviceID;
+use google_smart_home::sync::response::RotationDegreesRange;
+use google_smart_home::sync::response::SensorStateSupported;
+use google_smart_home::sync::response::TemperatureRange;
 use google_smart_home::sync::response::ThermostatTemperatureUnit;
 use homie_controller::ColorFormat;
+use homie_controller::Datatype;
 use homie_controller::Device;
 use homie_controller::Node;
+use homie_controller::Property;
 
 #[tracing::instrument(name = "Sync", skip(state), err)]
 pub async fn handle(state: State, user_id: user::ID) -> Result<response::Payload, ServerError> {
+    let agent_user_id = state.config.get_agent_user_id(&user_id);
     if let Some(homie_controller) = state.homie_controllers.get(&user_id) {
+        let all_devices = homie_controller.devices();
+        let cache_ttl = Duration::from_secs(state.config.fulfillment.sync_cache_seconds);
+        if let Some(payload) = state.sync_cache.get(&user_id, &all_devices, cache_ttl) {
+            tracing::debug!("Returning cached SYNC response");
+            return Ok(payload);
+        }
+
         // Return error if some nodes missing required attributes
-        let homie_devices = homie_controller.devices();
+        let homie_devices = state.device_filter.apply(all_devices.clone());
         if !homie_devices
             .values()
             .all(|device| device.has_required_attributes())
@@ -40,26 +77,34 @@ pub async fn handle(state: State, user_id: user::ID) -> Result<response::Payload
                 homie_devices.len()
             );
             return Ok(response::Payload {
-                agent_user_id: user_id.to_string(),
+                agent_user_id,
                 error_code: Some("offline".to_string()),
                 debug_string: Some("Devices missing required attributes.".to_string()),
                 devices: vec![],
             });
         }
 
-        let devices = homie_devices_to_google_home(&homie_devices);
+        let devices = homie_devices_to_google_home(
+            &state.config.devices,
+            state.config.get_device_id_prefix(&user_id),
+            &homie_devices,
+        );
 
         tracing::info!("Synced {} devices", devices.len());
 
-        Ok(response::Payload {
-            agent_user_id: user_id.to_string(),
+        let payload = response::Payload {
+            agent_user_id,
             error_code: None,
             debug_string: None,
             devices,
-        })
+        };
+        state
+            .sync_cache
+            .insert(user_id, all_devices, payload.clone());
+        Ok(payload)
     } else {
         Ok(response::Payload {
-            agent_user_id: user_id.to_string(),
+            agent_user_id,
             error_code: Some("authFailure".to_string()),
             debug_string: Some("No such user".to_string()),
             devices: vec![],
@@ -67,34 +112,75 @@ pub async fn handle(state: State, user_id: user::ID) -> Result<response::Payload
     }
 }
 
-fn homie_devices_to_google_home(devices: &HashMap<String, Device>) -> Vec<PayloadDevice> {
+/// Converts all supported nodes of the given Homie devices to Google Home devices.
+///
+/// Devices are included whatever their Homie state, so that they don't disappear from the Google
+/// Home app while they are temporarily offline. Their online status is instead reported by QUERY
+/// and report state, according to [`is_online`](crate::homie::state::is_online).
+fn homie_devices_to_google_home(
+    config: &Devices,
+    device_id_prefix: Option<&str>,
+    devices: &HashMap<String, Device>,
+) -> Vec<PayloadDevice> {
+    // Go through devices and nodes in order of ID so that the response is deterministic, and the
+    // same devices are kept if there are too many.
+    let mut devices: Vec<&Device> = devices.values().collect();
+    devices.sort_by(|a, b| a.id.cmp(&b.id));
     let mut google_home_devices = vec![];
-    for device in devices.values() {
-        for node in device.nodes.values() {
-            if let Some(google_home_device) = homie_node_to_google_home(device, node) {
+    for device in devices {
+        let mut nodes: Vec<&Node> = device.nodes.values().collect();
+        nodes.sort_by(|a, b| a.id.cmp(&b.id));
+        for node in nodes {
+            if let Some(google_home_device) =
+                homie_node_to_google_home(config, device_id_prefix, device, node)
+            {
                 google_home_devices.push(google_home_device);
             }
         }
     }
+    if let Some(max_devices) = config.max_devices {
+        if google_home_devices.len() > max_devices {
+            tracing::warn!(
+                "Found {} devices, but only returning the maximum of {}",
+                google_home_devices.len(),
+                max_devices
+            );
+            google_home_devices.truncate(max_devices);
+        }
+    }
     google_home_devices
 }
 
-fn homie_node_to_google_home(device: &Device, node: &Node) -> Option<PayloadDevice> {
-    let id = format!("{}/{}", device.id, node.id);
+fn homie_node_to_google_home(
+    config: &Devices,
+    device_id_prefix: Option<&str>,
+    device: &Device,
+    node: &Node,
+) -> Option<PayloadDevice> {
+    let id = google_device_id(device_id_prefix, &device.id, &node.id);
     let mut traits = vec![];
     let mut attributes = Attributes::default();
     let mut device_type = None;
-    if node.properties.contains_key("on") {
+    let enabled = |device_trait| !config.disabled_traits.contains(&device_trait);
+    let on = role_property(node, config, PropertyRole::On);
+    if on.is_some() && enabled(GHomeDeviceTrait::OnOff) {
         device_type = Some(GHomeDeviceType::Switch);
         traits.push(GHomeDeviceTrait::OnOff);
+        if config.is_command_only_on_off(&device.id, &node.id) {
+            attributes.command_only_on_off = Some(true);
+        }
     }
-    if node.properties.contains_key("brightness") {
-        if node.properties.contains_key("on") {
+    if role_property(node, config, PropertyRole::Brightness).is_some()
+        && enabled(GHomeDeviceTrait::Brightness)
+    {
+        if on.is_some() {
             device_type = Some(GHomeDeviceType::Light);
         }
         traits.push(GHomeDeviceTrait::Brightness);
     }
-    if let Some(color) = node.properties.get("color") {
+    if let Some(color) = role_property(node, config, PropertyRole::Color)
+        .filter(|_| enabled(GHomeDeviceTrait::ColorSetting))
+    {
         if let Ok(color_format) = color.color_format() {
             let color_model = match color_format {
                 ColorFormat::Rgb => ColorModel::Rgb,
@@ -103,27 +189,232 @@ fn homie_node_to_google_home(device: &Device, node: &Node) -> Option<PayloadDevi
             device_type = Some(GHomeDeviceType::Light);
             traits.push(GHomeDeviceTrait::ColorSetting);
             attributes.color_model = Some(color_model);
+            // A non-retained colour has no value to report, so tell Google not to expect one.
+            if !color.retained {
+                attributes.command_only_color_setting = Some(true);
+            }
+        }
+    }
+    if let Some(color_temperature) = role_property(node, config, PropertyRole::ColorTemperature)
+        .filter(|_| enabled(GHomeDeviceTrait::ColorSetting))
+    {
+        if let Some(range) = property_range(color_temperature) {
+            device_type = Some(GHomeDeviceType::Light);
+            if !traits.contains(&GHomeDeviceTrait::ColorSetting) {
+                traits.push(GHomeDeviceTrait::ColorSetting);
+            }
+            attributes.color_temperature_range = Some(ColorTemperatureRange {
+                temperature_min_k: *range.start() as u64,
+                temperature_max_k: *range.end() as u64,
+            });
+        }
+    }
+    if node
+        .properties
+        .get("locked")
+        .is_some_and(|locked| locked.datatype == Some(Datatype::Boolean))
+        && enabled(GHomeDeviceTrait::LockUnlock)
+    {
+        device_type = Some(GHomeDeviceType::Lock);
+        traits.push(GHomeDeviceTrait::LockUnlock);
+    }
+    if node
+        .properties
+        .get("dock")
+        .is_some_and(|dock| dock.datatype == Some(Datatype::Boolean))
+        && enabled(GHomeDeviceTrait::Dock)
+    {
+        device_type = Some(GHomeDeviceType::Vacuum);
+        traits.push(GHomeDeviceTrait::Dock);
+    }
+    if node.properties.contains_key("position") && enabled(GHomeDeviceTrait::OpenClose) {
+        device_type = Some(GHomeDeviceType::Blinds);
+        traits.push(GHomeDeviceTrait::OpenClose);
+    }
+    if let Some(tilt) = node.properties.get("tilt") {
+        if tilt.settable && enabled(GHomeDeviceTrait::Rotation) {
+            if is_percent_property(tilt) {
+                attributes.supports_percent = Some(true);
+                device_type = Some(GHomeDeviceType::Blinds);
+                traits.push(GHomeDeviceTrait::Rotation);
+            } else if let Some(range) = property_range(tilt) {
+                attributes.supports_degrees = Some(true);
+                attributes.rotation_degrees_range = Some(RotationDegreesRange {
+                    rotation_degrees_min: *range.start(),
+                    rotation_degrees_max: *range.end(),
+                });
+                device_type = Some(GHomeDeviceType::Blinds);
+                traits.push(GHomeDeviceTrait::Rotation);
+            } else {
+                tracing::warn!(
+                    "Not adding Rotation trait to {}/{} as its tilt property has no range",
+                    device.id,
+                    node.id
+                );
+            }
         }
     }
-    if node.properties.contains_key("temperature") {
+    if is_temperature_control(node, config) {
+        if enabled(GHomeDeviceTrait::TemperatureControl) {
+            let Some(range) =
+                role_property(node, config, PropertyRole::Temperature).and_then(property_range)
+            else {
+                return skip_node(device, node, "temperature property has no range");
+            };
+            device_type = Some(GHomeDeviceType::Refrigerator);
+            traits.push(GHomeDeviceTrait::TemperatureControl);
+            attributes.temperature_range = Some(TemperatureRange {
+                min_threshold_celsius: *range.start(),
+                max_threshold_celsius: *range.end(),
+            });
+            attributes.temperature_unit_for_ux = Some(ThermostatTemperatureUnit::C);
+        }
+    } else if role_property(node, config, PropertyRole::Temperature).is_some()
+        && config.temperature_trait != TemperatureTrait::SensorState
+        && enabled(GHomeDeviceTrait::TemperatureSetting)
+    {
         device_type = Some(GHomeDeviceType::Thermostat);
         traits.push(GHomeDeviceTrait::TemperatureSetting);
-        attributes.available_thermostat_modes = Some(vec!["off".to_string()]);
+        let synthesized_modes = if config.synthesize_thermostat_mode {
+            synthesized_thermostat_modes(node)
+        } else {
+            vec![]
+        };
+        attributes.available_thermostat_modes = if synthesized_modes.is_empty() {
+            Some(vec!["off".to_string()])
+        } else {
+            Some(synthesized_modes)
+        };
         attributes.thermostat_temperature_unit = Some(ThermostatTemperatureUnit::C);
         attributes.query_only_temperature_setting = Some(true);
     }
+    if let Some(target_humidity) =
+        target_humidity_property(node).filter(|_| enabled(GHomeDeviceTrait::HumiditySetting))
+    {
+        // Along with a temperature this is an AC unit which stays a thermostat, otherwise it is a
+        // dedicated humidifier.
+        device_type.get_or_insert(GHomeDeviceType::Humidifier);
+        traits.push(GHomeDeviceTrait::HumiditySetting);
+        if let Some(range) = property_range(target_humidity) {
+            attributes.humidity_setpoint_range = Some(HumiditySetpointRange {
+                min_percent: number_to_percent(*range.start()),
+                max_percent: number_to_percent(*range.end()),
+            });
+        }
+        if !target_humidity.settable {
+            attributes.query_only_humidity_setting = Some(true);
+        }
+    }
+    let mut modes: Vec<Mode> = node
+        .properties
+        .values()
+        .filter(|property| is_mode_property(property))
+        .filter_map(property_to_mode)
+        .collect();
+    if !modes.is_empty() && enabled(GHomeDeviceTrait::Modes) {
+        modes.sort_by(|a, b| a.name.cmp(&b.name));
+        traits.push(GHomeDeviceTrait::Modes);
+        attributes.available_modes = Some(modes);
+    }
+    if energy_property(node, config).is_some() && enabled(GHomeDeviceTrait::EnergyStorage) {
+        device_type.get_or_insert(GHomeDeviceType::Sensor);
+        traits.push(GHomeDeviceTrait::EnergyStorage);
+        attributes.query_only_energy_storage = Some(true);
+    }
+    let mut sensors: Vec<SensorStateSupported> = string_sensor_properties(node, config)
+        .map(|(sensor, _)| SensorStateSupported {
+            name: sensor.name.clone(),
+            descriptive_capabilities: Some(DescriptiveCapabilities {
+                available_states: sensor.states.clone(),
+            }),
+            numeric_capabilities: None,
+        })
+        .chain(
+            climate_sensors(node, config).map(|sensor| SensorStateSupported {
+                name: sensor.name.to_string(),
+                descriptive_capabilities: None,
+                numeric_capabilities: Some(NumericCapabilities {
+                    raw_value_unit: sensor.unit.to_string(),
+                }),
+            }),
+        )
+        .collect();
+    // Diagnostic sensors are only added to nodes which are exposed anyway.
+    if !traits.is_empty() || !sensors.is_empty() {
+        sensors.extend(
+            diagnostic_sensors(device, config).map(|sensor| SensorStateSupported {
+                name: sensor.name.to_string(),
+                descriptive_capabilities: None,
+                numeric_capabilities: Some(NumericCapabilities {
+                    raw_value_unit: sensor.unit.to_string(),
+                }),
+            }),
+        );
+    }
+    if !sensors.is_empty() && enabled(GHomeDeviceTrait::SensorState) {
+        device_type.get_or_insert(GHomeDeviceType::Sensor);
+        traits.push(GHomeDeviceTrait::SensorState);
+        attributes.sensor_states_supported = Some(sensors);
+    }
+    if is_scene(node) && enabled(GHomeDeviceTrait::Scene) {
+        device_type = Some(GHomeDeviceType::Scene);
+        traits.push(GHomeDeviceTrait::Scene);
+        attributes.scene_reversible = Some(false);
+    }
+
+    // Rebooting is only added to nodes which are exposed anyway, as it doesn't have a device type.
+    if !traits.is_empty()
+        && node
+            .properties
+            .get("reboot")
+            .is_some_and(|reboot| reboot.settable && reboot.datatype == Some(Datatype::Boolean))
+        && enabled(GHomeDeviceTrait::Reboot)
+    {
+        traits.push(GHomeDeviceTrait::Reboot);
+    }
+
+    if traits.is_empty() {
+        return skip_node(device, node, "no supported properties");
+    }
+    // An explicitly configured type for the Homie node type takes precedence over the inferred one.
+    if let Some(node_type) = node
+        .node_type
+        .as_ref()
+        .and_then(|node_type| config.node_types.get(node_type))
+    {
+        device_type = Some(node_type.clone());
+    }
+    let Some(device_type) = device_type else {
+        return skip_node(
+            device,
+            node,
+            &format!("no device type for traits {:?}", traits),
+        );
+    };
 
-    let device_name = device.name.clone().unwrap_or_else(|| device.id.clone());
-    let node_name = node.name.clone().unwrap_or_else(|| node.id.clone());
-    let will_report_state = !traits.is_empty();
+    let device_names = split_name(
+        device.name.as_deref().unwrap_or(&device.id),
+        config.name_delimiter.as_deref(),
+    );
+    let node_names = split_name(
+        node.name.as_deref().unwrap_or(&node.id),
+        config.name_delimiter.as_deref(),
+    );
+    let default_names = config
+        .default_names
+        .iter()
+        .find(|default_names| default_names.matches(&device.id, &node.id, &device_type))
+        .map(|default_names| default_names.names.clone());
+    // Scenes are stateless, so there is nothing to report.
+    let will_report_state = !traits.is_empty() && !traits.contains(&GHomeDeviceTrait::Scene);
     Some(response::PayloadDevice {
         id,
-        device_type: device_type?,
+        device_type,
         traits,
         name: response::PayloadDeviceName {
-            default_names: None,
-            name: format!("{} {}", device_name, node_name),
-            nicknames: Some(vec![node_name]),
+            default_names,
+            name: format!("{} {}", device_names[0], node_names[0]),
+            nicknames: Some(node_names),
         },
         device_info: None,
         will_report_state,
@@ -131,15 +422,92 @@ fn homie_node_to_google_home(device: &Device, node: &Node) -> Option<PayloadDevi
         room_hint: None,
         attributes,
         custom_data: None,
-        other_device_ids: None,
+        other_device_ids: other_device_ids(device, node),
+    })
+}
+
+/// Splits a Homie device or node name on the given delimiter, if any, into its trimmed non-empty
+/// parts. There is always at least one, which is the whole name if there is nothing to split.
+fn split_name(name: &str, delimiter: Option<&str>) -> Vec<String> {
+    let names: Vec<String> = match delimiter {
+        Some(delimiter) if !delimiter.is_empty() => name
+            .split(delimiter)
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .map(str::to_owned)
+            .collect(),
+        _ => vec![],
+    };
+    if names.is_empty() {
+        vec![name.to_owned()]
+    } else {
+        names
+    }
+}
+
+/// Logs why the given node isn't being exposed to Google Home, and returns `None`.
+fn skip_node(device: &Device, node: &Node, reason: &str) -> Option<PayloadDevice> {
+    tracing::debug!("Skipping node {}/{}: {}", device.id, node.id, reason);
+    None
+}
+
+/// Returns alternate IDs for the node based on the MAC address of its device, if it has one, so
+/// that Google can correlate it with the same device from other integrations.
+fn other_device_ids(device: &Device, node: &Node) -> Option<Vec<PayloadOtherDeviceID>> {
+    let mac = device.mac.as_ref()?.to_lowercase();
+    Some(vec![PayloadOtherDeviceID {
+        agent_id: None,
+        device_id: format!("{}/{}", mac, node.id),
+    }])
+}
+
+/// Converts a Homie enum property to a Google Home mode, with a setting for each enum value.
+fn property_to_mode(property: &Property) -> Option<Mode> {
+    let settings = property
+        .enum_values()
+        .ok()?
+        .into_iter()
+        .map(|value| ModeSetting {
+            setting_name: value.to_owned(),
+            setting_values: vec![ModeSettingValue {
+                setting_synonym: vec![value.to_owned()],
+                lang: "en".to_string(),
+            }],
+        })
+        .collect();
+    Some(Mode {
+        name: property.id.clone(),
+        name_values: vec![ModeName {
+            name_synonym: vec![property.name.clone().unwrap_or_else(|| property.id.clone())],
+            lang: "en".to_string(),
+        }],
+        settings,
+        ordered: false,
     })
 }
 
+/// Returns whether the given node looks like a scene which can be activated, i.e. it has a boolean
+/// `activate` property which is either settable or on a node of type `scene`.
+fn is_scene(node: &Node) -> bool {
+    if let Some(activate) = node.properties.get("activate") {
+        activate.datatype == Some(Datatype::Boolean)
+            && (activate.settable || node.node_type.as_deref() == Some("scene"))
+    } else {
+        false
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use homie_controller::{Datatype, Property, State};
+    use crate::config::server::{DefaultNames, StringSensor, TemperatureTrait};
+    use homie_controller::{HomieController, Property, State};
+    use rumqttc::{MqttOptions, QoS};
+    use std::io::{self, Write};
+    use std::str::FromStr;
+    use std::sync::{Arc, Mutex};
+    use tracing::Level;
 
     #[test]
     fn light_with_brightness() {
@@ -192,7 +560,13 @@ mod tests {
         };
 
         assert_eq!(
-            homie_node_to_google_home(&device, &device.nodes.get("node").unwrap()).unwrap(),
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("node").unwrap()
+            )
+            .unwrap(),
             PayloadDevice {
                 id: "device/node".to_string(),
                 device_type: GHomeDeviceType::Light,
@@ -213,6 +587,198 @@ mod tests {
         );
     }
 
+    #[test]
+    fn command_only_on_off() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: false,
+            unit: None,
+            format: None,
+            value: None,
+        };
+        let node = Node {
+            id: "power".to_string(),
+            name: Some("Power".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property]),
+        };
+        let device = Device {
+            id: "ir-blaster".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("TV".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let node = device.nodes.get("power").unwrap();
+
+        let google_home_device =
+            homie_node_to_google_home(&Devices::default(), None, &device, node).unwrap();
+        assert_eq!(google_home_device.attributes, Attributes::default());
+
+        for command_only in ["ir-blaster", "ir-blaster/power"] {
+            let config = Devices {
+                command_only_on_off: vec![command_only.to_string()],
+                ..Default::default()
+            };
+            let google_home_device =
+                homie_node_to_google_home(&config, None, &device, node).unwrap();
+            assert_eq!(google_home_device.traits, vec![GHomeDeviceTrait::OnOff]);
+            assert_eq!(
+                google_home_device.attributes,
+                Attributes {
+                    command_only_on_off: Some(true),
+                    ..Default::default()
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn split_names() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let node = Node {
+            id: "light".to_string(),
+            name: Some("Lamp, Reading Light".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property]),
+        };
+        let device = Device {
+            id: "lamp".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Study, Office".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let node = device.nodes.get("light").unwrap();
+
+        let google_home_device =
+            homie_node_to_google_home(&Devices::default(), None, &device, node).unwrap();
+        assert_eq!(
+            google_home_device.name,
+            response::PayloadDeviceName {
+                default_names: None,
+                name: "Study, Office Lamp, Reading Light".to_string(),
+                nicknames: Some(vec!["Lamp, Reading Light".to_string()]),
+            }
+        );
+
+        let config = Devices {
+            name_delimiter: Some(",".to_string()),
+            ..Default::default()
+        };
+        let google_home_device = homie_node_to_google_home(&config, None, &device, node).unwrap();
+        assert_eq!(
+            google_home_device.name,
+            response::PayloadDeviceName {
+                default_names: None,
+                name: "Study Lamp".to_string(),
+                nicknames: Some(vec!["Lamp".to_string(), "Reading Light".to_string()]),
+            }
+        );
+
+        assert_eq!(split_name(" , ", Some(",")), vec![" , ".to_string()]);
+    }
+
+    #[test]
+    fn node_type_overrides_device_type() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let node = Node {
+            id: "socket".to_string(),
+            name: Some("Socket".to_string()),
+            node_type: Some("socket".to_string()),
+            properties: property_set(vec![on_property]),
+        };
+        let device = Device {
+            id: "plug".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Plug".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let node = device.nodes.get("socket").unwrap();
+
+        // Without a mapping, a node with only an `on` property is a switch.
+        let google_home_device =
+            homie_node_to_google_home(&Devices::default(), None, &device, node).unwrap();
+        assert_eq!(google_home_device.device_type, GHomeDeviceType::Switch);
+
+        let config = Devices {
+            node_types: [
+                ("socket".to_string(), GHomeDeviceType::Outlet),
+                ("lightbulb".to_string(), GHomeDeviceType::Light),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        let google_home_device = homie_node_to_google_home(&config, None, &device, node).unwrap();
+        assert_eq!(google_home_device.device_type, GHomeDeviceType::Outlet);
+        assert_eq!(google_home_device.traits, vec![GHomeDeviceTrait::OnOff]);
+    }
+
     #[test]
     fn light_with_color() {
         let on_property = Property {
@@ -264,7 +830,13 @@ mod tests {
         };
 
         assert_eq!(
-            homie_node_to_google_home(&device, &device.nodes.get("node").unwrap()).unwrap(),
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("node").unwrap()
+            )
+            .unwrap(),
             PayloadDevice {
                 id: "device/node".to_string(),
                 device_type: GHomeDeviceType::Light,
@@ -289,32 +861,32 @@ mod tests {
     }
 
     #[test]
-    fn temperature_sensor() {
-        let temperature_property = Property {
-            id: "temperature".to_string(),
-            name: Some("Temperature".to_string()),
-            datatype: Some(Datatype::Float),
+    fn light_with_color_and_temperature() {
+        let color_property = Property {
+            id: "color".to_string(),
+            name: Some("Colour".to_string()),
+            datatype: Some(Datatype::Color),
             settable: true,
             retained: true,
-            unit: Some("°C".to_string()),
-            format: None,
-            value: Some("21.3".to_string()),
+            unit: None,
+            format: Some("hsv".to_string()),
+            value: Some("60,100,100".to_string()),
         };
-        let humidity_property = Property {
-            id: "humidity".to_string(),
-            name: Some("Humidity".to_string()),
+        let color_temperature_property = Property {
+            id: "color-temperature".to_string(),
+            name: Some("Colour temperature".to_string()),
             datatype: Some(Datatype::Integer),
             settable: true,
             retained: true,
-            unit: Some("%".to_string()),
-            format: Some("0:100".to_string()),
-            value: Some("27".to_string()),
+            unit: Some("K".to_string()),
+            format: Some("2700:6500".to_string()),
+            value: Some("3000".to_string()),
         };
         let node = Node {
             id: "node".to_string(),
             name: Some("Node name".to_string()),
             node_type: None,
-            properties: property_set(vec![temperature_property, humidity_property]),
+            properties: property_set(vec![color_property, color_temperature_property]),
         };
         let device = Device {
             id: "device".to_string(),
@@ -338,44 +910,1785 @@ mod tests {
             stats_supply: None,
         };
 
+        let google_home_device = homie_node_to_google_home(
+            &Devices::default(),
+            None,
+            &device,
+            device.nodes.get("node").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(google_home_device.device_type, GHomeDeviceType::Light);
         assert_eq!(
-            homie_node_to_google_home(&device, &device.nodes.get("node").unwrap()).unwrap(),
-            PayloadDevice {
-                id: "device/node".to_string(),
-                device_type: GHomeDeviceType::Thermostat,
-                traits: vec![GHomeDeviceTrait::TemperatureSetting],
-                name: response::PayloadDeviceName {
-                    default_names: None,
-                    name: "Device name Node name".to_string(),
-                    nicknames: Some(vec!["Node name".to_string()])
-                },
-                will_report_state: true,
-                notification_supported_by_agent: false,
-                room_hint: None,
-                device_info: None,
-                attributes: Attributes {
-                    available_thermostat_modes: Some(vec!["off".to_string()]),
-                    thermostat_temperature_unit: Some(ThermostatTemperatureUnit::C),
-                    query_only_temperature_setting: Some(true),
-                    ..Attributes::default()
-                },
-                custom_data: None,
-                other_device_ids: None,
+            google_home_device.traits,
+            vec![GHomeDeviceTrait::ColorSetting]
+        );
+        assert_eq!(
+            google_home_device.attributes,
+            Attributes {
+                color_model: Some(ColorModel::Hsv),
+                color_temperature_range: Some(ColorTemperatureRange {
+                    temperature_min_k: 2700,
+                    temperature_max_k: 6500,
+                }),
+                ..Attributes::default()
             }
         );
     }
 
-    fn property_set(properties: Vec<Property>) -> HashMap<String, Property> {
-        properties
-            .into_iter()
-            .map(|property| (property.id.clone(), property))
-            .collect()
-    }
-
-    fn node_set(nodes: Vec<Node>) -> HashMap<String, Node> {
-        nodes
-            .into_iter()
-            .map(|node| (node.id.clone(), node))
-            .collect()
+    #[test]
+    fn light_with_command_only_color() {
+        let color_property = Property {
+            id: "color".to_string(),
+            name: Some("Colour".to_string()),
+            datatype: Some(Datatype::Color),
+            settable: true,
+            retained: false,
+            unit: None,
+            format: Some("hsv".to_string()),
+            value: None,
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![color_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+
+        let google_device = homie_node_to_google_home(
+            &Devices::default(),
+            None,
+            &device,
+            device.nodes.get("node").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(google_device.traits, vec![GHomeDeviceTrait::ColorSetting]);
+        assert_eq!(
+            google_device.attributes,
+            Attributes {
+                color_model: Some(ColorModel::Hsv),
+                command_only_color_setting: Some(true),
+                ..Attributes::default()
+            }
+        );
+    }
+
+    #[test]
+    fn temperature_sensor() {
+        let temperature_property = Property {
+            id: "temperature".to_string(),
+            name: Some("Temperature".to_string()),
+            datatype: Some(Datatype::Float),
+            settable: true,
+            retained: true,
+            unit: Some("°C".to_string()),
+            format: None,
+            value: Some("21.3".to_string()),
+        };
+        let humidity_property = Property {
+            id: "humidity".to_string(),
+            name: Some("Humidity".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("%".to_string()),
+            format: Some("0:100".to_string()),
+            value: Some("27".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![temperature_property, humidity_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+
+        assert_eq!(
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("node").unwrap()
+            )
+            .unwrap(),
+            PayloadDevice {
+                id: "device/node".to_string(),
+                device_type: GHomeDeviceType::Thermostat,
+                traits: vec![GHomeDeviceTrait::TemperatureSetting],
+                name: response::PayloadDeviceName {
+                    default_names: None,
+                    name: "Device name Node name".to_string(),
+                    nicknames: Some(vec!["Node name".to_string()])
+                },
+                will_report_state: true,
+                notification_supported_by_agent: false,
+                room_hint: None,
+                device_info: None,
+                attributes: Attributes {
+                    available_thermostat_modes: Some(vec!["off".to_string()]),
+                    thermostat_temperature_unit: Some(ThermostatTemperatureUnit::C),
+                    query_only_temperature_setting: Some(true),
+                    ..Attributes::default()
+                },
+                custom_data: None,
+                other_device_ids: None,
+            }
+        );
+    }
+
+    #[test]
+    fn temperature_sensor_state() {
+        let temperature_property = Property {
+            id: "temperature".to_string(),
+            name: Some("Temperature".to_string()),
+            datatype: Some(Datatype::Float),
+            settable: false,
+            retained: true,
+            unit: Some("°C".to_string()),
+            format: None,
+            value: Some("21.3".to_string()),
+        };
+        let humidity_property = Property {
+            id: "humidity".to_string(),
+            name: Some("Humidity".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: false,
+            retained: true,
+            unit: Some("%".to_string()),
+            format: Some("0:100".to_string()),
+            value: Some("27".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![temperature_property, humidity_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let config = Devices {
+            temperature_trait: TemperatureTrait::SensorState,
+            ..Default::default()
+        };
+
+        let google_home_device =
+            homie_node_to_google_home(&config, None, &device, device.nodes.get("node").unwrap())
+                .unwrap();
+        assert_eq!(google_home_device.device_type, GHomeDeviceType::Sensor);
+        assert_eq!(
+            google_home_device.traits,
+            vec![GHomeDeviceTrait::SensorState]
+        );
+        assert_eq!(
+            google_home_device.attributes,
+            Attributes {
+                sensor_states_supported: Some(vec![
+                    SensorStateSupported {
+                        name: "Temperature".to_string(),
+                        descriptive_capabilities: None,
+                        numeric_capabilities: Some(NumericCapabilities {
+                            raw_value_unit: "DEGREES_CELSIUS".to_string(),
+                        }),
+                    },
+                    SensorStateSupported {
+                        name: "Humidity".to_string(),
+                        descriptive_capabilities: None,
+                        numeric_capabilities: Some(NumericCapabilities {
+                            raw_value_unit: "PERCENTAGE".to_string(),
+                        }),
+                    },
+                ]),
+                ..Attributes::default()
+            }
+        );
+    }
+
+    #[test]
+    fn thermostat_with_humidity_setting() {
+        let temperature_property = Property {
+            id: "temperature".to_string(),
+            name: Some("Temperature".to_string()),
+            datatype: Some(Datatype::Float),
+            settable: false,
+            retained: true,
+            unit: Some("°C".to_string()),
+            format: None,
+            value: Some("24.5".to_string()),
+        };
+        let humidity_property = Property {
+            id: "humidity".to_string(),
+            name: Some("Humidity".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: false,
+            retained: true,
+            unit: Some("%".to_string()),
+            format: None,
+            value: Some("62".to_string()),
+        };
+        let target_humidity_property = Property {
+            id: "target-humidity".to_string(),
+            name: Some("Target humidity".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("%".to_string()),
+            format: Some("30:70".to_string()),
+            value: Some("50".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Air conditioner".to_string()),
+            node_type: None,
+            properties: property_set(vec![
+                temperature_property,
+                humidity_property,
+                target_humidity_property,
+            ]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+
+        let google_home_device = homie_node_to_google_home(
+            &Devices::default(),
+            None,
+            &device,
+            device.nodes.get("node").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(google_home_device.device_type, GHomeDeviceType::Thermostat);
+        assert_eq!(
+            google_home_device.traits,
+            vec![
+                GHomeDeviceTrait::TemperatureSetting,
+                GHomeDeviceTrait::HumiditySetting
+            ]
+        );
+        assert_eq!(
+            google_home_device.attributes,
+            Attributes {
+                available_thermostat_modes: Some(vec!["off".to_string()]),
+                thermostat_temperature_unit: Some(ThermostatTemperatureUnit::C),
+                query_only_temperature_setting: Some(true),
+                humidity_setpoint_range: Some(HumiditySetpointRange {
+                    min_percent: 30,
+                    max_percent: 70,
+                }),
+                ..Attributes::default()
+            }
+        );
+    }
+
+    #[test]
+    fn node_with_diagnostic_sensors() {
+        let temperature_property = Property {
+            id: "temperature".to_string(),
+            name: Some("Temperature".to_string()),
+            datatype: Some(Datatype::Float),
+            settable: false,
+            retained: true,
+            unit: Some("°C".to_string()),
+            format: None,
+            value: Some("21.3".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![temperature_property]),
+        };
+        let unsupported_node = Node {
+            id: "other".to_string(),
+            name: Some("Other".to_string()),
+            node_type: None,
+            properties: HashMap::new(),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node, unsupported_node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: Some(48.5),
+            stats_cpuload: Some(12),
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let config = Devices {
+            diagnostic_sensors: true,
+            ..Devices::default()
+        };
+
+        // Without the config option, the stats aren't exposed.
+        let google_home_device = homie_node_to_google_home(
+            &Devices::default(),
+            None,
+            &device,
+            device.nodes.get("node").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(google_home_device.attributes.sensor_states_supported, None);
+
+        let google_home_device =
+            homie_node_to_google_home(&config, None, &device, device.nodes.get("node").unwrap())
+                .unwrap();
+        assert_eq!(google_home_device.device_type, GHomeDeviceType::Thermostat);
+        assert_eq!(
+            google_home_device.traits,
+            vec![
+                GHomeDeviceTrait::TemperatureSetting,
+                GHomeDeviceTrait::SensorState
+            ]
+        );
+        assert_eq!(
+            google_home_device.attributes.sensor_states_supported,
+            Some(vec![
+                SensorStateSupported {
+                    name: "CpuTemperature".to_string(),
+                    descriptive_capabilities: None,
+                    numeric_capabilities: Some(NumericCapabilities {
+                        raw_value_unit: "DEGREES_CELSIUS".to_string(),
+                    }),
+                },
+                SensorStateSupported {
+                    name: "CpuLoad".to_string(),
+                    descriptive_capabilities: None,
+                    numeric_capabilities: Some(NumericCapabilities {
+                        raw_value_unit: "PERCENTAGE".to_string(),
+                    }),
+                },
+            ])
+        );
+
+        // Nodes which wouldn't otherwise be exposed still aren't.
+        assert_eq!(
+            homie_node_to_google_home(&config, None, &device, device.nodes.get("other").unwrap()),
+            None
+        );
+    }
+
+    #[test]
+    fn skipped_node_logged() {
+        let unknown_property = Property {
+            id: "unknown".to_string(),
+            name: Some("Unknown".to_string()),
+            datatype: Some(Datatype::String),
+            settable: false,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("foo".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![unknown_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+
+        let (google_home_device, logs) = capture_logs(|| {
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("node").unwrap(),
+            )
+        });
+        assert_eq!(google_home_device, None);
+        assert!(
+            logs.contains("Skipping node device/node: no supported properties"),
+            "Unexpected logs: {}",
+            logs
+        );
+    }
+
+    #[test]
+    fn scene() {
+        let activate_property = Property {
+            id: "activate".to_string(),
+            name: Some("Activate".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: false,
+            unit: None,
+            format: None,
+            value: None,
+        };
+        let node = Node {
+            id: "movie".to_string(),
+            name: Some("Movie night".to_string()),
+            node_type: Some("scene".to_string()),
+            properties: property_set(vec![activate_property]),
+        };
+        let device = Device {
+            id: "scenes".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Scenes".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+
+        assert_eq!(
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("movie").unwrap()
+            )
+            .unwrap(),
+            PayloadDevice {
+                id: "scenes/movie".to_string(),
+                device_type: GHomeDeviceType::Scene,
+                traits: vec![GHomeDeviceTrait::Scene],
+                name: response::PayloadDeviceName {
+                    default_names: None,
+                    name: "Scenes Movie night".to_string(),
+                    nicknames: Some(vec!["Movie night".to_string()])
+                },
+                will_report_state: false,
+                notification_supported_by_agent: false,
+                room_hint: None,
+                device_info: None,
+                attributes: Attributes {
+                    scene_reversible: Some(false),
+                    ..Attributes::default()
+                },
+                custom_data: None,
+                other_device_ids: None,
+            }
+        );
+    }
+
+    #[test]
+    fn vacuum_with_dock() {
+        let dock_property = Property {
+            id: "dock".to_string(),
+            name: Some("Docked".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("false".to_string()),
+        };
+        let node = Node {
+            id: "robot".to_string(),
+            name: Some("Robot".to_string()),
+            node_type: None,
+            properties: property_set(vec![dock_property]),
+        };
+        let device = Device {
+            id: "vacuum".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Vacuum".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+
+        assert_eq!(
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("robot").unwrap()
+            )
+            .unwrap(),
+            PayloadDevice {
+                id: "vacuum/robot".to_string(),
+                device_type: GHomeDeviceType::Vacuum,
+                traits: vec![GHomeDeviceTrait::Dock],
+                name: response::PayloadDeviceName {
+                    default_names: None,
+                    name: "Vacuum Robot".to_string(),
+                    nicknames: Some(vec!["Robot".to_string()])
+                },
+                will_report_state: true,
+                notification_supported_by_agent: false,
+                room_hint: None,
+                device_info: None,
+                attributes: Attributes::default(),
+                custom_data: None,
+                other_device_ids: None,
+            }
+        );
+    }
+
+    #[test]
+    fn switch_with_reboot() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let reboot_property = Property {
+            id: "reboot".to_string(),
+            name: Some("Reboot".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: false,
+            unit: None,
+            format: None,
+            value: None,
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property, reboot_property.clone()]),
+        };
+        let reboot_only_node = Node {
+            id: "reboot-only".to_string(),
+            name: Some("Reboot only".to_string()),
+            node_type: None,
+            properties: property_set(vec![reboot_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node, reboot_only_node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+
+        let google_home_device = homie_node_to_google_home(
+            &Devices::default(),
+            None,
+            &device,
+            device.nodes.get("node").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(google_home_device.device_type, GHomeDeviceType::Switch);
+        assert_eq!(
+            google_home_device.traits,
+            vec![GHomeDeviceTrait::OnOff, GHomeDeviceTrait::Reboot]
+        );
+
+        // A node with nothing else to expose isn't exposed just for rebooting.
+        assert_eq!(
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("reboot-only").unwrap(),
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn default_names() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let node = |id: &str| Node {
+            id: id.to_string(),
+            name: None,
+            node_type: None,
+            properties: property_set(vec![on_property.clone()]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node("lamp"), node("plug")]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let config = Devices {
+            default_names: vec![
+                DefaultNames {
+                    node: Some("device/lamp".to_string()),
+                    device_type: None,
+                    names: vec!["Acme lamp".to_string()],
+                },
+                DefaultNames {
+                    node: None,
+                    device_type: Some(GHomeDeviceType::Switch),
+                    names: vec!["Smart switch".to_string(), "Switch".to_string()],
+                },
+                DefaultNames {
+                    node: None,
+                    device_type: Some(GHomeDeviceType::Light),
+                    names: vec!["Smart light".to_string()],
+                },
+            ],
+            ..Devices::default()
+        };
+        let default_names = |config: &Devices, node_id: &str| {
+            homie_node_to_google_home(config, None, &device, &device.nodes[node_id])
+                .unwrap()
+                .name
+                .default_names
+        };
+
+        assert_eq!(
+            default_names(&config, "lamp"),
+            Some(vec!["Acme lamp".to_string()])
+        );
+        assert_eq!(
+            default_names(&config, "plug"),
+            Some(vec!["Smart switch".to_string(), "Switch".to_string()])
+        );
+        assert_eq!(default_names(&Devices::default(), "plug"), None);
+    }
+
+    #[test]
+    fn fridge_with_temperature_control() {
+        let temperature_property = Property {
+            id: "temperature".to_string(),
+            name: Some("Temperature".to_string()),
+            datatype: Some(Datatype::Float),
+            settable: true,
+            retained: true,
+            unit: Some("°C".to_string()),
+            format: Some("1:7".to_string()),
+            value: Some("4.5".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![temperature_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let config = Devices {
+            temperature_trait: TemperatureTrait::TemperatureControl,
+            ..Default::default()
+        };
+
+        assert_eq!(
+            homie_node_to_google_home(&config, None, &device, device.nodes.get("node").unwrap())
+                .unwrap(),
+            PayloadDevice {
+                id: "device/node".to_string(),
+                device_type: GHomeDeviceType::Refrigerator,
+                traits: vec![GHomeDeviceTrait::TemperatureControl],
+                name: response::PayloadDeviceName {
+                    default_names: None,
+                    name: "Device name Node name".to_string(),
+                    nicknames: Some(vec!["Node name".to_string()]),
+                },
+                device_info: None,
+                will_report_state: true,
+                notification_supported_by_agent: false,
+                room_hint: None,
+                attributes: Attributes {
+                    temperature_range: Some(TemperatureRange {
+                        min_threshold_celsius: 1.0,
+                        max_threshold_celsius: 7.0,
+                    }),
+                    temperature_unit_for_ux: Some(ThermostatTemperatureUnit::C),
+                    ..Default::default()
+                },
+                custom_data: None,
+                other_device_ids: None,
+            }
+        );
+
+        // With the default config it is still a thermostat.
+        assert_eq!(
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("node").unwrap()
+            )
+            .unwrap()
+            .traits,
+            vec![GHomeDeviceTrait::TemperatureSetting]
+        );
+    }
+
+    #[test]
+    fn blinds_with_tilt() {
+        let position_property = Property {
+            id: "position".to_string(),
+            name: Some("Position".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("%".to_string()),
+            format: Some("0:100".to_string()),
+            value: Some("40".to_string()),
+        };
+        let tilt_property = Property {
+            id: "tilt".to_string(),
+            name: Some("Tilt".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("°".to_string()),
+            format: Some("-90:90".to_string()),
+            value: Some("30".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![position_property, tilt_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+
+        assert_eq!(
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("node").unwrap()
+            )
+            .unwrap(),
+            PayloadDevice {
+                id: "device/node".to_string(),
+                device_type: GHomeDeviceType::Blinds,
+                traits: vec![GHomeDeviceTrait::OpenClose, GHomeDeviceTrait::Rotation],
+                name: response::PayloadDeviceName {
+                    default_names: None,
+                    name: "Device name Node name".to_string(),
+                    nicknames: Some(vec!["Node name".to_string()]),
+                },
+                device_info: None,
+                will_report_state: true,
+                notification_supported_by_agent: false,
+                room_hint: None,
+                attributes: Attributes {
+                    supports_degrees: Some(true),
+                    rotation_degrees_range: Some(RotationDegreesRange {
+                        rotation_degrees_min: -90.0,
+                        rotation_degrees_max: 90.0,
+                    }),
+                    ..Default::default()
+                },
+                custom_data: None,
+                other_device_ids: None,
+            }
+        );
+    }
+
+    #[test]
+    fn blinds_with_tilt_no_range() {
+        let position_property = Property {
+            id: "position".to_string(),
+            name: Some("Position".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("%".to_string()),
+            format: Some("0:100".to_string()),
+            value: Some("40".to_string()),
+        };
+        let tilt_property = Property {
+            id: "tilt".to_string(),
+            name: Some("Tilt".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("°".to_string()),
+            format: None,
+            value: Some("30".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![position_property, tilt_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+
+        assert_eq!(
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("node").unwrap()
+            )
+            .unwrap(),
+            PayloadDevice {
+                id: "device/node".to_string(),
+                device_type: GHomeDeviceType::Blinds,
+                traits: vec![GHomeDeviceTrait::OpenClose],
+                name: response::PayloadDeviceName {
+                    default_names: None,
+                    name: "Device name Node name".to_string(),
+                    nicknames: Some(vec!["Node name".to_string()]),
+                },
+                device_info: None,
+                will_report_state: true,
+                notification_supported_by_agent: false,
+                room_hint: None,
+                attributes: Attributes::default(),
+                custom_data: None,
+                other_device_ids: None,
+            }
+        );
+    }
+
+    #[test]
+    fn blinds_with_tilt_percent() {
+        let position_property = Property {
+            id: "position".to_string(),
+            name: Some("Position".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("%".to_string()),
+            format: Some("0:100".to_string()),
+            value: Some("40".to_string()),
+        };
+        let tilt_property = Property {
+            id: "tilt".to_string(),
+            name: Some("Tilt".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: Some("%".to_string()),
+            format: Some("0:100".to_string()),
+            value: Some("30".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![position_property, tilt_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+
+        let google_home_device = homie_node_to_google_home(
+            &Devices::default(),
+            None,
+            &device,
+            device.nodes.get("node").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            google_home_device.attributes,
+            Attributes {
+                supports_percent: Some(true),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn disabled_traits() {
+        let temperature_property = Property {
+            id: "temperature".to_string(),
+            name: Some("Temperature".to_string()),
+            datatype: Some(Datatype::Float),
+            settable: false,
+            retained: true,
+            unit: Some("°C".to_string()),
+            format: None,
+            value: Some("21.3".to_string()),
+        };
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![temperature_property, on_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let node = device.nodes.get("node").unwrap();
+
+        let config = Devices {
+            disabled_traits: vec![GHomeDeviceTrait::TemperatureSetting],
+            ..Default::default()
+        };
+        let google_home_device = homie_node_to_google_home(&config, None, &device, node).unwrap();
+        assert_eq!(google_home_device.device_type, GHomeDeviceType::Switch);
+        assert_eq!(google_home_device.traits, vec![GHomeDeviceTrait::OnOff]);
+        assert_eq!(
+            google_home_device.attributes.thermostat_temperature_unit,
+            None
+        );
+
+        // If no traits are left then the node isn't included at all.
+        let config = Devices {
+            disabled_traits: vec![
+                GHomeDeviceTrait::TemperatureSetting,
+                GHomeDeviceTrait::OnOff,
+            ],
+            ..Default::default()
+        };
+        assert_eq!(
+            homie_node_to_google_home(&config, None, &device, node),
+            None
+        );
+    }
+
+    #[test]
+    fn other_device_ids_from_mac() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: Some("DE:AD:BE:EF:00:01".to_string()),
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+
+        assert_eq!(
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("node").unwrap(),
+            )
+            .unwrap()
+            .other_device_ids,
+            Some(vec![PayloadOtherDeviceID {
+                agent_id: None,
+                device_id: "de:ad:be:ef:00:01/node".to_string(),
+            }])
+        );
+    }
+
+    #[test]
+    fn string_sensor() {
+        let cycle_property = Property {
+            id: "cycle".to_string(),
+            name: Some("Cycle".to_string()),
+            datatype: Some(Datatype::String),
+            settable: false,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("spinning".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![cycle_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let config = Devices {
+            string_sensors: vec![StringSensor {
+                property: "cycle".to_string(),
+                name: "WashCycle".to_string(),
+                states: vec!["washing".to_string(), "spinning".to_string()],
+            }],
+            ..Default::default()
+        };
+
+        // Without the config the property isn't exposed.
+        assert_eq!(
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("node").unwrap(),
+            ),
+            None
+        );
+
+        let google_home_device =
+            homie_node_to_google_home(&config, None, &device, device.nodes.get("node").unwrap())
+                .unwrap();
+        assert_eq!(google_home_device.device_type, GHomeDeviceType::Sensor);
+        assert_eq!(
+            google_home_device.traits,
+            vec![GHomeDeviceTrait::SensorState]
+        );
+        assert_eq!(
+            google_home_device.attributes,
+            Attributes {
+                sensor_states_supported: Some(vec![SensorStateSupported {
+                    name: "WashCycle".to_string(),
+                    descriptive_capabilities: Some(DescriptiveCapabilities {
+                        available_states: vec!["washing".to_string(), "spinning".to_string()],
+                    }),
+                    numeric_capabilities: None,
+                }]),
+                ..Attributes::default()
+            }
+        );
+    }
+
+    #[test]
+    fn energy_meter() {
+        let energy_property = Property {
+            id: "energy".to_string(),
+            name: Some("Energy".to_string()),
+            datatype: Some(Datatype::Float),
+            settable: false,
+            retained: true,
+            unit: Some("kWh".to_string()),
+            format: None,
+            value: Some("1234.5".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![energy_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+        let config = Devices {
+            energy_property: Some("energy".to_string()),
+            ..Default::default()
+        };
+
+        // Without the config the property isn't exposed.
+        assert_eq!(
+            homie_node_to_google_home(
+                &Devices::default(),
+                None,
+                &device,
+                device.nodes.get("node").unwrap(),
+            ),
+            None
+        );
+
+        let google_home_device =
+            homie_node_to_google_home(&config, None, &device, device.nodes.get("node").unwrap())
+                .unwrap();
+        assert_eq!(google_home_device.device_type, GHomeDeviceType::Sensor);
+        assert_eq!(
+            google_home_device.traits,
+            vec![GHomeDeviceTrait::EnergyStorage]
+        );
+        assert_eq!(
+            google_home_device.attributes,
+            Attributes {
+                query_only_energy_storage: Some(true),
+                ..Attributes::default()
+            }
+        );
+    }
+
+    #[test]
+    fn washer_with_modes() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let mode_property = Property {
+            id: "mode".to_string(),
+            name: Some("Wash mode".to_string()),
+            datatype: Some(Datatype::Enum),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("delicate,normal,heavy".to_string()),
+            value: Some("normal".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: property_set(vec![on_property, mode_property]),
+        };
+        let device = Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: node_set(vec![node]),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        };
+
+        let google_home_device = homie_node_to_google_home(
+            &Devices::default(),
+            None,
+            &device,
+            device.nodes.get("node").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            google_home_device.traits,
+            vec![GHomeDeviceTrait::OnOff, GHomeDeviceTrait::Modes]
+        );
+        let setting = |name: &str| ModeSetting {
+            setting_name: name.to_string(),
+            setting_values: vec![ModeSettingValue {
+                setting_synonym: vec![name.to_string()],
+                lang: "en".to_string(),
+            }],
+        };
+        assert_eq!(
+            google_home_device.attributes.available_modes,
+            Some(vec![Mode {
+                name: "mode".to_string(),
+                name_values: vec![ModeName {
+                    name_synonym: vec!["Wash mode".to_string()],
+                    lang: "en".to_string(),
+                }],
+                settings: vec![setting("delicate"), setting("normal"), setting("heavy")],
+                ordered: false,
+            }])
+        );
+    }
+
+    #[test]
+    fn devices_in_all_states_included() {
+        for state in [
+            State::Init,
+            State::Ready,
+            State::Disconnected,
+            State::Sleeping,
+            State::Lost,
+            State::Alert,
+        ] {
+            let on_property = Property {
+                id: "on".to_string(),
+                name: Some("On".to_string()),
+                datatype: Some(Datatype::Boolean),
+                settable: true,
+                retained: true,
+                unit: None,
+                format: None,
+                value: Some("true".to_string()),
+            };
+            let node = Node {
+                id: "node".to_string(),
+                name: Some("Node name".to_string()),
+                node_type: None,
+                properties: property_set(vec![on_property]),
+            };
+            let device = Device {
+                id: "device".to_string(),
+                homie_version: "4.0".to_string(),
+                name: Some("Device name".to_string()),
+                state,
+                implementation: None,
+                nodes: node_set(vec![node]),
+                extensions: vec![],
+                local_ip: None,
+                mac: None,
+                firmware_name: None,
+                firmware_version: None,
+                stats_interval: None,
+                stats_uptime: None,
+                stats_signal: None,
+                stats_cputemp: None,
+                stats_cpuload: None,
+                stats_battery: None,
+                stats_freeheap: None,
+                stats_supply: None,
+            };
+            let devices = device_set(vec![device]);
+
+            let google_home_devices =
+                homie_devices_to_google_home(&Devices::default(), None, &devices);
+            assert_eq!(google_home_devices.len(), 1, "Device in state {:?}", state);
+            assert_eq!(google_home_devices[0].id, "device/node");
+        }
+    }
+
+    #[test]
+    fn max_devices() {
+        let devices: HashMap<String, Device> = (0..5)
+            .map(|i| {
+                let on_property = Property {
+                    id: "on".to_string(),
+                    name: Some("On".to_string()),
+                    datatype: Some(Datatype::Boolean),
+                    settable: true,
+                    retained: true,
+                    unit: None,
+                    format: None,
+                    value: Some("true".to_string()),
+                };
+                let node = Node {
+                    id: "node".to_string(),
+                    name: Some("Node name".to_string()),
+                    node_type: None,
+                    properties: property_set(vec![on_property]),
+                };
+                Device {
+                    id: format!("device-{}", i),
+                    homie_version: "4.0".to_string(),
+                    name: Some("Device name".to_string()),
+                    state: State::Ready,
+                    implementation: None,
+                    nodes: node_set(vec![node]),
+                    extensions: vec![],
+                    local_ip: None,
+                    mac: None,
+                    firmware_name: None,
+                    firmware_version: None,
+                    stats_interval: None,
+                    stats_uptime: None,
+                    stats_signal: None,
+                    stats_cputemp: None,
+                    stats_cpuload: None,
+                    stats_battery: None,
+                    stats_freeheap: None,
+                    stats_supply: None,
+                }
+            })
+            .map(|device| (device.id.clone(), device))
+            .collect();
+        let config = |max_devices| Devices {
+            max_devices,
+            ..Devices::default()
+        };
+
+        let (google_home_devices, logs) =
+            capture_logs(|| homie_devices_to_google_home(&config(Some(3)), None, &devices));
+        assert_eq!(
+            google_home_devices
+                .iter()
+                .map(|device| device.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["device-0/node", "device-1/node", "device-2/node"]
+        );
+        assert!(
+            logs.contains("Found 5 devices, but only returning the maximum of 3"),
+            "Unexpected logs: {}",
+            logs
+        );
+
+        let (google_home_devices, logs) =
+            capture_logs(|| homie_devices_to_google_home(&config(Some(5)), None, &devices));
+        assert_eq!(google_home_devices.len(), 5);
+        assert!(!logs.contains("maximum"), "Unexpected logs: {}", logs);
+
+        assert_eq!(
+            homie_devices_to_google_home(&config(None), None, &devices).len(),
+            5
+        );
+    }
+
+    fn state_with_sync_cache(user_id: user::ID, sync_cache_seconds: u64) -> crate::State {
+        let config = toml::from_str(&format!(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [fulfillment]
+            sync-cache-seconds = {}
+            "#,
+            sync_cache_seconds
+        ))
+        .unwrap();
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        crate::State::new(
+            config,
+            [(user_id, Arc::new(controller))].into_iter().collect(),
+        )
+    }
+
+    #[tokio::test]
+    async fn cached_within_ttl() {
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let state = state_with_sync_cache(user_id, 60);
+
+        let payload = handle(state.clone(), user_id).await.unwrap();
+        assert_eq!(payload.devices, vec![]);
+        let devices = state.homie_controllers[&user_id].devices();
+        assert_eq!(
+            state
+                .sync_cache
+                .get(&user_id, &devices, Duration::from_secs(60)),
+            Some(payload.clone())
+        );
+
+        // The second SYNC uses the cached response rather than converting the devices again.
+        let cached = response::Payload {
+            debug_string: Some("cached".to_string()),
+            ..payload
+        };
+        state.sync_cache.insert(user_id, devices, cached.clone());
+        assert_eq!(handle(state, user_id).await.unwrap(), cached);
+    }
+
+    #[tokio::test]
+    async fn cache_disabled() {
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let state = state_with_sync_cache(user_id, 0);
+
+        let payload = handle(state.clone(), user_id).await.unwrap();
+        let cached = response::Payload {
+            debug_string: Some("cached".to_string()),
+            ..payload.clone()
+        };
+        let devices = state.homie_controllers[&user_id].devices();
+        state.sync_cache.insert(user_id, devices, cached);
+        assert_eq!(handle(state, user_id).await.unwrap(), payload);
+    }
+
+    #[test]
+    fn deterministic_order() {
+        let node = |id: &str| {
+            let on_property = Property {
+                id: "on".to_string(),
+                name: Some("On".to_string()),
+                datatype: Some(Datatype::Boolean),
+                settable: true,
+                retained: true,
+                unit: None,
+                format: None,
+                value: Some("true".to_string()),
+            };
+            Node {
+                id: id.to_string(),
+                name: Some("Node name".to_string()),
+                node_type: None,
+                properties: property_set(vec![on_property]),
+            }
+        };
+        let devices = device_set(
+            ["c", "a", "b"]
+                .iter()
+                .map(|id| Device {
+                    id: id.to_string(),
+                    homie_version: "4.0".to_string(),
+                    name: Some("Device name".to_string()),
+                    state: State::Ready,
+                    implementation: None,
+                    nodes: node_set(vec![node("z"), node("x"), node("y")]),
+                    extensions: vec![],
+                    local_ip: None,
+                    mac: None,
+                    firmware_name: None,
+                    firmware_version: None,
+                    stats_interval: None,
+                    stats_uptime: None,
+                    stats_signal: None,
+                    stats_cputemp: None,
+                    stats_cpuload: None,
+                    stats_battery: None,
+                    stats_freeheap: None,
+                    stats_supply: None,
+                })
+                .collect(),
+        );
+
+        let ids: Vec<String> = homie_devices_to_google_home(&Devices::default(), None, &devices)
+            .into_iter()
+            .map(|device| device.id)
+            .collect();
+        assert_eq!(
+            ids,
+            vec!["a/x", "a/y", "a/z", "b/x", "b/y", "b/z", "c/x", "c/y", "c/z"]
+        );
+    }
+
+    fn property_set(properties: Vec<Property>) -> HashMap<String, Property> {
+        properties
+            .into_iter()
+            .map(|property| (property.id.clone(), property))
+            .collect()
+    }
+
+    fn node_set(nodes: Vec<Node>) -> HashMap<String, Node> {
+        nodes
+            .into_iter()
+            .map(|node| (node.id.clone(), node))
+            .collect()
+    }
+
+    fn device_set(devices: Vec<Device>) -> HashMap<String, Device> {
+        devices
+            .into_iter()
+            .map(|device| (device.id.clone(), device))
+            .collect()
+    }
+
+    /// Runs the given function with all logs captured, and returns its result along with the logs.
+    fn capture_logs<T>(f: impl FnOnce() -> T) -> (T, String) {
+        let logs = LogBuffer::default();
+        let writer = logs.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(Level::DEBUG)
+            .with_ansi(false)
+            .with_writer(move || writer.clone())
+            .finish();
+        let result = tracing::subscriber::with_default(subscriber, f);
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        (result, logs)
+    }
+
+    #[derive(Clone, Default)]
+    struct LogBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for LogBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
     }
 }
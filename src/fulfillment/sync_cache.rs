@@ -0,0 +1,120 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+use crate::types::user;
+use google_smart_home::sync::response;
+use homie_controller::Device;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+/// The most recent SYNC response for each user, so that repeated SYNC requests in quick succession
+/// don't each need to convert all the Homie devices again.
+#[derive(Debug, Default)]
+pub struct SyncCache(Mutex<HashMap<user::ID, CacheEntry>>);
+
+#[derive(Debug)]
+struct CacheEntry {
+    cached_at: Instant,
+    /// The Homie controller's devices from which the response was built.
+    devices: Arc<HashMap<String, Device>>,
+    payload: response::Payload,
+}
+
+impl SyncCache {
+    /// Returns the cached SYNC response for the given user, if there is one from within the given
+    /// time to live which was built from the same Homie devices.
+    ///
+    /// The Homie controller replaces its map of devices whenever anything changes, so comparing
+    /// pointers is enough to tell whether the response is out of date.
+    pub fn get(
+        &self,
+        user_id: &user::ID,
+        devices: &Arc<HashMap<String, Device>>,
+        ttl: Duration,
+    ) -> Option<response::Payload> {
+        self.get_at(user_id, devices, ttl, Instant::now())
+    }
+
+    fn get_at(
+        &self,
+        user_id: &user::ID,
+        devices: &Arc<HashMap<String, Device>>,
+        ttl: Duration,
+        now: Instant,
+    ) -> Option<response::Payload> {
+        let cache = self.0.lock().unwrap();
+        let entry = cache.get(user_id)?;
+        if Arc::ptr_eq(&entry.devices, devices)
+            && now.saturating_duration_since(entry.cached_at) < ttl
+        {
+            Some(entry.payload.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Caches the given SYNC response built from the given devices for the given user, replacing
+    /// any previous one.
+    pub fn insert(
+        &self,
+        user_id: user::ID,
+        devices: Arc<HashMap<String, Device>>,
+        payload: response::Payload,
+    ) {
+        let entry = CacheEntry {
+            cached_at: Instant::now(),
+            devices,
+            payload,
+        };
+        self.0.lock().unwrap().insert(user_id, entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn expires() {
+        let cache = SyncCache::default();
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let ttl = Duration::from_secs(5);
+        let payload = response::Payload {
+            agent_user_id: "agent".to_string(),
+            error_code: None,
+            debug_string: None,
+            devices: vec![],
+        };
+        let devices = Arc::new(HashMap::new());
+
+        assert_eq!(cache.get(&user_id, &devices, ttl), None);
+        cache.insert(user_id, devices.clone(), payload.clone());
+        assert_eq!(cache.get(&user_id, &devices, ttl), Some(payload));
+        assert_eq!(
+            cache.get_at(
+                &user_id,
+                &devices,
+                ttl,
+                Instant::now() + Duration::from_secs(6)
+            ),
+            None
+        );
+        // A zero time to live disables the cache.
+        assert_eq!(cache.get(&user_id, &devices, Duration::ZERO), None);
+        // Once the devices change the cached response is no longer used.
+        assert_eq!(cache.get(&user_id, &Arc::new(HashMap::new()), ttl), None);
+    }
+}
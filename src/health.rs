@@ -0,0 +1,158 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+use crate::types::errors::{AuthError, ServerError};
+use crate::types::user;
+use crate::State;
+use axum::extract::Extension;
+use axum::Json;
+use http::HeaderMap;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::Ordering;
+
+/// The header in which the health check secret must be sent, if one is configured.
+const HEALTH_SECRET_HEADER: &str = "X-Health-Secret";
+
+/// A minimal liveness probe, which is always unauthenticated.
+pub async fn liveness() -> &'static str {
+    "I'm alive!"
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct DetailedHealth {
+    pub users: Vec<UserHealth>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+pub struct UserHealth {
+    pub user_id: user::ID,
+    /// Whether the user's Homie controller is currently connected to its MQTT broker.
+    pub mqtt_connected: bool,
+    /// The number of Homie devices the controller has discovered.
+    pub device_count: usize,
+}
+
+/// Returns the status of each user's Homie controller, if the request includes the configured
+/// health check secret.
+pub async fn detailed(
+    Extension(state): Extension<State>,
+    headers: HeaderMap,
+) -> Result<Json<DetailedHealth>, ServerError> {
+    if let Some(secret) = &state.config.health.secret {
+        let given = headers
+            .get(HEALTH_SECRET_HEADER)
+            .map(|value| value.as_bytes());
+        if !given.is_some_and(|given| constant_time_eq(given, secret.as_bytes())) {
+            return Err(AuthError::InvalidHealthSecret.into());
+        }
+    }
+
+    let mut users: Vec<_> = state
+        .homie_controllers
+        .iter()
+        .map(|(user_id, controller)| UserHealth {
+            user_id: *user_id,
+            mqtt_connected: state.mqtt_connected[user_id].load(Ordering::Relaxed),
+            device_count: controller.devices().len(),
+        })
+        .collect();
+    users.sort_by_key(|user| user.user_id.to_string());
+    Ok(Json(DetailedHealth { users }))
+}
+
+/// Compares two byte strings without returning early on the first difference, so as not to leak
+/// how much of the secret was guessed correctly.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app;
+    use homie_controller::HomieController;
+    use http::{Request, StatusCode};
+    use hyper::Body;
+    use rumqttc::{MqttOptions, QoS};
+    use std::str::FromStr;
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    fn test_state() -> State {
+        let config = toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [health]
+            secret = "health-secret"
+            "#,
+        )
+        .unwrap();
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::AtLeastOnce,
+        );
+        State::new(
+            config,
+            [(user_id, Arc::new(controller))].into_iter().collect(),
+        )
+    }
+
+    async fn get_detailed(secret: Option<&str>) -> http::Response<axum::body::BoxBody> {
+        let mut request = Request::get("/health_check/detailed");
+        if let Some(secret) = secret {
+            request = request.header(HEALTH_SECRET_HEADER, secret);
+        }
+        app(test_state())
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn liveness_needs_no_secret() {
+        let response = app(test_state())
+            .oneshot(Request::get("/health_check").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn detailed_requires_secret() {
+        assert_eq!(get_detailed(None).await.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            get_detailed(Some("wrong-secret")).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let response = get_detailed(Some("health-secret")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let health: DetailedHealth = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            health,
+            DetailedHealth {
+                users: vec![UserHealth {
+                    user_id: user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap(),
+                    mqtt_connected: false,
+                    device_count: 0,
+                }]
+            }
+        );
+    }
+}
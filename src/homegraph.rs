@@ -10,7 +10,8 @@
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU General Public License for more details.
 
-use crate::{json_prost::json_to_prost_struct, types::user};
+use crate::json_prost::json_to_prost_struct;
+use crate::retry::retry;
 use google_api_proto::google::home::graph::v1::{
     home_graph_api_service_client::HomeGraphApiServiceClient, ReportStateAndNotificationDevice,
     ReportStateAndNotificationRequest, RequestSyncDevicesRequest, StateAndNotificationPayload,
@@ -19,38 +20,89 @@ use google_authz::{Credentials, GoogleAuthz};
 use google_smart_home::query::response;
 use prost_types::{value::Kind, Struct, Value};
 use serde_json::to_value;
-use std::{collections::BTreeMap, error::Error, path::Path, sync::Arc};
-use tokio::sync::Mutex;
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fmt::Debug,
+    future::Future,
+    path::Path,
+    time::{Duration, Instant},
+};
 use tonic::{transport::Channel, Status};
+use tracing::{field, Span};
+
+/// How many times to retry building credentials if it fails, such as because fetching the initial
+/// token failed.
+const CREDENTIALS_RETRIES: u32 = 3;
+/// How long to wait before retrying building credentials.
+const CREDENTIALS_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Builds credentials with the given function, retrying up to the given number of times on failure
+/// so that a momentary problem fetching a token doesn't stop the server from starting.
+async fn build_credentials<E: Debug, F: Future<Output = Result<Credentials, E>>>(
+    retries: u32,
+    retry_delay: Duration,
+    build: impl FnMut() -> F,
+) -> Result<Credentials, E> {
+    retry(retries, retry_delay, build).await
+}
 
+/// A client for the Google Home Graph API.
+///
+/// This is cheap to clone, and clones share the same underlying connection. Each call uses its own
+/// clone of the gRPC client, so concurrent calls don't block each other.
 #[derive(Clone, Debug)]
-pub struct HomeGraphClient(Arc<Mutex<HomeGraphApiServiceClient<GoogleAuthz<Channel>>>>);
+pub struct HomeGraphClient(HomeGraphApiServiceClient<GoogleAuthz<Channel>>);
 
 impl HomeGraphClient {
     /// Connects to the Google Home Graph gRPC API server and returns a client which can make calls to
     /// the API.
     pub async fn connect(credentials_file: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::connect_with_retries(credentials_file, CREDENTIALS_RETRIES).await
+    }
+
+    /// Connects like [`connect`](Self::connect), but only tries building the credentials once, so
+    /// that a problem with them is reported straight away.
+    pub async fn connect_without_retries(credentials_file: &Path) -> Result<Self, Box<dyn Error>> {
+        Self::connect_with_retries(credentials_file, 0).await
+    }
+
+    async fn connect_with_retries(
+        credentials_file: &Path,
+        retries: u32,
+    ) -> Result<Self, Box<dyn Error>> {
+        let credentials = build_credentials(retries, CREDENTIALS_RETRY_DELAY, || {
+            Credentials::builder()
+                .json_file(credentials_file)
+                .scopes(&["https://www.googleapis.com/auth/homegraph"])
+                .build()
+        })
+        .await?;
         let channel = Channel::from_static("https://homegraph.googleapis.com")
             .connect()
             .await?;
-        let credentials = Credentials::builder()
-            .json_file(credentials_file)
-            .scopes(&["https://www.googleapis.com/auth/homegraph"])
-            .build()
-            .await?;
+        Ok(Self::new(channel, credentials).await)
+    }
+
+    /// Returns a client which makes calls over the given channel, authenticated with the given
+    /// credentials.
+    async fn new(channel: Channel, credentials: Credentials) -> Self {
         let channel = GoogleAuthz::builder(channel)
             .credentials(credentials)
             .build()
             .await;
-        Ok(Self(Arc::new(Mutex::new(HomeGraphApiServiceClient::new(
-            channel,
-        )))))
+        Self(HomeGraphApiServiceClient::new(channel))
     }
 
     /// Reports state of the single device with the given ID for the given user.
+    #[tracing::instrument(
+        name = "ReportState",
+        skip(self, state),
+        fields(latency_ms = field::Empty, status = field::Empty)
+    )]
     pub async fn report_state(
         &self,
-        user_id: user::ID,
+        agent_user_id: &str,
         device_id: String,
         state: response::State,
     ) -> Result<(), Status> {
@@ -62,7 +114,7 @@ impl HomeGraphClient {
             },
         );
         let request = ReportStateAndNotificationRequest {
-            agent_user_id: user_id.to_string(),
+            agent_user_id: agent_user_id.to_owned(),
             payload: Some(StateAndNotificationPayload {
                 devices: Some(ReportStateAndNotificationDevice {
                     states: Some(Struct { fields }),
@@ -71,27 +123,46 @@ impl HomeGraphClient {
             }),
             ..Default::default()
         };
-        self.0
-            .lock()
-            .await
-            .report_state_and_notification(request)
-            .await?;
+        let mut client = self.0.clone();
+        record_call(client.report_state_and_notification(request)).await?;
 
         Ok(())
     }
 
     /// Requests that Google make a SYNC intent, because devices have been added, removed or changed.
-    pub async fn request_sync(&self, user_id: user::ID) -> Result<(), Status> {
+    #[tracing::instrument(
+        name = "RequestSync",
+        skip(self),
+        fields(latency_ms = field::Empty, status = field::Empty)
+    )]
+    pub async fn request_sync(&self, agent_user_id: &str) -> Result<(), Status> {
         let request = RequestSyncDevicesRequest {
-            agent_user_id: user_id.to_string(),
+            agent_user_id: agent_user_id.to_owned(),
             r#async: true,
         };
-        self.0.lock().await.request_sync_devices(request).await?;
+        let mut client = self.0.clone();
+        record_call(client.request_sync_devices(request)).await?;
 
         Ok(())
     }
 }
 
+/// Awaits the given gRPC call, recording its latency and status on the current span.
+async fn record_call<T>(call: impl Future<Output = Result<T, Status>>) -> Result<T, Status> {
+    let start = Instant::now();
+    let result = call.await;
+    let latency_ms = start.elapsed().as_millis() as u64;
+    let status = match &result {
+        Ok(_) => "OK".to_string(),
+        Err(status) => format!("{:?}", status.code()),
+    };
+    let span = Span::current();
+    span.record("latency_ms", &latency_ms);
+    span.record("status", &status.as_str());
+    tracing::debug!(latency_ms, %status, "Home Graph call finished");
+    result
+}
+
 fn query_state_to_report_state(state: response::State) -> Struct {
     if let Ok(serde_json::Value::Object(state_map)) = to_value(state) {
         json_to_prost_struct(state_map)
@@ -102,11 +173,201 @@ fn query_state_to_report_state(state: response::State) -> Struct {
 
 #[cfg(test)]
 mod tests {
+    use futures_util::future::join_all;
+    use hyper::{
+        body::Bytes,
+        header::HeaderValue,
+        service::{make_service_fn, service_fn},
+        Body, HeaderMap, Response, Server,
+    };
     use prost_types::{value::Kind, Value};
-    use std::collections::BTreeMap;
+    use std::{
+        collections::BTreeMap,
+        convert::Infallible,
+        net::SocketAddr,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+    use tokio::sync::Barrier;
+    use tonic::Code;
+    use tracing::{
+        field::{Field, Visit},
+        span::{Id, Record},
+        Subscriber,
+    };
+    use tracing_subscriber::{layer::Context, prelude::*, Layer};
 
     use super::*;
 
+    /// A tracing layer which collects all values recorded on spans after they are created.
+    #[derive(Clone, Default)]
+    struct RecordedFields(Arc<Mutex<BTreeMap<String, String>>>);
+
+    impl Visit for RecordedFields {
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_string(), value.to_string());
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(field.name().to_string(), format!("{:?}", value));
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for RecordedFields {
+        fn on_record(&self, _span: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            values.record(&mut self.clone());
+        }
+    }
+
+    async fn record_test_call(result: Result<(), Status>) -> BTreeMap<String, String> {
+        let recorded = RecordedFields::default();
+        let subscriber = tracing_subscriber::registry().with(recorded.clone());
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let span = tracing::info_span!("test", latency_ms = field::Empty, status = field::Empty);
+        let _entered = span.enter();
+        let _ = record_call(async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            result
+        })
+        .await;
+
+        let fields = recorded.0.lock().unwrap().clone();
+        fields
+    }
+
+    #[tokio::test]
+    async fn record_call_success() {
+        let fields = record_test_call(Ok(())).await;
+        assert_eq!(fields.get("status").unwrap(), "OK");
+        assert!(fields.get("latency_ms").unwrap().parse::<u64>().unwrap() >= 10);
+    }
+
+    #[tokio::test]
+    async fn build_credentials_retries() {
+        let attempts = Mutex::new(0);
+        let credentials = build_credentials(CREDENTIALS_RETRIES, Duration::ZERO, || {
+            *attempts.lock().unwrap() += 1;
+            let attempt = *attempts.lock().unwrap();
+            async move {
+                if attempt < 3 {
+                    Err("token endpoint unavailable")
+                } else {
+                    Ok(Credentials::builder()
+                        .no_credentials()
+                        .build()
+                        .await
+                        .unwrap())
+                }
+            }
+        })
+        .await;
+        assert!(credentials.is_ok());
+        assert_eq!(*attempts.lock().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn build_credentials_gives_up() {
+        let attempts = Mutex::new(0);
+        let credentials = build_credentials(CREDENTIALS_RETRIES, Duration::ZERO, || {
+            *attempts.lock().unwrap() += 1;
+            async { Err("token endpoint unavailable") }
+        })
+        .await;
+        assert_eq!(credentials.unwrap_err(), "token endpoint unavailable");
+        assert_eq!(*attempts.lock().unwrap(), CREDENTIALS_RETRIES + 1);
+    }
+
+    #[tokio::test]
+    async fn record_call_error() {
+        let fields = record_test_call(Err(Status::new(Code::Unavailable, "Oh no"))).await;
+        assert_eq!(fields.get("status").unwrap(), "Unavailable");
+        assert!(fields.contains_key("latency_ms"));
+    }
+
+    /// Starts a fake Home Graph server which doesn't respond to any request until `concurrency`
+    /// requests are in progress at once, and then responds successfully to all of them.
+    fn start_fake_server(concurrency: usize) -> SocketAddr {
+        let barrier = Arc::new(Barrier::new(concurrency));
+        let make_service = make_service_fn(move |_| {
+            let barrier = barrier.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_request| {
+                    let barrier = barrier.clone();
+                    async move {
+                        barrier.wait().await;
+                        // An empty response message, followed by an OK status.
+                        let (mut sender, body) = Body::channel();
+                        tokio::spawn(async move {
+                            sender.send_data(Bytes::from_static(&[0; 5])).await?;
+                            let mut trailers = HeaderMap::new();
+                            trailers.insert("grpc-status", HeaderValue::from_static("0"));
+                            sender.send_trailers(trailers).await
+                        });
+                        Response::builder()
+                            .header("content-type", "application/grpc")
+                            .body(body)
+                    }
+                }))
+            }
+        });
+        let server = Server::bind(&SocketAddr::from(([127, 0, 0, 1], 0)))
+            .http2_only(true)
+            .serve(make_service);
+        let address = server.local_addr();
+        tokio::spawn(server);
+        address
+    }
+
+    #[tokio::test]
+    async fn concurrent_reports() {
+        const CONCURRENCY: usize = 5;
+        let address = start_fake_server(CONCURRENCY);
+        let channel = Channel::from_shared(format!("http://{}", address))
+            .unwrap()
+            .connect_lazy();
+        let credentials = Credentials::builder()
+            .no_credentials()
+            .build()
+            .await
+            .unwrap();
+        let client = HomeGraphClient::new(channel, credentials).await;
+
+        // The server won't respond until all reports have been sent, so this would time out if
+        // they were serialised.
+        let reports = (0..CONCURRENCY).map(|i| {
+            let client = client.clone();
+            async move {
+                client
+                    .report_state(
+                        &format!("user{}", i),
+                        "device".to_string(),
+                        response::State::default(),
+                    )
+                    .await
+            }
+        });
+        let results = tokio::time::timeout(Duration::from_secs(5), join_all(reports))
+            .await
+            .expect("Reports were serialised");
+        for result in results {
+            result.unwrap();
+        }
+    }
+
     #[test]
     fn convert_state() {
         let state = response::State {
@@ -0,0 +1,127 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+use super::filter::DeviceFilter;
+use homie_controller::Device;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+/// The Homie devices on a broker which are exposed to Google Home, so that requests don't need to
+/// filter the full device list again unless it has changed.
+#[derive(Debug, Default)]
+pub struct DeviceStateCache(Mutex<Option<CacheEntry>>);
+
+#[derive(Debug)]
+struct CacheEntry {
+    /// The Homie controller's devices from which the filtered devices were built.
+    devices: Arc<HashMap<String, Device>>,
+    filtered: Arc<HashMap<String, Device>>,
+}
+
+impl DeviceStateCache {
+    /// Returns the given devices from the Homie controller after removing any nodes which don't
+    /// match the filter, reusing the previous result if they are the same devices as last time.
+    ///
+    /// The Homie controller replaces its map of devices whenever anything changes, so comparing
+    /// pointers is enough to tell whether the previous result is out of date.
+    pub fn devices(
+        &self,
+        devices: Arc<HashMap<String, Device>>,
+        device_filter: &DeviceFilter,
+    ) -> Arc<HashMap<String, Device>> {
+        let mut cache = self.0.lock().unwrap();
+        if let Some(entry) = &*cache {
+            if Arc::ptr_eq(&entry.devices, &devices) {
+                return entry.filtered.clone();
+            }
+        }
+        let filtered = device_filter.apply(devices.clone());
+        *cache = Some(CacheEntry {
+            devices,
+            filtered: filtered.clone(),
+        });
+        filtered
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use homie_controller::{Node, State};
+
+    fn device(id: &str, node_ids: &[&str]) -> Device {
+        Device {
+            id: id.to_string(),
+            homie_version: "4.0".to_string(),
+            name: None,
+            state: State::Ready,
+            implementation: None,
+            nodes: node_ids
+                .iter()
+                .map(|node_id| {
+                    (
+                        node_id.to_string(),
+                        Node {
+                            id: node_id.to_string(),
+                            name: None,
+                            node_type: None,
+                            properties: HashMap::new(),
+                        },
+                    )
+                })
+                .collect(),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        }
+    }
+
+    #[test]
+    fn refilters_only_on_change() {
+        let cache = DeviceStateCache::default();
+        let filter = DeviceFilter::new(&[], &["*/debug".to_string()]).unwrap();
+        assert!(cache.devices(Arc::default(), &filter).is_empty());
+
+        let devices: Arc<HashMap<_, _>> = Arc::new(
+            [("device".to_string(), device("device", &["light", "debug"]))]
+                .into_iter()
+                .collect(),
+        );
+        let cached = cache.devices(devices.clone(), &filter);
+        assert_eq!(cached.len(), 1);
+        assert_eq!(
+            cached["device"].nodes.keys().collect::<Vec<_>>(),
+            vec!["light"]
+        );
+        // The same devices again give the same filtered map, without filtering them again.
+        assert!(Arc::ptr_eq(&cache.devices(devices, &filter), &cached));
+
+        let devices: HashMap<_, _> = [("other".to_string(), device("other", &["light"]))]
+            .into_iter()
+            .collect();
+        let cached = cache.devices(Arc::new(devices), &filter);
+        assert!(!cached.contains_key("device"));
+        assert!(cached.contains_key("other"));
+    }
+}
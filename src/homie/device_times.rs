@@ -0,0 +1,121 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Tracks a point in time for each Homie device on a broker, such as when it was last heard from.
+#[derive(Debug, Default)]
+pub struct DeviceTimes(Mutex<HashMap<String, Instant>>);
+
+impl DeviceTimes {
+    /// Records the current time for the device with the given ID, replacing any earlier time.
+    pub fn record(&self, device_id: &str) {
+        self.record_at(device_id, Instant::now());
+    }
+
+    /// Records the given time for the device with the given ID, replacing any earlier time.
+    pub fn record_at(&self, device_id: &str, time: Instant) {
+        self.0.lock().unwrap().insert(device_id.to_owned(), time);
+    }
+
+    /// Records the current time for the device with the given ID, unless a time is already
+    /// recorded for it. Returns whether the time was recorded.
+    pub fn record_first(&self, device_id: &str) -> bool {
+        self.record_first_at(device_id, Instant::now())
+    }
+
+    /// Records the given time for the device with the given ID, unless a time is already recorded
+    /// for it. Returns whether the time was recorded.
+    pub fn record_first_at(&self, device_id: &str, time: Instant) -> bool {
+        let mut times = self.0.lock().unwrap();
+        if times.contains_key(device_id) {
+            false
+        } else {
+            times.insert(device_id.to_owned(), time);
+            true
+        }
+    }
+
+    /// Forgets the time recorded for the device with the given ID. Returns whether there was one.
+    pub fn clear(&self, device_id: &str) -> bool {
+        self.0.lock().unwrap().remove(device_id).is_some()
+    }
+
+    /// Returns how long ago the time recorded for the device with the given ID was, if any.
+    pub fn elapsed(&self, device_id: &str) -> Option<Duration> {
+        self.0.lock().unwrap().get(device_id).map(Instant::elapsed)
+    }
+
+    /// Returns whether a time is recorded for the device with the given ID, and it was less than
+    /// the given duration ago.
+    pub fn within(&self, device_id: &str, duration: Duration) -> bool {
+        self.elapsed(device_id)
+            .is_some_and(|elapsed| elapsed < duration)
+    }
+
+    /// Returns whether a time is recorded for the device with the given ID, and it was more than
+    /// the given duration ago.
+    pub fn older_than(&self, device_id: &str, duration: Duration) -> bool {
+        self.elapsed(device_id)
+            .is_some_and(|elapsed| elapsed > duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record() {
+        let times = DeviceTimes::default();
+        let duration = Duration::from_secs(60);
+
+        assert_eq!(times.elapsed("device"), None);
+        assert!(!times.within("device", duration));
+        assert!(!times.older_than("device", duration));
+
+        times.record("device");
+        assert!(times.within("device", duration));
+        assert!(!times.older_than("device", duration));
+
+        times.record_at("device", Instant::now() - 2 * duration);
+        assert!(!times.within("device", duration));
+        assert!(times.older_than("device", duration));
+        assert!(!times.older_than("other", duration));
+
+        // Recording again replaces the earlier time.
+        times.record("device");
+        assert!(times.within("device", duration));
+    }
+
+    #[test]
+    fn record_first() {
+        let times = DeviceTimes::default();
+        let duration = Duration::from_secs(60);
+
+        assert!(times.record_first_at("device", Instant::now() - 2 * duration));
+        assert!(times.older_than("device", duration));
+
+        // Recording again doesn't reset the time.
+        assert!(!times.record_first("device"));
+        assert!(times.older_than("device", duration));
+
+        assert!(times.clear("device"));
+        assert!(!times.clear("device"));
+        assert!(times.record_first("device"));
+        assert!(times.within("device", duration));
+    }
+}
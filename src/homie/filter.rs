@@ -0,0 +1,135 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+//! Filtering of which Homie nodes are exposed to Google Home.
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use homie_controller::Device;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A set of glob patterns matched against `device_id/node_id` to decide which Homie nodes to
+/// expose to Google Home.
+#[derive(Clone, Debug)]
+pub struct DeviceFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl DeviceFilter {
+    /// Compiles the given include and exclude patterns. If `include` is empty then all nodes not
+    /// matching `exclude` are included.
+    ///
+    /// `*` and `?` don't match `/`, so `*/debug` matches the `debug` node of any device.
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, globset::Error> {
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(build_glob_set(include)?)
+        };
+        Ok(Self {
+            include,
+            exclude: build_glob_set(exclude)?,
+        })
+    }
+
+    /// Returns whether the given node should be exposed.
+    pub fn matches(&self, device_id: &str, node_id: &str) -> bool {
+        let id = format!("{}/{}", device_id, node_id);
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(&id))
+            && !self.exclude.is_match(&id)
+    }
+
+    /// Returns whether the filter includes all nodes.
+    pub fn is_empty(&self) -> bool {
+        self.include.is_none() && self.exclude.is_empty()
+    }
+
+    /// Removes all nodes which don't match the filter from the given devices.
+    pub fn apply(&self, devices: Arc<HashMap<String, Device>>) -> Arc<HashMap<String, Device>> {
+        if self.is_empty() {
+            return devices;
+        }
+        let filtered = devices
+            .values()
+            .map(|device| {
+                let mut device = device.clone();
+                device
+                    .nodes
+                    .retain(|node_id, _| self.matches(&device.id, node_id));
+                (device.id.clone(), device)
+            })
+            .collect();
+        Arc::new(filtered)
+    }
+}
+
+fn build_glob_set(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(GlobBuilder::new(pattern).literal_separator(true).build()?);
+    }
+    builder.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_filter(include: &[&str], exclude: &[&str]) -> DeviceFilter {
+        let to_strings =
+            |patterns: &[&str]| -> Vec<String> { patterns.iter().map(|p| p.to_string()).collect() };
+        DeviceFilter::new(&to_strings(include), &to_strings(exclude)).unwrap()
+    }
+
+    #[test]
+    fn empty() {
+        let filter = device_filter(&[], &[]);
+        assert!(filter.is_empty());
+        assert!(filter.matches("device", "node"));
+    }
+
+    #[test]
+    fn star() {
+        let filter = device_filter(&["sensor-*/temperature"], &["*/debug"]);
+        assert!(filter.matches("sensor-kitchen", "temperature"));
+        assert!(!filter.matches("sensor-kitchen", "humidity"));
+        assert!(!filter.matches("light", "temperature"));
+
+        let filter = device_filter(&[], &["*/debug"]);
+        assert!(filter.matches("light", "temperature"));
+        assert!(!filter.matches("light", "debug"));
+        // Patterns must match the whole ID.
+        assert!(filter.matches("light", "debug-extra"));
+    }
+
+    #[test]
+    fn question_mark() {
+        let filter = device_filter(&["light-?/*"], &[]);
+        assert!(filter.matches("light-1", "node"));
+        assert!(!filter.matches("light-10", "node"));
+    }
+
+    #[test]
+    fn character_class() {
+        let filter = device_filter(&[], &["relay-[0-3]/*"]);
+        assert!(!filter.matches("relay-2", "node"));
+        assert!(filter.matches("relay-4", "node"));
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        assert!(DeviceFilter::new(&["light-[".to_string()], &[]).is_err());
+    }
+}
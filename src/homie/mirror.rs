@@ -0,0 +1,133 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+use crate::types::user::Homie;
+use google_smart_home::query::response;
+use homie_controller::HomieController;
+use rumqttc::{AsyncClient, ClientError, QoS};
+
+/// Republishes the state reported to Google Home for each node to an MQTT topic, so that it can be
+/// observed with other MQTT tools.
+#[derive(Clone, Debug)]
+pub struct StateMirror {
+    client: AsyncClient,
+    topic: String,
+    qos: QoS,
+}
+
+impl StateMirror {
+    /// Creates a mirror which publishes with the given client and QoS to the given topic template,
+    /// in which `{device}` and `{node}` are replaced by the Homie device and node IDs.
+    pub fn new(client: AsyncClient, topic: String, qos: QoS) -> Self {
+        Self { client, topic, qos }
+    }
+
+    /// Creates a mirror for the given config, if a mirror topic is configured. This publishes over
+    /// the given controller's MQTT connection with the same QoS, rather than opening another.
+    pub fn from_config(config: &Homie, controller: &HomieController) -> Option<Self> {
+        let topic = config.mirror_topic.as_ref()?;
+        Some(Self::new(
+            controller.mqtt_client().clone(),
+            topic.to_owned(),
+            controller.qos(),
+        ))
+    }
+
+    /// Returns the topic to which to publish the state of the given node.
+    fn topic(&self, device_id: &str, node_id: &str) -> String {
+        self.topic
+            .replace("{device}", device_id)
+            .replace("{node}", node_id)
+    }
+
+    /// Publishes the given state of the given node as retained JSON.
+    pub async fn publish(
+        &self,
+        device_id: &str,
+        node_id: &str,
+        state: &response::State,
+    ) -> Result<(), ClientError> {
+        let payload = serde_json::to_vec(state).expect("State should serialize to JSON");
+        self.client
+            .publish(self.topic(device_id, node_id), self.qos, true, payload)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rumqttc::{MqttOptions, Request};
+
+    #[tokio::test]
+    async fn only_created_when_configured() {
+        let config = |mirror_topic: &str| -> Homie {
+            toml::from_str(&format!(
+                r#"
+                host = "localhost"
+                port = 1883
+                client-id = "homieflow"
+                reconnect-interval-seconds = 5
+                {}
+                "#,
+                mirror_topic
+            ))
+            .unwrap()
+        };
+
+        let (controller, _event_loop) = HomieController::new(
+            MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            QoS::ExactlyOnce,
+        );
+
+        assert!(StateMirror::from_config(&config(""), &controller).is_none());
+
+        let mirror = StateMirror::from_config(
+            &config("mirror-topic = \"mirror/{device}/{node}\""),
+            &controller,
+        )
+        .unwrap();
+        assert_eq!(mirror.topic("device", "node"), "mirror/device/node");
+        assert_eq!(mirror.qos, QoS::ExactlyOnce);
+    }
+
+    #[tokio::test]
+    async fn publish_state() {
+        let (client, event_loop) =
+            AsyncClient::new(MqttOptions::new("test", "localhost", 1883), 10);
+        let mirror = StateMirror::new(
+            client,
+            "homieflow/state/{device}/{node}".to_string(),
+            QoS::ExactlyOnce,
+        );
+
+        let state = response::State {
+            online: true,
+            on: Some(true),
+            ..Default::default()
+        };
+        mirror.publish("device", "node", &state).await.unwrap();
+
+        match event_loop.requests_rx.try_recv().unwrap() {
+            Request::Publish(publish) => {
+                assert_eq!(publish.topic, "homieflow/state/device/node");
+                assert!(publish.retain);
+                assert_eq!(publish.qos, QoS::ExactlyOnce);
+                let published: serde_json::Value =
+                    serde_json::from_slice(&publish.payload).unwrap();
+                assert_eq!(published, serde_json::to_value(&state).unwrap());
+            }
+            request => panic!("Unexpected request {:?}", request),
+        }
+    }
+}
@@ -10,20 +10,40 @@
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU General Public License for more details.
 
+pub mod device_cache;
+pub mod device_times;
+pub mod filter;
+pub mod mirror;
 pub mod state;
 
-use self::state::homie_node_to_state;
+use self::device_times::DeviceTimes;
+use self::filter::DeviceFilter;
+use self::mirror::StateMirror;
+use self::state::{
+    homie_node_to_state, is_online, is_online_with_grace, is_stale, offline_grace_remaining,
+    update_offline_since,
+};
 use crate::{
+    config::server::Devices,
     homegraph::HomeGraphClient,
     ratelimit::RateLimiter,
-    types::user::{self, Homie},
+    types::user::{self, Homie, User},
 };
+use google_smart_home::query::response::State;
 use homie_controller::{Device, Event, HomieController, HomieEventLoop, Node, PollError};
 use rumqttc::{ClientConfig, ConnectionError, MqttOptions, TlsConfiguration, Transport};
-use std::{collections::HashMap, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    io,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use tokio::{
     task::{self, JoinHandle},
-    time::sleep,
+    time::{sleep, timeout},
 };
 
 const KEEP_ALIVE: Duration = Duration::from_secs(5);
@@ -31,12 +51,23 @@ const KEEP_ALIVE: Duration = Duration::from_secs(5);
 pub fn get_mqtt_options(
     config: &Homie,
     tls_client_config: Option<Arc<ClientConfig>>,
-) -> MqttOptions {
+) -> io::Result<MqttOptions> {
     let mut mqtt_options = MqttOptions::new(&config.client_id, &config.host, config.port);
     mqtt_options.set_keep_alive(KEEP_ALIVE);
 
-    if let (Some(username), Some(password)) = (&config.username, &config.password) {
-        mqtt_options.set_credentials(username, password);
+    if let Some(connect_timeout_seconds) = config.connect_timeout_seconds {
+        mqtt_options.set_connection_timeout(connect_timeout_seconds);
+    }
+
+    if let Some(inflight) = config.inflight {
+        mqtt_options.set_inflight(inflight);
+    }
+    if let Some(request_channel_capacity) = config.request_channel_capacity {
+        mqtt_options.set_request_channel_capacity(request_channel_capacity);
+    }
+
+    if let (Some(username), Some(password)) = (&config.username, config.password()?) {
+        mqtt_options.set_credentials(username, &password);
     }
 
     if let Some(client_config) = tls_client_config {
@@ -45,74 +76,364 @@ pub fn get_mqtt_options(
         )));
     }
 
-    mqtt_options
+    Ok(mqtt_options)
+}
+
+/// A connection to an MQTT broker, with a Homie controller for each base topic used on it, and the
+/// users whose devices it provides.
+pub struct Connection {
+    pub controllers: Vec<Arc<HomieController>>,
+    pub event_loop: HomieEventLoop,
+    /// The users sharing the connection, with their Homie configs. These all have the same MQTT
+    /// connection options, but may use different base topics.
+    pub users: Vec<(user::ID, Homie)>,
+}
+
+impl Connection {
+    /// Returns the controller for the given Homie base topic.
+    pub fn controller(&self, homie_prefix: &str) -> &Arc<HomieController> {
+        self.controllers
+            .iter()
+            .find(|controller| controller.base_topic() == homie_prefix)
+            .expect("No controller for Homie prefix")
+    }
+}
+
+/// Creates a Homie controller for each of the given users who has a Homie config.
+///
+/// Users who set `share-connection` and use the same broker host, port and username share a
+/// single MQTT connection, rather than each opening their own. Users with the same `homie-prefix`
+/// also share a controller, while each other prefix gets its own controller on the connection.
+pub fn connect_users(
+    users: &[User],
+    tls_client_config: &Arc<ClientConfig>,
+) -> io::Result<Vec<Connection>> {
+    let mut groups: Vec<Vec<(user::ID, Homie)>> = Vec::new();
+    let mut shared: HashMap<_, usize> = HashMap::new();
+    for user in users {
+        if let Some(homie_config) = &user.homie {
+            if let Some(key) = homie_config.connection_key() {
+                if let Some(&index) = shared.get(&key) {
+                    groups[index].push((user.id, homie_config.clone()));
+                    continue;
+                }
+                shared.insert(key, groups.len());
+            }
+            groups.push(vec![(user.id, homie_config.clone())]);
+        }
+    }
+
+    groups
+        .into_iter()
+        .map(|users| {
+            let homie_config = &users[0].1;
+            let mqtt_options = get_mqtt_options(
+                homie_config,
+                homie_config.use_tls.then(|| tls_client_config.clone()),
+            )?;
+            let mut base_topics: Vec<&str> = Vec::new();
+            for (_, homie_config) in &users {
+                if !base_topics.contains(&homie_config.homie_prefix.as_str()) {
+                    base_topics.push(&homie_config.homie_prefix);
+                }
+            }
+            let (controllers, event_loop) =
+                HomieController::new_shared(mqtt_options, &base_topics, homie_config.qos());
+            Ok(Connection {
+                controllers: controllers.into_iter().map(Arc::new).collect(),
+                event_loop,
+                users,
+            })
+        })
+        .collect()
+}
+
+/// A user whose devices a Homie poller provides, along with the per-user state it should keep up
+/// to date.
+pub struct PollerUser {
+    pub user_id: user::ID,
+    pub agent_user_id: String,
+    pub homie_config: Homie,
+    pub home_graph_client: Option<HomeGraphClient>,
+    pub state_mirror: Option<StateMirror>,
+    pub request_sync_rate_limit: Duration,
+    pub last_seen: Arc<DeviceTimes>,
+    pub offline_since: Arc<DeviceTimes>,
+    pub mqtt_connected: Arc<AtomicBool>,
 }
 
+/// Spawns a task to poll the given connection, which may be shared between several users, and
+/// handle its events for each of them.
 pub fn spawn_homie_poller(
-    controller: Arc<HomieController>,
+    controllers: Vec<Arc<HomieController>>,
     event_loop: HomieEventLoop,
-    home_graph_client: Option<HomeGraphClient>,
-    user_id: user::ID,
-    reconnect_interval: Duration,
-    request_sync_rate_limit: Duration,
+    users: Vec<PollerUser>,
+    devices_config: Devices,
+    device_filter: Arc<DeviceFilter>,
 ) -> JoinHandle<()> {
     task::spawn(homie_poller(
-        controller,
+        controllers,
         event_loop,
-        home_graph_client,
-        user_id,
-        reconnect_interval,
-        request_sync_rate_limit,
+        users,
+        devices_config,
+        device_filter,
     ))
 }
 
 async fn homie_poller(
-    controller: Arc<HomieController>,
+    controllers: Vec<Arc<HomieController>>,
     mut event_loop: HomieEventLoop,
-    mut home_graph_client: Option<HomeGraphClient>,
-    user_id: user::ID,
-    reconnect_interval: Duration,
-    request_sync_rate_limit: Duration,
+    users: Vec<PollerUser>,
+    devices_config: Devices,
+    device_filter: Arc<DeviceFilter>,
 ) {
-    let home_graph_client_clone = home_graph_client.clone();
-    let request_sync = RateLimiter::new(request_sync_rate_limit, move || {
-        Box::pin(request_sync(user_id, home_graph_client_clone.clone()))
-    });
+    // The connection options are the same for all users sharing a connection.
+    let homie_config = users[0].homie_config.clone();
+    let base_topics = controllers
+        .iter()
+        .map(|controller| format!("'{}'", controller.base_topic()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut users: Vec<_> = users
+        .into_iter()
+        .map(|user| {
+            let home_graph_client = user.home_graph_client.clone();
+            let agent_user_id = user.agent_user_id.clone();
+            let request_sync = RateLimiter::new(user.request_sync_rate_limit, move || {
+                Box::pin(request_sync(
+                    agent_user_id.clone(),
+                    home_graph_client.clone(),
+                ))
+            });
+            let controller_index = controllers
+                .iter()
+                .position(|controller| controller.base_topic() == user.homie_config.homie_prefix)
+                .expect("No controller for Homie prefix");
+            (user, request_sync, 0, controller_index, HashMap::new())
+        })
+        .collect();
 
+    let mut connected = false;
+    let mut failed_attempts = 0;
     loop {
-        match controller.poll(&mut event_loop).await {
-            Ok(Some(event)) => {
-                handle_homie_event(
-                    controller.as_ref(),
-                    &request_sync,
-                    &mut home_graph_client,
-                    user_id,
-                    event,
-                )
-                .await;
+        match poll(&controllers, &mut event_loop, connected, &homie_config).await {
+            Ok(events) => {
+                if !events.is_empty() {
+                    failed_attempts = 0;
+                }
+                if !connected
+                    && events
+                        .iter()
+                        .any(|(_, event)| matches!(event, Event::Connected))
+                {
+                    tracing::info!(
+                        "Connected to MQTT broker {}:{} for base topics {}",
+                        homie_config.host,
+                        homie_config.port,
+                        base_topics
+                    );
+                    connected = true;
+                    for (user, _, _, _, _) in &users {
+                        user.mqtt_connected.store(true, Ordering::Relaxed);
+                    }
+                }
+                for (index, event) in events {
+                    let controller = &controllers[index];
+                    for (user, request_sync, device_count, _, pending_offline_reports) in users
+                        .iter_mut()
+                        .filter(|(_, _, _, controller_index, _)| *controller_index == index)
+                    {
+                        let mut availability_changed = false;
+                        if let Some(device_id) = event_device_id(&event) {
+                            user.last_seen.record(device_id);
+                            if let Some(device) = controller.devices().get(device_id) {
+                                availability_changed = update_offline_since(
+                                    &user.offline_since,
+                                    device_id,
+                                    is_online(device, &devices_config),
+                                );
+                            }
+                            let new_device_count = online_device_count(
+                                &controller.devices(),
+                                &devices_config,
+                                &device_filter,
+                            );
+                            if new_device_count != *device_count {
+                                tracing::info!(
+                                    user_id = %user.user_id,
+                                    device_count = new_device_count,
+                                    "Online device count changed from {} to {}",
+                                    device_count,
+                                    new_device_count
+                                );
+                                *device_count = new_device_count;
+                            }
+                        }
+                        handle_homie_event(
+                            controller.as_ref(),
+                            request_sync,
+                            &mut user.home_graph_client,
+                            user.state_mirror.as_ref(),
+                            &user.agent_user_id,
+                            &user.homie_config,
+                            &devices_config,
+                            &device_filter,
+                            &user.last_seen,
+                            &user.offline_since,
+                            pending_offline_reports,
+                            availability_changed,
+                            &event,
+                        )
+                        .await;
+                    }
+                }
             }
-            Ok(None) => {}
             Err(e) => {
+                if connected && matches!(e, PollError::Connection(_)) {
+                    tracing::warn!(
+                        "Disconnected from MQTT broker {}:{} for base topics {}",
+                        homie_config.host,
+                        homie_config.port,
+                        base_topics
+                    );
+                    connected = false;
+                    for (user, _, _, _, _) in &users {
+                        user.mqtt_connected.store(false, Ordering::Relaxed);
+                    }
+                }
                 tracing::error!(
-                    "Failed to poll HomieController for base topic '{}': {}",
-                    controller.base_topic(),
+                    "Failed to poll HomieController for base topics {}: {}",
+                    base_topics,
                     e
                 );
-                if let PollError::Connection(ConnectionError::Io(_)) = e {
-                    sleep(reconnect_interval).await;
+                if matches!(e, PollError::Connection(_)) {
+                    failed_attempts += 1;
+                    if homie_config
+                        .max_reconnect_attempts
+                        .is_some_and(|max_attempts| failed_attempts >= max_attempts)
+                    {
+                        tracing::error!(
+                            "Giving up on MQTT broker {}:{} for base topics {} after {} failed \
+                             connection attempts",
+                            homie_config.host,
+                            homie_config.port,
+                            base_topics,
+                            failed_attempts
+                        );
+                        return;
+                    }
                 }
+                if let PollError::Connection(ConnectionError::Io(_) | ConnectionError::Timeout(_)) =
+                    e
+                {
+                    sleep(homie_config.reconnect_interval).await;
+                }
+            }
+        }
+    }
+}
+
+/// Spawns a task to report the state of all of the controller's nodes to Home Graph after the
+/// given delay.
+#[allow(clippy::too_many_arguments)]
+pub fn spawn_startup_report(
+    controller: Arc<HomieController>,
+    home_graph_client: HomeGraphClient,
+    state_mirror: Option<StateMirror>,
+    agent_user_id: String,
+    homie_config: Homie,
+    devices_config: Devices,
+    device_filter: Arc<DeviceFilter>,
+    last_seen: Arc<DeviceTimes>,
+    offline_since: Arc<DeviceTimes>,
+    delay: Duration,
+) -> JoinHandle<()> {
+    task::spawn(async move {
+        sleep(delay).await;
+        let devices = device_filter.apply(controller.devices());
+        tracing::info!(
+            "Reporting state of {} devices for {} after startup",
+            devices.len(),
+            agent_user_id
+        );
+        for device in devices.values() {
+            for node_id in device.nodes.keys() {
+                node_state_changed(
+                    &controller,
+                    Some(&home_graph_client),
+                    state_mirror.as_ref(),
+                    &agent_user_id,
+                    &devices_config,
+                    &last_seen,
+                    &offline_since,
+                    homie_config.device_id_prefix.as_deref(),
+                    &device.id,
+                    node_id,
+                )
+                .await;
             }
         }
+    })
+}
+
+/// Polls the connection for the next events. If not yet connected and a connection timeout is
+/// configured then this gives up after the timeout, as the MQTT client doesn't otherwise time out
+/// while establishing the underlying network connection.
+async fn poll(
+    controllers: &[Arc<HomieController>],
+    event_loop: &mut HomieEventLoop,
+    connected: bool,
+    homie_config: &Homie,
+) -> Result<Vec<(usize, Event)>, PollError> {
+    match homie_config.connect_timeout() {
+        Some(connect_timeout) if !connected => timeout(
+            connect_timeout,
+            HomieController::poll_shared(controllers, event_loop),
+        )
+        .await
+        .unwrap_or_else(|elapsed| Err(ConnectionError::Timeout(elapsed).into())),
+        _ => HomieController::poll_shared(controllers, event_loop).await,
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_homie_event(
     controller: &HomieController,
     request_sync: &RateLimiter,
     home_graph_client: &mut Option<HomeGraphClient>,
-    user_id: user::ID,
-    event: Event,
+    state_mirror: Option<&StateMirror>,
+    agent_user_id: &str,
+    homie_config: &Homie,
+    devices_config: &Devices,
+    device_filter: &Arc<DeviceFilter>,
+    last_seen: &Arc<DeviceTimes>,
+    offline_since: &Arc<DeviceTimes>,
+    pending_offline_reports: &mut HashMap<String, JoinHandle<()>>,
+    availability_changed: bool,
+    event: &Event,
 ) {
+    // Let Google know promptly when a device comes online or goes offline, rather than waiting for
+    // the next QUERY.
+    if let Event::DeviceUpdated { device_id, .. } = event {
+        if availability_changed && (home_graph_client.is_some() || state_mirror.is_some()) {
+            if let Some(device) = controller.devices().get(device_id) {
+                report_availability(
+                    device,
+                    home_graph_client.as_ref(),
+                    state_mirror,
+                    agent_user_id,
+                    homie_config.device_id_prefix.as_deref(),
+                    devices_config,
+                    device_filter,
+                    last_seen,
+                    offline_since,
+                    pending_offline_reports,
+                )
+                .await;
+            }
+        }
+    }
+
     match event {
         Event::DeviceUpdated {
             device_id: _,
@@ -142,43 +463,260 @@ async fn handle_homie_event(
             }
         }
         Event::PropertyValueChanged {
-            ref device_id,
-            ref node_id,
+            device_id,
+            node_id,
             property_id: _,
             value: _,
             fresh: true,
-        } => {
-            if let Some(home_graph_client) = home_graph_client {
-                node_state_changed(controller, home_graph_client, user_id, device_id, node_id)
-                    .await;
+        } if device_filter.matches(device_id, node_id) => {
+            if home_graph_client.is_some() || state_mirror.is_some() {
+                node_state_changed(
+                    controller,
+                    home_graph_client.as_ref(),
+                    state_mirror,
+                    agent_user_id,
+                    devices_config,
+                    last_seen,
+                    offline_since,
+                    homie_config.device_id_prefix.as_deref(),
+                    device_id,
+                    node_id,
+                )
+                .await;
             }
         }
         _ => tracing::trace!("Homie event {:?}", event),
     }
 }
 
-async fn request_sync(user_id: user::ID, home_graph_client: Option<HomeGraphClient>) {
+/// Returns the number of devices which have at least one node matching the filter and are reported
+/// to Google Home as online.
+fn online_device_count(
+    devices: &HashMap<String, Device>,
+    devices_config: &Devices,
+    device_filter: &DeviceFilter,
+) -> usize {
+    devices
+        .values()
+        .filter(|device| {
+            device
+                .nodes
+                .keys()
+                .any(|node_id| device_filter.matches(&device.id, node_id))
+                && is_online(device, devices_config)
+        })
+        .count()
+}
+
+/// Returns the ID of the device which the given event is about, if any.
+fn event_device_id(event: &Event) -> Option<&str> {
+    match event {
+        Event::DeviceUpdated { device_id, .. }
+        | Event::NodeUpdated { device_id, .. }
+        | Event::PropertyUpdated { device_id, .. }
+        | Event::PropertyValueChanged { device_id, .. } => Some(device_id),
+        _ => None,
+    }
+}
+
+async fn request_sync(agent_user_id: String, home_graph_client: Option<HomeGraphClient>) {
     if let Some(home_graph_client) = home_graph_client {
-        if let Err(e) = home_graph_client.request_sync(user_id).await {
-            tracing::error!("Error requesting sync for {}: {:?}", user_id, e);
+        if let Err(e) = home_graph_client.request_sync(&agent_user_id).await {
+            tracing::error!("Error requesting sync for {}: {:?}", agent_user_id, e);
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn node_state_changed(
     controller: &HomieController,
-    home_graph_client: &mut HomeGraphClient,
-    user_id: user::ID,
+    home_graph_client: Option<&HomeGraphClient>,
+    state_mirror: Option<&StateMirror>,
+    agent_user_id: &str,
+    devices_config: &Devices,
+    last_seen: &DeviceTimes,
+    offline_since: &DeviceTimes,
+    device_id_prefix: Option<&str>,
     device_id: &str,
     node_id: &str,
 ) {
     if let Some((device, node)) = get_homie_node(&controller.devices(), device_id, node_id) {
-        let online = device.state == homie_controller::State::Ready
-            || device.state == homie_controller::State::Sleeping;
-        let state = homie_node_to_state(node, online);
+        let state = node_report_state(
+            device,
+            node,
+            devices_config,
+            Some(last_seen),
+            Some(offline_since),
+        );
+        send_node_state(
+            home_graph_client,
+            state_mirror,
+            agent_user_id,
+            device_id_prefix,
+            device_id,
+            node_id,
+            state,
+        )
+        .await;
+    }
+}
+
+/// Reports the state of the given device's nodes after it has come online or gone offline, cancelling
+/// any report already scheduled for it.
+///
+/// If the device is still within its offline grace period then it is still reported online until
+/// that ends, so instead of reporting now this schedules a report for when the grace period ends.
+#[allow(clippy::too_many_arguments)]
+async fn report_availability(
+    device: &Device,
+    home_graph_client: Option<&HomeGraphClient>,
+    state_mirror: Option<&StateMirror>,
+    agent_user_id: &str,
+    device_id_prefix: Option<&str>,
+    devices_config: &Devices,
+    device_filter: &Arc<DeviceFilter>,
+    last_seen: &Arc<DeviceTimes>,
+    offline_since: &Arc<DeviceTimes>,
+    pending_offline_reports: &mut HashMap<String, JoinHandle<()>>,
+) {
+    if let Some(pending) = pending_offline_reports.remove(&device.id) {
+        pending.abort();
+    }
+
+    if let Some(remaining) = offline_grace_remaining(device, devices_config, offline_since) {
+        let device = device.clone();
+        let home_graph_client = home_graph_client.cloned();
+        let state_mirror = state_mirror.cloned();
+        let agent_user_id = agent_user_id.to_owned();
+        let device_id_prefix = device_id_prefix.map(ToOwned::to_owned);
+        let devices_config = devices_config.clone();
+        let device_filter = device_filter.clone();
+        let last_seen = last_seen.clone();
+        let offline_since = offline_since.clone();
+        pending_offline_reports.insert(
+            device.id.clone(),
+            task::spawn(async move {
+                sleep(remaining).await;
+                send_availability_states(
+                    &device,
+                    home_graph_client.as_ref(),
+                    state_mirror.as_ref(),
+                    &agent_user_id,
+                    device_id_prefix.as_deref(),
+                    &devices_config,
+                    &device_filter,
+                    &last_seen,
+                    &offline_since,
+                )
+                .await;
+            }),
+        );
+    } else {
+        send_availability_states(
+            device,
+            home_graph_client,
+            state_mirror,
+            agent_user_id,
+            device_id_prefix,
+            devices_config,
+            device_filter,
+            last_seen,
+            offline_since,
+        )
+        .await;
+    }
+}
+
+/// Reports the current state of each of the given device's nodes which are exposed to Google Home.
+#[allow(clippy::too_many_arguments)]
+async fn send_availability_states(
+    device: &Device,
+    home_graph_client: Option<&HomeGraphClient>,
+    state_mirror: Option<&StateMirror>,
+    agent_user_id: &str,
+    device_id_prefix: Option<&str>,
+    devices_config: &Devices,
+    device_filter: &DeviceFilter,
+    last_seen: &DeviceTimes,
+    offline_since: &DeviceTimes,
+) {
+    for (node_id, state) in availability_report_states(
+        device,
+        devices_config,
+        device_filter,
+        last_seen,
+        offline_since,
+    ) {
+        send_node_state(
+            home_graph_client,
+            state_mirror,
+            agent_user_id,
+            device_id_prefix,
+            &device.id,
+            &node_id,
+            state,
+        )
+        .await;
+    }
+}
+
+/// Returns the state to report for each of the given device's nodes which are exposed to Google
+/// Home, after the device has come online or gone offline.
+fn availability_report_states(
+    device: &Device,
+    devices_config: &Devices,
+    device_filter: &DeviceFilter,
+    last_seen: &DeviceTimes,
+    offline_since: &DeviceTimes,
+) -> Vec<(String, State)> {
+    device
+        .nodes
+        .values()
+        .filter(|node| device_filter.matches(&device.id, &node.id))
+        .map(|node| {
+            (
+                node.id.clone(),
+                node_report_state(
+                    device,
+                    node,
+                    devices_config,
+                    Some(last_seen),
+                    Some(offline_since),
+                ),
+            )
+        })
+        .collect()
+}
+
+/// Publishes the given state of a Homie node to the state mirror and reports it to Home Graph, as
+/// applicable.
+async fn send_node_state(
+    home_graph_client: Option<&HomeGraphClient>,
+    state_mirror: Option<&StateMirror>,
+    agent_user_id: &str,
+    device_id_prefix: Option<&str>,
+    device_id: &str,
+    node_id: &str,
+    state: State,
+) {
+    if let Some(state_mirror) = state_mirror {
+        if let Err(e) = state_mirror.publish(device_id, node_id, &state).await {
+            tracing::error!(
+                "Error mirroring state of {}/{}: {:?}",
+                device_id,
+                node_id,
+                e
+            );
+        }
+    }
 
+    if let Some(home_graph_client) = home_graph_client {
         if let Err(e) = home_graph_client
-            .report_state(user_id, format!("{}/{}", device_id, node_id), state.clone())
+            .report_state(
+                agent_user_id,
+                google_device_id(device_id_prefix, device_id, node_id),
+                state.clone(),
+            )
             .await
         {
             tracing::error!(
@@ -192,6 +730,36 @@ async fn node_state_changed(
     }
 }
 
+/// Returns the state of the given Homie node to report to Home Graph.
+///
+/// This uses the same mapping and online checks as QUERY responses, so that the state cached by
+/// Google matches what it gets when it queries.
+pub fn node_report_state(
+    device: &Device,
+    node: &Node,
+    devices_config: &Devices,
+    last_seen: Option<&DeviceTimes>,
+    offline_since: Option<&DeviceTimes>,
+) -> State {
+    homie_node_to_state(
+        device,
+        node,
+        is_online_with_grace(device, devices_config, offline_since)
+            && !is_stale(device, devices_config, last_seen),
+        devices_config,
+    )
+}
+
+/// Returns the Google Home device ID to use for the given Homie device and node ID, with the given
+/// prefix if any.
+pub fn google_device_id(device_id_prefix: Option<&str>, device_id: &str, node_id: &str) -> String {
+    if let Some(prefix) = device_id_prefix {
+        format!("{}/{}/{}", prefix, device_id, node_id)
+    } else {
+        format!("{}/{}", device_id, node_id)
+    }
+}
+
 /// Given a Homie device and node ID, looks up the corresponding Homie node (if any).
 pub fn get_homie_node<'a>(
     devices: &'a HashMap<String, Device>,
@@ -205,3 +773,519 @@ pub fn get_homie_node<'a>(
     }
     None
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use homie_controller::State;
+    use rumqttc::{AsyncClient, QoS, Request};
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn shared_connection() {
+        let user = |id: &str, extra: &str| -> User {
+            toml::from_str(&format!(
+                r#"
+                id = "{}"
+                email = "{}@example.com"
+
+                [homie]
+                host = "localhost"
+                client-id = "homieflow"
+                reconnect-interval-seconds = 5
+                {}
+                "#,
+                id, id, extra
+            ))
+            .unwrap()
+        };
+        let a = user(
+            "861ccceaa3e349138ce2498768dbfe09",
+            "port = 1883\nshare-connection = true",
+        );
+        let b = user(
+            "bd7feab5033940e296ed7fcdc700ba65",
+            "port = 1883\nshare-connection = true",
+        );
+        let c = user("5ca5d0d5ab284b14b1b3e1b5a0b9f1a8", "port = 1883");
+        let d = user(
+            "0b6f2f0e3c5a4f6e9d8c7b6a59483726",
+            "port = 1884\nshare-connection = true",
+        );
+
+        let connections = connect_users(
+            &[a.clone(), b.clone(), c.clone(), d.clone()],
+            &Arc::new(ClientConfig::new()),
+        )
+        .unwrap();
+        let user_ids: Vec<Vec<user::ID>> = connections
+            .iter()
+            .map(|connection| connection.users.iter().map(|(id, _)| *id).collect())
+            .collect();
+        assert_eq!(user_ids, vec![vec![a.id, b.id], vec![c.id], vec![d.id]]);
+        assert_eq!(connections[0].controllers.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn shared_connection_different_prefixes() {
+        let user = |id: &str, homie_prefix: &str| -> User {
+            toml::from_str(&format!(
+                r#"
+                id = "{}"
+                email = "{}@example.com"
+
+                [homie]
+                host = "localhost"
+                port = 1883
+                client-id = "homieflow"
+                reconnect-interval-seconds = 5
+                homie-prefix = "{}"
+                share-connection = true
+                "#,
+                id, id, homie_prefix
+            ))
+            .unwrap()
+        };
+        let a = user("861ccceaa3e349138ce2498768dbfe09", "homie");
+        let b = user("bd7feab5033940e296ed7fcdc700ba65", "other");
+        let c = user("5ca5d0d5ab284b14b1b3e1b5a0b9f1a8", "homie");
+
+        let connections = connect_users(
+            &[a.clone(), b.clone(), c.clone()],
+            &Arc::new(ClientConfig::new()),
+        )
+        .unwrap();
+        assert_eq!(connections.len(), 1);
+        let connection = &connections[0];
+        assert_eq!(connection.users.len(), 3);
+        let base_topics: Vec<&str> = connection
+            .controllers
+            .iter()
+            .map(|controller| controller.base_topic())
+            .collect();
+        assert_eq!(base_topics, vec!["homie", "other"]);
+        assert_eq!(connection.controller("other").base_topic(), "other");
+        assert!(Arc::ptr_eq(
+            connection.controller(&a.homie.unwrap().homie_prefix),
+            connection.controller(&c.homie.unwrap().homie_prefix)
+        ));
+    }
+
+    #[tokio::test]
+    async fn shared_connection_mirrors() {
+        let user = |id: &str, homie_prefix: &str| -> User {
+            toml::from_str(&format!(
+                r#"
+                id = "{}"
+                email = "{}@example.com"
+
+                [homie]
+                host = "localhost"
+                port = 1883
+                client-id = "homieflow"
+                reconnect-interval-seconds = 5
+                homie-prefix = "{}"
+                share-connection = true
+                mirror-topic = "{}/state/{{device}}/{{node}}"
+                "#,
+                id, id, homie_prefix, homie_prefix
+            ))
+            .unwrap()
+        };
+        let a = user("861ccceaa3e349138ce2498768dbfe09", "homie");
+        let b = user("bd7feab5033940e296ed7fcdc700ba65", "other");
+
+        let mut connections = connect_users(&[a, b], &Arc::new(ClientConfig::new())).unwrap();
+        assert_eq!(connections.len(), 1);
+        let connection = connections.remove(0);
+        let mirrors: Vec<StateMirror> = connection
+            .users
+            .iter()
+            .map(|(_, homie_config)| {
+                StateMirror::from_config(
+                    homie_config,
+                    connection.controller(&homie_config.homie_prefix),
+                )
+                .unwrap()
+            })
+            .collect();
+        let state = google_smart_home::query::response::State::default();
+
+        // Both mirrors publish over the shared connection rather than opening their own, which
+        // would have the same client ID, so publishing stops working once its event loop is gone.
+        for mirror in &mirrors {
+            mirror.publish("device", "node", &state).await.unwrap();
+        }
+        drop(connection);
+        for mirror in &mirrors {
+            assert!(mirror.publish("device", "node", &state).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn poller_gives_up() {
+        // Find a port with nothing listening on it.
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let homie_config: Homie = toml::from_str(&format!(
+            r#"
+            host = "127.0.0.1"
+            port = {}
+            client-id = "homieflow"
+            reconnect-interval-seconds = 0
+            max-reconnect-attempts = 3
+            "#,
+            port
+        ))
+        .unwrap();
+        let (controllers, event_loop) = HomieController::new_shared(
+            get_mqtt_options(&homie_config, None).unwrap(),
+            &[&homie_config.homie_prefix],
+            homie_config.qos(),
+        );
+        let user = PollerUser {
+            user_id: user::ID::from_u128(1),
+            agent_user_id: "agent".to_string(),
+            homie_config,
+            home_graph_client: None,
+            state_mirror: None,
+            request_sync_rate_limit: Duration::from_secs(1000),
+            last_seen: Arc::default(),
+            offline_since: Arc::default(),
+            mqtt_connected: Arc::default(),
+        };
+
+        let handle = spawn_homie_poller(
+            controllers.into_iter().map(Arc::new).collect(),
+            event_loop,
+            vec![user],
+            Devices::default(),
+            Arc::new(DeviceFilter::new(&[], &[]).unwrap()),
+        );
+        timeout(Duration::from_secs(10), handle)
+            .await
+            .expect("Poller didn't give up")
+            .unwrap();
+    }
+
+    #[test]
+    fn mqtt_options_inflight_and_capacity() {
+        let config = |extra: &str| -> Homie {
+            toml::from_str(&format!(
+                r#"
+                host = "localhost"
+                port = 1883
+                client-id = "homieflow"
+                reconnect-interval-seconds = 5
+                {}
+                "#,
+                extra
+            ))
+            .unwrap()
+        };
+
+        let defaults = MqttOptions::new("homieflow", "localhost", 1883);
+        let mqtt_options = get_mqtt_options(&config(""), None).unwrap();
+        assert_eq!(mqtt_options.inflight(), defaults.inflight());
+        assert_eq!(
+            mqtt_options.request_channel_capacity(),
+            defaults.request_channel_capacity()
+        );
+
+        let mqtt_options = get_mqtt_options(
+            &config("inflight = 500\nrequest-channel-capacity = 1000"),
+            None,
+        )
+        .unwrap();
+        assert_eq!(mqtt_options.inflight(), 500);
+        assert_eq!(mqtt_options.request_channel_capacity(), 1000);
+    }
+
+    fn device(id: &str, state: State, node_ids: &[&str]) -> (String, Device) {
+        let nodes = node_ids
+            .iter()
+            .map(|node_id| {
+                (
+                    node_id.to_string(),
+                    Node {
+                        id: node_id.to_string(),
+                        name: None,
+                        node_type: None,
+                        properties: HashMap::new(),
+                    },
+                )
+            })
+            .collect();
+        (
+            id.to_string(),
+            Device {
+                id: id.to_string(),
+                homie_version: "4.0".to_string(),
+                name: None,
+                state,
+                implementation: None,
+                nodes,
+                extensions: vec![],
+                local_ip: None,
+                mac: None,
+                firmware_name: None,
+                firmware_version: None,
+                stats_interval: None,
+                stats_uptime: None,
+                stats_signal: None,
+                stats_cputemp: None,
+                stats_cpuload: None,
+                stats_battery: None,
+                stats_freeheap: None,
+                stats_supply: None,
+            },
+        )
+    }
+
+    #[test]
+    fn connect_timeout() {
+        let config: Homie = toml::from_str(
+            r#"
+            host = "localhost"
+            port = 1883
+            client-id = "homieflow"
+            reconnect-interval-seconds = 5
+            connect-timeout-seconds = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            get_mqtt_options(&config, None)
+                .unwrap()
+                .connection_timeout(),
+            2
+        );
+    }
+
+    #[test]
+    fn password_file() {
+        let config: Homie = toml::from_str(concat!(
+            r#"
+            host = "localhost"
+            port = 1883
+            client-id = "homieflow"
+            reconnect-interval-seconds = 5
+            username = "user"
+            password-file = ""#,
+            env!("CARGO_MANIFEST_DIR"),
+            r#"/testdata/mqtt_password.txt"
+            "#,
+        ))
+        .unwrap();
+        assert_eq!(
+            get_mqtt_options(&config, None).unwrap().credentials(),
+            Some(("user".to_string(), "secret".to_string()))
+        );
+    }
+
+    #[test]
+    fn password_file_missing() {
+        let config: Homie = toml::from_str(
+            r#"
+            host = "localhost"
+            port = 1883
+            client-id = "homieflow"
+            reconnect-interval-seconds = 5
+            username = "user"
+            password-file = "/nonexistent/mqtt_password.txt"
+            "#,
+        )
+        .unwrap();
+        assert!(get_mqtt_options(&config, None).is_err());
+    }
+
+    #[test]
+    fn lost_device_reported_offline() {
+        let (_, ready_device) = device("device", State::Ready, &["node"]);
+        let (_, lost_device) = device("device", State::Lost, &["node"]);
+        let config = Devices::default();
+        let filter = DeviceFilter::new(&[], &[]).unwrap();
+        let last_seen = DeviceTimes::default();
+        let offline_since = DeviceTimes::default();
+
+        assert!(!update_offline_since(
+            &offline_since,
+            "device",
+            is_online(&ready_device, &config)
+        ));
+        assert!(update_offline_since(
+            &offline_since,
+            "device",
+            is_online(&lost_device, &config)
+        ));
+        let states =
+            availability_report_states(&lost_device, &config, &filter, &last_seen, &offline_since);
+        assert_eq!(states.len(), 1);
+        assert_eq!(states[0].0, "node");
+        assert!(!states[0].1.online);
+
+        // Coming back online is reported too.
+        assert!(update_offline_since(
+            &offline_since,
+            "device",
+            is_online(&ready_device, &config)
+        ));
+        let states =
+            availability_report_states(&ready_device, &config, &filter, &last_seen, &offline_since);
+        assert!(states[0].1.online);
+    }
+
+    #[test]
+    fn stale_device_reported_offline() {
+        let (_, device) = device("device", State::Ready, &["node"]);
+        let node = &device.nodes["node"];
+        let config = Devices {
+            online_timeout_seconds: Some(60),
+            ..Default::default()
+        };
+        let last_seen = DeviceTimes::default();
+
+        last_seen.record("device");
+        assert!(node_report_state(&device, node, &config, Some(&last_seen), None).online);
+
+        // A device which hasn't published anything within the timeout is reported offline, the same
+        // as in QUERY responses.
+        last_seen.record_at("device", Instant::now() - Duration::from_secs(61));
+        assert!(!node_report_state(&device, node, &config, Some(&last_seen), None).online);
+    }
+
+    #[tokio::test]
+    async fn offline_report_delayed_by_grace() {
+        let (_, ready_device) = device("device", State::Ready, &["node"]);
+        let (_, lost_device) = device("device", State::Lost, &["node"]);
+        let config = Devices {
+            offline_grace_seconds: Some(60),
+            ..Default::default()
+        };
+        let filter = Arc::new(DeviceFilter::new(&[], &[]).unwrap());
+        let last_seen = Arc::new(DeviceTimes::default());
+        let offline_since = Arc::new(DeviceTimes::default());
+        let (client, event_loop) =
+            AsyncClient::new(MqttOptions::new("test", "localhost", 1883), 10);
+        let mirror = StateMirror::new(
+            client,
+            "state/{device}/{node}".to_string(),
+            QoS::AtLeastOnce,
+        );
+        let mut pending_offline_reports = HashMap::new();
+        let published_online = || match event_loop.requests_rx.try_recv() {
+            Ok(Request::Publish(publish)) => {
+                let state: serde_json::Value = serde_json::from_slice(&publish.payload).unwrap();
+                Some(state["online"].as_bool().unwrap())
+            }
+            Ok(request) => panic!("Unexpected request {:?}", request),
+            Err(_) => None,
+        };
+
+        // The device went offline almost a whole grace period ago, so nothing is reported until the
+        // grace period ends.
+        offline_since.record_first_at(
+            "device",
+            Instant::now() - Duration::from_secs(60) + Duration::from_millis(100),
+        );
+        report_availability(
+            &lost_device,
+            None,
+            Some(&mirror),
+            "agent",
+            None,
+            &config,
+            &filter,
+            &last_seen,
+            &offline_since,
+            &mut pending_offline_reports,
+        )
+        .await;
+        assert_eq!(published_online(), None);
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(published_online(), Some(false));
+        assert_eq!(published_online(), None);
+
+        // If the device comes back within the grace period, the offline report is cancelled.
+        offline_since.clear("device");
+        offline_since.record_first_at(
+            "device",
+            Instant::now() - Duration::from_secs(60) + Duration::from_millis(100),
+        );
+        report_availability(
+            &lost_device,
+            None,
+            Some(&mirror),
+            "agent",
+            None,
+            &config,
+            &filter,
+            &last_seen,
+            &offline_since,
+            &mut pending_offline_reports,
+        )
+        .await;
+        update_offline_since(&offline_since, "device", true);
+        report_availability(
+            &ready_device,
+            None,
+            Some(&mirror),
+            "agent",
+            None,
+            &config,
+            &filter,
+            &last_seen,
+            &offline_since,
+            &mut pending_offline_reports,
+        )
+        .await;
+        assert_eq!(published_online(), Some(true));
+        sleep(Duration::from_millis(300)).await;
+        assert_eq!(published_online(), None);
+    }
+
+    #[test]
+    fn count_online_devices() {
+        let devices: HashMap<String, Device> = [
+            device("ready", State::Ready, &["node"]),
+            device("sleeping", State::Sleeping, &["node", "other"]),
+            device("no-nodes", State::Ready, &[]),
+            device("init", State::Init, &["node"]),
+            device("lost", State::Lost, &["node"]),
+            device("alert", State::Alert, &["node"]),
+        ]
+        .into_iter()
+        .collect();
+
+        let filter = DeviceFilter::new(&[], &[]).unwrap();
+
+        assert_eq!(
+            online_device_count(&HashMap::new(), &Devices::default(), &filter),
+            0
+        );
+        assert_eq!(
+            online_device_count(&devices, &Devices::default(), &filter),
+            2
+        );
+        assert_eq!(
+            online_device_count(
+                &devices,
+                &Devices {
+                    alert_online: true,
+                    ..Default::default()
+                },
+                &filter
+            ),
+            3
+        );
+        // Devices with no nodes matching the filter aren't counted.
+        let filter = DeviceFilter::new(&[], &["*/node".to_string()]).unwrap();
+        assert_eq!(
+            online_device_count(&devices, &Devices::default(), &filter),
+            1
+        );
+    }
+}
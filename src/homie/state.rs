@@ -12,44 +12,513 @@
 
 //! Functions to get Google Home state for Homie devices.
 
+use super::device_times::DeviceTimes;
+use crate::config::server::{Devices, PropertyRole, StringSensor, TemperatureTrait};
 use google_smart_home::{
-    device::commands::{ColorAbsolute, ColorValue},
+    device::commands::{ColorAbsolute, ColorValue, Hsv},
     query::response::{self, Color},
 };
-use homie_controller::{ColorFormat, ColorHsv, ColorRgb, Datatype, Node, Property};
+use homie_controller::{ColorFormat, ColorHsv, ColorRgb, Datatype, Device, Node, Property, State};
+use std::collections::HashMap;
 use std::ops::RangeInclusive;
+use std::time::Duration;
 
-pub fn homie_node_to_state(node: &Node, online: bool) -> response::State {
+/// Returns whether the given Homie device should be reported to Google Home as online.
+///
+/// Devices which are `init`, `disconnected` or `lost` are reported as offline, but are still
+/// included in SYNC responses so that they don't disappear from the Google Home app. Devices in
+/// the `alert` state are reported as offline unless `alert-online` is set in the config.
+pub fn is_online(device: &Device, config: &Devices) -> bool {
+    match device.state {
+        State::Ready | State::Sleeping => true,
+        State::Alert => config.alert_online,
+        State::Unknown | State::Init | State::Disconnected | State::Lost => false,
+    }
+}
+
+/// Records in `offline_since` whether the given device is currently online, keeping the time at
+/// which it first went offline. Returns whether this is a change from what was previously recorded.
+/// Devices not seen before are assumed to have been online.
+pub fn update_offline_since(offline_since: &DeviceTimes, device_id: &str, online: bool) -> bool {
+    if online {
+        offline_since.clear(device_id)
+    } else {
+        offline_since.record_first(device_id)
+    }
+}
+
+/// Returns whether the given device should be reported to Google Home as online, either because it
+/// is according to [`is_online`] or because it went offline within the configured offline grace
+/// period.
+pub fn is_online_with_grace(
+    device: &Device,
+    config: &Devices,
+    offline_since: Option<&DeviceTimes>,
+) -> bool {
+    is_online(device, config)
+        || offline_since
+            .and_then(|offline_since| offline_grace_remaining(device, config, offline_since))
+            .is_some()
+}
+
+/// Returns whether the given device hasn't been heard from within the configured online timeout,
+/// so should be reported to Google Home as offline even if its last published state was ready.
+pub fn is_stale(device: &Device, config: &Devices, last_seen: Option<&DeviceTimes>) -> bool {
+    if let (Some(timeout), Some(last_seen)) = (config.online_timeout_seconds, last_seen) {
+        last_seen.older_than(&device.id, Duration::from_secs(timeout))
+    } else {
+        false
+    }
+}
+
+/// Returns how much longer the given device will be reported to Google Home as online despite
+/// having gone offline, if it is currently offline but within the configured offline grace period.
+pub fn offline_grace_remaining(
+    device: &Device,
+    config: &Devices,
+    offline_since: &DeviceTimes,
+) -> Option<Duration> {
+    if is_online(device, config) {
+        return None;
+    }
+    let grace = Duration::from_secs(config.offline_grace_seconds?);
+    grace
+        .checked_sub(offline_since.elapsed(&device.id)?)
+        .filter(|remaining| !remaining.is_zero())
+}
+
+pub fn homie_node_to_state(
+    device: &Device,
+    node: &Node,
+    online: bool,
+    config: &Devices,
+) -> response::State {
     let mut state = response::State {
         online,
         ..Default::default()
     };
 
-    if let Some(on) = node.properties.get("on") {
-        state.on = on.value().ok();
+    if let Some(on) = role_property(node, config, PropertyRole::On)
+        .filter(|_| !config.is_command_only_on_off(&device.id, &node.id))
+    {
+        // Prefer the status property if there is one, as it reflects the actual state of the device
+        // rather than the last command.
+        let status = config
+            .on_status_property
+            .as_ref()
+            .and_then(|property_id| node.properties.get(property_id))
+            .filter(|status| status.datatype == Some(Datatype::Boolean));
+        state.on = property_value_to_on(status.unwrap_or(on));
     }
-    if let Some(brightness) = node.properties.get("brightness") {
+    if let Some(brightness) = role_property(node, config, PropertyRole::Brightness) {
         state.brightness = property_value_to_percentage(brightness);
+        // Dimmers without a separate on/off property are on whenever their brightness is non-zero.
+        if role_property(node, config, PropertyRole::On).is_none() {
+            state.on = state.brightness.map(|brightness| brightness > 0);
+        }
     }
-    if let Some(color) = node.properties.get("color") {
+    if let Some(color) = role_property(node, config, PropertyRole::Color) {
         state.color = property_value_to_color(color);
     }
-    if let Some(temperature) = node.properties.get("temperature") {
-        state.thermostat_temperature_ambient = property_value_to_number(temperature);
+    if state.color.is_none() {
+        if let Some(color_temperature) = role_property(node, config, PropertyRole::ColorTemperature)
+        {
+            state.color = property_value_to_number(color_temperature)
+                .map(|temperature| Color::TemperatureK(temperature.round() as u64));
+        }
+    }
+    if let Some(dock) = node.properties.get("dock") {
+        state.is_docked = dock.value().ok();
+    }
+    if let Some(locked) = node.properties.get("locked") {
+        state.is_locked = locked.value().ok();
+        state.is_jammed = Some(is_jammed(node));
+    }
+    if let Some(position) = node.properties.get("position") {
+        state.open_percent = property_value_to_percentage(position);
+    }
+    if let Some(tilt) = node.properties.get("tilt") {
+        if is_percent_property(tilt) {
+            state.rotation_percent = property_value_to_percentage(tilt).map(Into::into);
+        } else {
+            state.rotation_degrees = property_value_to_number(tilt);
+        }
     }
-    if let Some(humidity) = node.properties.get("humidity") {
-        state.thermostat_humidity_ambient = property_value_to_number(humidity);
+    if config.temperature_trait != TemperatureTrait::SensorState {
+        if let Some(temperature) = role_property(node, config, PropertyRole::Temperature) {
+            if is_temperature_control(node, config) {
+                state.temperature_setpoint_celsius = property_value_to_number(temperature);
+            } else {
+                state.thermostat_temperature_ambient = property_value_to_number(temperature);
+                if config.synthesize_thermostat_mode {
+                    state.thermostat_mode = synthesized_thermostat_mode(node).map(str::to_owned);
+                }
+            }
+        }
+        if let Some(humidity) = role_property(node, config, PropertyRole::Humidity) {
+            state.thermostat_humidity_ambient = property_value_to_number(humidity);
+        }
+    }
+    let mode_settings: HashMap<String, String> = node
+        .properties
+        .values()
+        .filter(|property| is_mode_property(property))
+        .filter_map(|property| Some((property.id.clone(), property.value.clone()?)))
+        .collect();
+    if !mode_settings.is_empty() {
+        state.current_mode_settings = Some(mode_settings);
+    }
+    let sensor_state_data: Vec<_> = string_sensor_properties(node, config)
+        .filter_map(|(sensor, property)| {
+            let value = property.value.as_ref()?;
+            sensor
+                .states
+                .contains(value)
+                .then(|| response::CurrentSensorStateData {
+                    name: sensor.name.clone(),
+                    current_sensor_state: Some(value.clone()),
+                    raw_value: None,
+                })
+        })
+        .chain(climate_sensors(node, config).filter_map(|sensor| {
+            Some(response::CurrentSensorStateData {
+                name: sensor.name.to_string(),
+                current_sensor_state: None,
+                raw_value: Some(property_value_to_number(sensor.property)?),
+            })
+        }))
+        .chain(
+            diagnostic_sensors(device, config).map(|sensor| response::CurrentSensorStateData {
+                name: sensor.name.to_string(),
+                current_sensor_state: None,
+                raw_value: Some(sensor.value),
+            }),
+        )
+        .collect();
+    if !sensor_state_data.is_empty() {
+        state.current_sensor_state_data = Some(sensor_state_data);
+    }
+    if let Some(target_humidity) = target_humidity_property(node) {
+        state.humidity_setpoint_percent =
+            property_value_to_number(target_humidity).map(number_to_percent);
+        state.humidity_ambient_percent = role_property(node, config, PropertyRole::Humidity)
+            .and_then(property_value_to_number)
+            .map(number_to_percent);
+    }
+    if let Some(energy) = energy_property(node, config) {
+        if let Some(value) = property_value_to_number(energy) {
+            state.descriptive_capacity_remaining =
+                property_range(energy).map(|range| descriptive_capacity(value, &range).to_string());
+            state.capacity_remaining = Some(vec![response::CapacityValue {
+                raw_value: value,
+                unit: "KILOWATT_HOURS".to_string(),
+            }]);
+        }
     }
 
     state
 }
 
+/// Returns the node's property for the given role: the first of the candidate IDs configured for
+/// the role which the node has, or else the property with the role's standard ID.
+pub fn role_property<'a>(
+    node: &'a Node,
+    config: &Devices,
+    role: PropertyRole,
+) -> Option<&'a Property> {
+    match config.property_names.candidates(role) {
+        Some(candidates) => candidates.iter().find_map(|id| node.properties.get(id)),
+        None => node.properties.get(role.default_id()),
+    }
+}
+
+/// Returns whether the given node has a boolean `jammed` property which is true.
+pub fn is_jammed(node: &Node) -> bool {
+    node.properties
+        .get("jammed")
+        .is_some_and(|jammed| jammed.value() == Ok(true))
+}
+
+/// Returns the string sensors configured for properties of the given node, along with the
+/// corresponding properties.
+pub fn string_sensor_properties<'a>(
+    node: &'a Node,
+    config: &'a Devices,
+) -> impl Iterator<Item = (&'a StringSensor, &'a Property)> {
+    config.string_sensors.iter().filter_map(move |sensor| {
+        let property = node.properties.get(&sensor.property)?;
+        (property.datatype == Some(Datatype::String)).then_some((sensor, property))
+    })
+}
+
+/// Boolean properties from which a thermostat mode can be synthesized, with the mode each means when
+/// it is true, in order of preference.
+const THERMOSTAT_ACTIVITY_PROPERTIES: [(&str, &str); 3] =
+    [("heating", "heat"), ("cooling", "cool"), ("active", "on")];
+
+/// Returns the boolean activity properties of the given thermostat node which a mode can be
+/// synthesized from, or an empty iterator if it has an explicit `thermostat-mode` property.
+fn thermostat_activity_properties(
+    node: &Node,
+) -> impl Iterator<Item = (&Property, &'static str)> + '_ {
+    let explicit_mode = node.properties.contains_key("thermostat-mode");
+    THERMOSTAT_ACTIVITY_PROPERTIES
+        .iter()
+        .filter(move |_| !explicit_mode)
+        .filter_map(move |(id, mode)| {
+            let property = node.properties.get(*id)?;
+            (property.datatype == Some(Datatype::Boolean)).then_some((property, *mode))
+        })
+}
+
+/// Returns the thermostat modes which may be synthesized for the given thermostat node, which are
+/// `off` along with a mode for each activity property it has. This is empty if none can be.
+pub fn synthesized_thermostat_modes(node: &Node) -> Vec<String> {
+    let modes: Vec<String> = thermostat_activity_properties(node)
+        .map(|(_, mode)| mode.to_owned())
+        .collect();
+    if modes.is_empty() {
+        modes
+    } else {
+        std::iter::once("off".to_owned()).chain(modes).collect()
+    }
+}
+
+/// Synthesizes a thermostat mode for the given node from whichever of its `heating`, `cooling` or
+/// `active` boolean properties is true, or `off` if none are. Returns `None` if the node has none of
+/// these properties, or an explicit `thermostat-mode` property.
+pub fn synthesized_thermostat_mode(node: &Node) -> Option<&'static str> {
+    let mut any = false;
+    for (property, mode) in thermostat_activity_properties(node) {
+        any = true;
+        if property.value() == Ok(true) {
+            return Some(mode);
+        }
+    }
+    any.then_some("off")
+}
+
+/// Returns the node's `target-humidity` property, if it has one and it is numeric.
+pub fn target_humidity_property(node: &Node) -> Option<&Property> {
+    let property = node.properties.get("target-humidity")?;
+    matches!(
+        property.datatype,
+        Some(Datatype::Integer) | Some(Datatype::Float)
+    )
+    .then_some(property)
+}
+
+/// Rounds the given number to a whole percentage, clamped to the valid range.
+pub fn number_to_percent(value: f64) -> u8 {
+    value.round().clamp(0.0, 100.0) as u8
+}
+
+/// Returns the energy property configured for the given node, if it has one and it is numeric.
+pub fn energy_property<'a>(node: &'a Node, config: &Devices) -> Option<&'a Property> {
+    let property = node.properties.get(config.energy_property.as_ref()?)?;
+    matches!(
+        property.datatype,
+        Some(Datatype::Integer) | Some(Datatype::Float)
+    )
+    .then_some(property)
+}
+
+/// Describes how much of the given range an energy value has reached, for the `EnergyStorage`
+/// trait.
+fn descriptive_capacity(value: f64, range: &RangeInclusive<f64>) -> &'static str {
+    let fraction = (value - range.start()) / (range.end() - range.start());
+    if fraction >= 1.0 {
+        "FULL"
+    } else if fraction >= 0.7 {
+        "HIGH"
+    } else if fraction >= 0.3 {
+        "MEDIUM"
+    } else if fraction >= 0.1 {
+        "LOW"
+    } else {
+        "CRITICALLY_LOW"
+    }
+}
+
+/// The Homie property IDs exposed as sensors when `temperature-trait` is `sensor-state`, with the
+/// corresponding Google Home sensor names and units.
+const CLIMATE_SENSORS: [(PropertyRole, &str, &str); 2] = [
+    (PropertyRole::Temperature, "Temperature", "DEGREES_CELSIUS"),
+    (PropertyRole::Humidity, "Humidity", "PERCENTAGE"),
+];
+
+/// A numeric Homie property exposed as a sensor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PropertySensor<'a> {
+    /// The Google Home sensor name.
+    pub name: &'static str,
+    /// The Google Home unit of the sensor value.
+    pub unit: &'static str,
+    /// The Homie property with the sensor value.
+    pub property: &'a Property,
+}
+
+/// Returns sensors for the node's `temperature` and `humidity` properties, if the config asks for
+/// them to be exposed as sensors rather than as a thermostat.
+pub fn climate_sensors<'a>(
+    node: &'a Node,
+    config: &'a Devices,
+) -> impl Iterator<Item = PropertySensor<'a>> {
+    let enabled = config.temperature_trait == TemperatureTrait::SensorState;
+    CLIMATE_SENSORS
+        .iter()
+        .filter(move |_| enabled)
+        .filter_map(move |(role, name, unit)| {
+            let property = role_property(node, config, *role)?;
+            matches!(
+                property.datatype,
+                Some(Datatype::Integer) | Some(Datatype::Float)
+            )
+            .then_some(PropertySensor {
+                name,
+                unit,
+                property,
+            })
+        })
+}
+
+/// A numeric sensor for one of a Homie device's stats.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DiagnosticSensor {
+    /// The Google Home sensor name.
+    pub name: &'static str,
+    /// The Google Home unit of the sensor value.
+    pub unit: &'static str,
+    /// The current value of the stat.
+    pub value: f64,
+}
+
+/// Returns diagnostic sensors for the stats which the given device reports, if they are enabled in
+/// the config.
+pub fn diagnostic_sensors(
+    device: &Device,
+    config: &Devices,
+) -> impl Iterator<Item = DiagnosticSensor> {
+    let stats = if config.diagnostic_sensors {
+        vec![
+            device.stats_cputemp.map(|value| DiagnosticSensor {
+                name: "CpuTemperature",
+                unit: "DEGREES_CELSIUS",
+                value,
+            }),
+            device.stats_cpuload.map(|value| DiagnosticSensor {
+                name: "CpuLoad",
+                unit: "PERCENTAGE",
+                value: value as f64,
+            }),
+        ]
+    } else {
+        vec![]
+    };
+    stats.into_iter().flatten()
+}
+
+/// Returns whether the given node should be exposed with the `TemperatureControl` trait rather than
+/// as a thermostat. This requires the config to ask for it, and the node to have a settable
+/// `temperature` property with a range but no `humidity` property.
+pub fn is_temperature_control(node: &Node, config: &Devices) -> bool {
+    if config.temperature_trait != TemperatureTrait::TemperatureControl
+        || role_property(node, config, PropertyRole::Humidity).is_some()
+    {
+        return false;
+    }
+    if let Some(temperature) = role_property(node, config, PropertyRole::Temperature) {
+        temperature.settable && property_range(temperature).is_some()
+    } else {
+        false
+    }
+}
+
+/// Returns whether the given property should be exposed with the `Modes` trait, i.e. it is a
+/// settable enum property whose ID ends with `mode`.
+pub fn is_mode_property(property: &Property) -> bool {
+    property.settable && property.datatype == Some(Datatype::Enum) && property.id.ends_with("mode")
+}
+
+/// Enum values which are treated as meaning on, for on/off properties.
+const ON_VALUES: [&str; 3] = ["on", "true", "yes"];
+/// Enum values which are treated as meaning off, for on/off properties.
+const OFF_VALUES: [&str; 3] = ["off", "false", "no"];
+
+/// Converts the value of an on/off property to a boolean. This may be a boolean property, an integer
+/// property where any non-zero value is on, or an enum property with values such as `on` and `off`.
+pub fn property_value_to_on(property: &Property) -> Option<bool> {
+    match property.datatype? {
+        Datatype::Boolean => property.value().ok(),
+        Datatype::Integer => integer_value(property).map(|value| value != 0),
+        Datatype::Enum => {
+            let value = property.value.as_deref()?.to_lowercase();
+            if ON_VALUES.contains(&value.as_str()) {
+                Some(true)
+            } else if OFF_VALUES.contains(&value.as_str()) {
+                Some(false)
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Converts an on/off state to the appropriate value to set on the given property, if it is a
+/// boolean, an integer (set to 1 or 0), or an enum with a suitable value.
+pub fn on_to_property_value(property: &Property, on: bool) -> Option<String> {
+    match property.datatype? {
+        Datatype::Boolean => Some(on.to_string()),
+        Datatype::Integer => Some(if on { "1" } else { "0" }.to_string()),
+        Datatype::Enum => {
+            let candidates = if on { ON_VALUES } else { OFF_VALUES };
+            property
+                .enum_values()
+                .ok()?
+                .into_iter()
+                .find(|value| candidates.contains(&value.to_lowercase().as_str()))
+                .map(ToOwned::to_owned)
+        }
+        _ => None,
+    }
+}
+
+/// Returns whether the given numeric property has a unit of `%`, so should be exposed to Google Home
+/// as a percentage rather than an absolute value.
+pub fn is_percent_property(property: &Property) -> bool {
+    property.unit.as_deref() == Some("%")
+}
+
+/// Returns whether the given string is a valid value for the property's datatype and format, so
+/// that it can be set. Properties with an unknown datatype accept any value.
+pub fn is_valid_value(property: &Property, value: &str) -> bool {
+    let candidate = Property {
+        value: Some(value.to_owned()),
+        ..property.clone()
+    };
+    match property.datatype {
+        None | Some(Datatype::String) => true,
+        Some(Datatype::Integer) => candidate.value::<i64>().is_ok(),
+        Some(Datatype::Float) => candidate.value::<f64>().is_ok(),
+        Some(Datatype::Boolean) => candidate.value::<bool>().is_ok(),
+        Some(Datatype::Enum) => property
+            .enum_values()
+            .is_ok_and(|values| values.contains(&value)),
+        Some(Datatype::Color) => match property.color_format() {
+            Ok(ColorFormat::Rgb) => candidate.value::<ColorRgb>().is_ok(),
+            Ok(ColorFormat::Hsv) => candidate.value::<ColorHsv>().is_ok(),
+            Err(_) => false,
+        },
+    }
+}
+
 /// Scales the value of the given property to a percentage.
 pub fn property_value_to_percentage(property: &Property) -> Option<u8> {
     match property.datatype? {
         Datatype::Integer => {
-            let value: i64 = property.value().ok()?;
-            let range: RangeInclusive<i64> = property.range().ok()?;
+            let value = integer_value(property)?;
+            let range = integer_range(property)?;
             let percentage = (value - range.start()) * 100 / (range.end() - range.start());
             let percentage = cap(percentage, 0, 100);
             Some(percentage as u8)
@@ -66,28 +535,81 @@ pub fn property_value_to_percentage(property: &Property) -> Option<u8> {
 }
 
 /// Converts a percentage to the appropriately scaled property value of the given property, if it has
-/// a range specified.
-pub fn percentage_to_property_value(property: &Property, percentage: u8) -> Option<String> {
+/// a range specified. Percentages below `min_percentage` are raised to it.
+pub fn percentage_to_property_value(
+    property: &Property,
+    percentage: u8,
+    min_percentage: u8,
+    float_precision: Option<usize>,
+) -> Option<String> {
+    let percentage = percentage.max(min_percentage).min(100);
     match property.datatype? {
         Datatype::Integer => {
-            let range: RangeInclusive<i64> = property.range().ok()?;
+            let range = integer_range(property)?;
             let value = range.start() + percentage as i64 * (range.end() - range.start()) / 100;
             Some(format!("{}", value))
         }
         Datatype::Float => {
             let range: RangeInclusive<f64> = property.range().ok()?;
             let value = range.start() + percentage as f64 * (range.end() - range.start()) / 100.0;
-            Some(format!("{}", value))
+            Some(format_float(value, float_precision))
         }
         _ => None,
     }
 }
 
+/// Converts a number to the appropriate value to set on the given property, if it is a numeric
+/// property.
+pub fn number_to_property_value(
+    property: &Property,
+    value: f64,
+    float_precision: Option<usize>,
+) -> Option<String> {
+    match property.datatype? {
+        Datatype::Integer => Some(format!("{}", value.round() as i64)),
+        Datatype::Float => Some(format_float(value, float_precision)),
+        _ => None,
+    }
+}
+
+/// The number of decimal places to which to round values set on float properties, if not
+/// configured.
+const DEFAULT_FLOAT_PRECISION: usize = 6;
+
+/// Formats a value for a float property, rounded to the given number of decimal places (or
+/// [`DEFAULT_FLOAT_PRECISION`]) and without trailing zeros, so that floating point noise such as
+/// `1.7000000000000002` isn't sent to devices.
+fn format_float(value: f64, precision: Option<usize>) -> String {
+    let formatted = format!("{:.*}", precision.unwrap_or(DEFAULT_FLOAT_PRECISION), value);
+    let trimmed = if formatted.contains('.') {
+        formatted.trim_end_matches('0').trim_end_matches('.')
+    } else {
+        &formatted
+    };
+    if trimmed == "-0" {
+        "0".to_owned()
+    } else {
+        trimmed.to_owned()
+    }
+}
+
+/// Gets the range of the given numeric property as floats, if it specifies one.
+pub fn property_range(property: &Property) -> Option<RangeInclusive<f64>> {
+    match property.datatype? {
+        Datatype::Integer => {
+            let range = integer_range(property)?;
+            Some(*range.start() as f64..=*range.end() as f64)
+        }
+        Datatype::Float => property.range().ok(),
+        _ => None,
+    }
+}
+
 /// Converts the property value to a JSON number if it is an appropriate type.
 pub fn property_value_to_number(property: &Property) -> Option<f64> {
     match property.datatype? {
         Datatype::Integer => {
-            let value: i64 = property.value().ok()?;
+            let value = integer_value(property)?;
             Some(value as f64)
         }
         Datatype::Float => {
@@ -98,6 +620,37 @@ pub fn property_value_to_number(property: &Property) -> Option<f64> {
     }
 }
 
+/// Gets the value of the given integer property. Some devices publish values such as `50.0` for
+/// integer properties, so if the value isn't a valid integer but is a valid float then a warning is
+/// logged and it is rounded.
+fn integer_value(property: &Property) -> Option<i64> {
+    property.value().ok().or_else(|| {
+        let value: f64 = property.value.as_deref()?.parse().ok()?;
+        tracing::warn!(
+            "Integer property {} has float value {:?}, rounding it.",
+            property.id,
+            property.value
+        );
+        Some(value.round() as i64)
+    })
+}
+
+/// Gets the range of the given integer property. Some devices declare ranges such as `0.0:100.0`
+/// for integer properties, so if the format isn't a valid integer range but is a valid float range
+/// then a warning is logged and it is rounded.
+fn integer_range(property: &Property) -> Option<RangeInclusive<i64>> {
+    property.range().ok().or_else(|| {
+        let (start, end) = property.format.as_deref()?.split_once(':')?;
+        let range: RangeInclusive<f64> = start.parse().ok()?..=end.parse().ok()?;
+        tracing::warn!(
+            "Integer property {} has float range {:?}, rounding it.",
+            property.id,
+            property.format
+        );
+        Some(range.start().round() as i64..=range.end().round() as i64)
+    })
+}
+
 /// Converts the value of the given property to a Google Home JSON color value, if it is the
 /// appropriate type.
 pub fn property_value_to_color(property: &Property) -> Option<Color> {
@@ -120,6 +673,37 @@ pub fn property_value_to_color(property: &Property) -> Option<Color> {
     Some(color_value)
 }
 
+/// Common colour names which Google may send without a spectrum value, and their RGB values.
+const NAMED_COLORS: [(&str, u32); 14] = [
+    ("red", 0xff0000),
+    ("orange", 0xffa500),
+    ("yellow", 0xffff00),
+    ("lime", 0x00ff00),
+    ("green", 0x00ff00),
+    ("turquoise", 0x40e0d0),
+    ("cyan", 0x00ffff),
+    ("blue", 0x0000ff),
+    ("purple", 0x8000ff),
+    ("violet", 0xee82ee),
+    ("magenta", 0xff00ff),
+    ("pink", 0xffc0cb),
+    ("white", 0xffffff),
+    ("black", 0x000000),
+];
+
+/// Looks up the RGB value for the given colour name, ignoring case and whitespace.
+fn named_color(name: &str) -> Option<ColorValue> {
+    let name = name
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_lowercase();
+    NAMED_COLORS
+        .iter()
+        .find(|(color_name, _)| *color_name == name)
+        .map(|&(_, spectrum_rgb)| ColorValue::Rgb { spectrum_rgb })
+}
+
 /// Converts a Google Home `ColorAbsolute` command to the appropriate value to set on the given
 /// Homie property, if it is the appropriate format.
 pub fn color_absolute_to_property_value(
@@ -127,29 +711,80 @@ pub fn color_absolute_to_property_value(
     color_absolute: &ColorAbsolute,
 ) -> Option<String> {
     let color_format = property.color_format().ok()?;
-    match color_format {
-        ColorFormat::Rgb => {
-            if let ColorValue::Rgb { spectrum_rgb } = color_absolute.color.value {
-                let rgb = ColorRgb::new(
-                    (spectrum_rgb >> 16) as u8,
-                    (spectrum_rgb >> 8) as u8,
-                    spectrum_rgb as u8,
-                );
-                return Some(rgb.to_string());
-            }
+    // Fall back to looking up the name if no value was given.
+    let value = match &color_absolute.color.value {
+        Some(value) => value.clone(),
+        None => named_color(color_absolute.color.name.as_deref()?)?,
+    };
+    // Convert between RGB and HSV if the command doesn't match the property's format.
+    match (color_format, value) {
+        (ColorFormat::Rgb, ColorValue::Rgb { spectrum_rgb }) => {
+            let rgb = ColorRgb::new(
+                (spectrum_rgb >> 16) as u8,
+                (spectrum_rgb >> 8) as u8,
+                spectrum_rgb as u8,
+            );
+            Some(rgb.to_string())
         }
-        ColorFormat::Hsv => {
-            if let ColorValue::Hsv { spectrum_hsv } = &color_absolute.color.value {
-                let hsv = ColorHsv::new(
-                    spectrum_hsv.hue as u16,
-                    (spectrum_hsv.saturation * 100.0) as u8,
-                    (spectrum_hsv.value * 100.0) as u8,
-                );
-                return Some(hsv.to_string());
-            }
+        (ColorFormat::Rgb, ColorValue::Hsv { spectrum_hsv }) => {
+            Some(hsv_to_rgb(&spectrum_hsv).to_string())
+        }
+        (ColorFormat::Hsv, ColorValue::Hsv { spectrum_hsv }) => {
+            let hsv = ColorHsv::new(
+                spectrum_hsv.hue as u16,
+                (spectrum_hsv.saturation * 100.0) as u8,
+                (spectrum_hsv.value * 100.0) as u8,
+            );
+            Some(hsv.to_string())
         }
+        (ColorFormat::Hsv, ColorValue::Rgb { spectrum_rgb }) => {
+            Some(rgb_to_hsv(spectrum_rgb).to_string())
+        }
+        (_, ColorValue::Temperature { .. }) => None,
     }
-    None
+}
+
+/// Converts a Google Home HSV colour, with the hue in degrees and the saturation and value between
+/// 0 and 1, to RGB.
+fn hsv_to_rgb(hsv: &Hsv) -> ColorRgb {
+    let sector = hsv.hue.rem_euclid(360.0) / 60.0;
+    let value = cap(hsv.value, 0.0, 1.0);
+    let chroma = value * cap(hsv.saturation, 0.0, 1.0);
+    let x = chroma * (1.0 - (sector % 2.0 - 1.0).abs());
+    let (r, g, b) = match sector as u8 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let min = value - chroma;
+    let channel = |c: f64| ((c + min) * 255.0).round() as u8;
+    ColorRgb::new(channel(r), channel(g), channel(b))
+}
+
+/// Converts a Google Home 24-bit RGB colour to HSV.
+fn rgb_to_hsv(spectrum_rgb: u32) -> ColorHsv {
+    let channel = |shift: u32| ((spectrum_rgb >> shift) & 0xff) as f64 / 255.0;
+    let (r, g, b) = (channel(16), channel(8), channel(0));
+    let max = r.max(g).max(b);
+    let delta = max - r.min(g).min(b);
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * ((g - b) / delta).rem_euclid(6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let saturation = if max == 0.0 { 0.0 } else { delta / max };
+    ColorHsv::new(
+        hue.round() as u16 % 360,
+        (saturation * 100.0).round() as u8,
+        (max * 100.0).round() as u8,
+    )
 }
 
 fn cap<N: Copy + PartialOrd>(value: N, min: N, max: N) -> N {
@@ -170,6 +805,7 @@ mod tests {
     };
 
     use super::*;
+    use crate::config::server::PropertyNames;
 
     #[test]
     fn percentage_integer() {
@@ -186,11 +822,43 @@ mod tests {
 
         assert_eq!(property_value_to_percentage(&property), Some(30));
         assert_eq!(
-            percentage_to_property_value(&property, 70),
+            percentage_to_property_value(&property, 70, 0, None),
             Some("17".to_string())
         );
     }
 
+    #[test]
+    fn percentage_minimum() {
+        let property = Property {
+            id: "brightness".to_string(),
+            name: Some("Brightness".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("0:255".to_string()),
+            value: Some("13".to_string()),
+        };
+
+        assert_eq!(
+            percentage_to_property_value(&property, 1, 0, None),
+            Some("2".to_string())
+        );
+        assert_eq!(
+            percentage_to_property_value(&property, 1, 10, None),
+            Some("25".to_string())
+        );
+        assert_eq!(
+            percentage_to_property_value(&property, 0, 10, None),
+            Some("25".to_string())
+        );
+        // Percentages above the minimum are unaffected.
+        assert_eq!(
+            percentage_to_property_value(&property, 50, 10, None),
+            Some("127".to_string())
+        );
+    }
+
     #[test]
     fn percentage_float() {
         let property = Property {
@@ -206,11 +874,96 @@ mod tests {
 
         assert_eq!(property_value_to_percentage(&property), Some(30));
         assert_eq!(
-            percentage_to_property_value(&property, 70),
+            percentage_to_property_value(&property, 70, 0, None),
             Some("1.7".to_string())
         );
     }
 
+    #[test]
+    fn float_precision() {
+        let property = Property {
+            id: "level".to_string(),
+            name: Some("Level".to_string()),
+            datatype: Some(Datatype::Float),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("0.1:0.3".to_string()),
+            value: Some("0.2".to_string()),
+        };
+
+        // 0.1 + 0.2 is 0.30000000000000004 as a float.
+        assert_eq!(
+            number_to_property_value(&property, 0.1 + 0.2, None),
+            Some("0.3".to_string())
+        );
+        assert_eq!(
+            percentage_to_property_value(&property, 10, 0, None),
+            Some("0.12".to_string())
+        );
+        assert_eq!(
+            percentage_to_property_value(&property, 10, 0, Some(1)),
+            Some("0.1".to_string())
+        );
+        assert_eq!(
+            number_to_property_value(&property, 21.0, Some(2)),
+            Some("21".to_string())
+        );
+        assert_eq!(
+            number_to_property_value(&property, -0.001, Some(2)),
+            Some("0".to_string())
+        );
+    }
+
+    #[test]
+    fn percentage_integer_with_float_range() {
+        let property = Property {
+            id: "brightness".to_string(),
+            name: Some("Brightness".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("0.0:200.0".to_string()),
+            value: Some("50".to_string()),
+        };
+
+        assert_eq!(property_value_to_percentage(&property), Some(25));
+        assert_eq!(property_range(&property), Some(0.0..=200.0));
+        assert_eq!(
+            percentage_to_property_value(&property, 70, 0, None),
+            Some("140".to_string())
+        );
+
+        // A float value is also accepted.
+        let property = Property {
+            value: Some("100.0".to_string()),
+            ..property
+        };
+        assert_eq!(property_value_to_percentage(&property), Some(50));
+        assert_eq!(property_value_to_number(&property), Some(100.0));
+    }
+
+    #[test]
+    fn percentage_float_with_integer_range() {
+        let property = Property {
+            id: "brightness".to_string(),
+            name: Some("Brightness".to_string()),
+            datatype: Some(Datatype::Float),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("0:200".to_string()),
+            value: Some("50".to_string()),
+        };
+
+        assert_eq!(property_value_to_percentage(&property), Some(25));
+        assert_eq!(
+            percentage_to_property_value(&property, 70, 0, None),
+            Some("140".to_string())
+        );
+    }
+
     #[test]
     fn number_integer() {
         let property = Property {
@@ -266,9 +1019,9 @@ mod tests {
                 &ColorAbsolute {
                     color: Color {
                         name: None,
-                        value: ColorValue::Rgb {
+                        value: Some(ColorValue::Rgb {
                             spectrum_rgb: 0x445566
-                        }
+                        })
                     }
                 }
             ),
@@ -276,6 +1029,54 @@ mod tests {
         );
     }
 
+    #[test]
+    fn color_name_only() {
+        let property = Property {
+            id: "color".to_string(),
+            name: Some("Colour".to_string()),
+            datatype: Some(Datatype::Color),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("rgb".to_string()),
+            value: Some("17,34,51".to_string()),
+        };
+        let named = |name: &str| ColorAbsolute {
+            color: Color {
+                name: Some(name.to_string()),
+                value: None,
+            },
+        };
+
+        assert_eq!(
+            color_absolute_to_property_value(&property, &named("red")),
+            Some("255,0,0".to_string())
+        );
+        assert_eq!(
+            color_absolute_to_property_value(&property, &named("Dark Red")),
+            None
+        );
+        assert_eq!(
+            color_absolute_to_property_value(&property, &named("Magenta")),
+            Some("255,0,255".to_string())
+        );
+        // A spectrum value takes precedence over the name.
+        assert_eq!(
+            color_absolute_to_property_value(
+                &property,
+                &ColorAbsolute {
+                    color: Color {
+                        name: Some("red".to_string()),
+                        value: Some(ColorValue::Rgb {
+                            spectrum_rgb: 0x0000ff
+                        }),
+                    },
+                }
+            ),
+            Some("0,0,255".to_string())
+        );
+    }
+
     #[test]
     fn color_hsv() {
         let property = Property {
@@ -303,17 +1104,620 @@ mod tests {
                 &ColorAbsolute {
                     color: Color {
                         name: None,
-                        value: ColorValue::Hsv {
+                        value: Some(ColorValue::Hsv {
                             spectrum_hsv: Hsv {
                                 hue: 290.0,
                                 saturation: 0.2,
                                 value: 0.3
                             }
-                        }
+                        })
                     }
                 }
             ),
             Some("290,20,30".to_string())
         );
     }
+
+    #[test]
+    fn color_rgb_from_hsv() {
+        let property = Property {
+            id: "color".to_string(),
+            name: Some("Colour".to_string()),
+            datatype: Some(Datatype::Color),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("rgb".to_string()),
+            value: Some("17,34,51".to_string()),
+        };
+        let hsv = |hue, saturation, value| ColorAbsolute {
+            color: Color {
+                name: None,
+                value: Some(ColorValue::Hsv {
+                    spectrum_hsv: Hsv {
+                        hue,
+                        saturation,
+                        value,
+                    },
+                }),
+            },
+        };
+
+        assert_eq!(
+            color_absolute_to_property_value(&property, &hsv(0.0, 1.0, 1.0)),
+            Some("255,0,0".to_string())
+        );
+        assert_eq!(
+            color_absolute_to_property_value(&property, &hsv(200.0, 1.0, 0.8)),
+            Some("0,136,204".to_string())
+        );
+        assert_eq!(
+            color_absolute_to_property_value(&property, &hsv(360.0, 0.0, 1.0)),
+            Some("255,255,255".to_string())
+        );
+    }
+
+    #[test]
+    fn color_hsv_from_rgb() {
+        let property = Property {
+            id: "color".to_string(),
+            name: Some("Colour".to_string()),
+            datatype: Some(Datatype::Color),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("hsv".to_string()),
+            value: Some("280,50,60".to_string()),
+        };
+        let rgb = |spectrum_rgb| ColorAbsolute {
+            color: Color {
+                name: None,
+                value: Some(ColorValue::Rgb { spectrum_rgb }),
+            },
+        };
+
+        assert_eq!(
+            color_absolute_to_property_value(&property, &rgb(0x445566)),
+            Some("210,33,40".to_string())
+        );
+        assert_eq!(
+            color_absolute_to_property_value(&property, &rgb(0xff8000)),
+            Some("30,100,100".to_string())
+        );
+        assert_eq!(
+            color_absolute_to_property_value(&property, &rgb(0xff00ff)),
+            Some("300,100,100".to_string())
+        );
+        assert_eq!(
+            color_absolute_to_property_value(&property, &rgb(0x000000)),
+            Some("0,0,0".to_string())
+        );
+    }
+
+    #[test]
+    fn valid_values() {
+        let integer = Property {
+            id: "brightness".to_string(),
+            name: Some("Brightness".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("0:100".to_string()),
+            value: Some("13".to_string()),
+        };
+        assert!(is_valid_value(&integer, "42"));
+        assert!(!is_valid_value(&integer, "4.2"));
+        assert!(!is_valid_value(&integer, "true"));
+
+        let float = Property {
+            datatype: Some(Datatype::Float),
+            ..integer.clone()
+        };
+        assert!(is_valid_value(&float, "4.2"));
+        assert!(is_valid_value(&float, "42"));
+        assert!(!is_valid_value(&float, "255,255,0"));
+
+        let color = Property {
+            datatype: Some(Datatype::Color),
+            format: Some("rgb".to_string()),
+            ..integer.clone()
+        };
+        assert!(is_valid_value(&color, "255,255,0"));
+        assert!(!is_valid_value(&color, "42"));
+
+        let enum_property = Property {
+            datatype: Some(Datatype::Enum),
+            format: Some("low,high".to_string()),
+            ..integer
+        };
+        assert!(is_valid_value(&enum_property, "high"));
+        assert!(!is_valid_value(&enum_property, "medium"));
+    }
+
+    #[test]
+    fn on_enum() {
+        let property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Enum),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("ON,OFF".to_string()),
+            value: Some("OFF".to_string()),
+        };
+
+        assert_eq!(property_value_to_on(&property), Some(false));
+        assert_eq!(
+            on_to_property_value(&property, true),
+            Some("ON".to_string())
+        );
+        assert_eq!(
+            on_to_property_value(&property, false),
+            Some("OFF".to_string())
+        );
+    }
+
+    #[test]
+    fn on_integer() {
+        let property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Integer),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("0:1".to_string()),
+            value: Some("0".to_string()),
+        };
+
+        assert_eq!(property_value_to_on(&property), Some(false));
+        let on = Property {
+            value: Some("1".to_string()),
+            ..property.clone()
+        };
+        assert_eq!(property_value_to_on(&on), Some(true));
+        // Any non-zero value is on.
+        let on = Property {
+            value: Some("255".to_string()),
+            ..property.clone()
+        };
+        assert_eq!(property_value_to_on(&on), Some(true));
+        assert_eq!(on_to_property_value(&property, true), Some("1".to_string()));
+        assert_eq!(
+            on_to_property_value(&property, false),
+            Some("0".to_string())
+        );
+    }
+
+    #[test]
+    fn on_enum_without_on_value() {
+        let property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Enum),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("low,high".to_string()),
+            value: Some("low".to_string()),
+        };
+
+        assert_eq!(property_value_to_on(&property), None);
+        assert_eq!(on_to_property_value(&property, true), None);
+    }
+
+    #[test]
+    fn state_includes_color() {
+        let on_property = Property {
+            id: "on".to_string(),
+            name: Some("On".to_string()),
+            datatype: Some(Datatype::Boolean),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some("true".to_string()),
+        };
+        let color_property = Property {
+            id: "color".to_string(),
+            name: Some("Colour".to_string()),
+            datatype: Some(Datatype::Color),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: Some("rgb".to_string()),
+            value: Some("255,255,0".to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: [on_property, color_property]
+                .into_iter()
+                .map(|property| (property.id.clone(), property))
+                .collect(),
+        };
+
+        let device = device(node.clone());
+
+        assert_eq!(
+            homie_node_to_state(&device, &node, true, &Devices::default()),
+            response::State {
+                online: true,
+                on: Some(true),
+                color: Some(query::response::Color::SpectrumRgb(0xffff00)),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn state_includes_diagnostic_sensors() {
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: HashMap::new(),
+        };
+        let device = Device {
+            stats_cputemp: Some(48.5),
+            stats_cpuload: Some(12),
+            ..device(node.clone())
+        };
+        let config = Devices {
+            diagnostic_sensors: true,
+            ..Devices::default()
+        };
+
+        assert_eq!(
+            homie_node_to_state(&device, &node, true, &Devices::default()),
+            response::State {
+                online: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            homie_node_to_state(&device, &node, true, &config),
+            response::State {
+                online: true,
+                current_sensor_state_data: Some(vec![
+                    response::CurrentSensorStateData {
+                        name: "CpuTemperature".to_string(),
+                        current_sensor_state: None,
+                        raw_value: Some(48.5),
+                    },
+                    response::CurrentSensorStateData {
+                        name: "CpuLoad".to_string(),
+                        current_sensor_state: None,
+                        raw_value: Some(12.0),
+                    },
+                ]),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn state_includes_humidity_setpoint() {
+        let property = |id: &str, value: &str| Property {
+            id: id.to_string(),
+            name: None,
+            datatype: Some(Datatype::Float),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some(value.to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: [
+                property("temperature", "24.5"),
+                property("humidity", "61.6"),
+                property("target-humidity", "45"),
+            ]
+            .into_iter()
+            .map(|property| (property.id.clone(), property))
+            .collect(),
+        };
+
+        assert_eq!(
+            homie_node_to_state(&device(node.clone()), &node, true, &Devices::default()),
+            response::State {
+                online: true,
+                thermostat_temperature_ambient: Some(24.5),
+                thermostat_humidity_ambient: Some(61.6),
+                humidity_setpoint_percent: Some(45),
+                humidity_ambient_percent: Some(62),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn state_with_climate_sensors() {
+        let property = |id: &str, value: &str| Property {
+            id: id.to_string(),
+            name: None,
+            datatype: Some(Datatype::Float),
+            settable: false,
+            retained: true,
+            unit: None,
+            format: None,
+            value: Some(value.to_string()),
+        };
+        let node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: [property("temperature", "19.5"), property("humidity", "55")]
+                .into_iter()
+                .map(|property| (property.id.clone(), property))
+                .collect(),
+        };
+        let config = Devices {
+            temperature_trait: TemperatureTrait::SensorState,
+            ..Devices::default()
+        };
+
+        assert_eq!(
+            homie_node_to_state(&device(node.clone()), &node, true, &config),
+            response::State {
+                online: true,
+                current_sensor_state_data: Some(vec![
+                    response::CurrentSensorStateData {
+                        name: "Temperature".to_string(),
+                        current_sensor_state: None,
+                        raw_value: Some(19.5),
+                    },
+                    response::CurrentSensorStateData {
+                        name: "Humidity".to_string(),
+                        current_sensor_state: None,
+                        raw_value: Some(55.0),
+                    },
+                ]),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn property_name_candidates() {
+        let property = |id: &str, datatype: Datatype, value: &str| Property {
+            id: id.to_string(),
+            name: None,
+            datatype: Some(datatype),
+            settable: true,
+            retained: true,
+            unit: None,
+            format: (datatype == Datatype::Integer).then(|| "0:100".to_string()),
+            value: Some(value.to_string()),
+        };
+        let node = |properties: Vec<Property>| Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: properties
+                .into_iter()
+                .map(|property| (property.id.clone(), property))
+                .collect(),
+        };
+        let config = Devices {
+            property_names: PropertyNames {
+                on: Some(vec!["power".to_string(), "switch".to_string()]),
+                brightness: Some(vec!["level".to_string(), "dim".to_string()]),
+                ..Default::default()
+            },
+            ..Devices::default()
+        };
+        let state = |node: Node| {
+            let state = homie_node_to_state(&device(node.clone()), &node, true, &config);
+            (state.on, state.brightness)
+        };
+
+        assert_eq!(
+            state(node(vec![
+                property("power", Datatype::Boolean, "true"),
+                property("level", Datatype::Integer, "30"),
+            ])),
+            (Some(true), Some(30))
+        );
+        assert_eq!(
+            state(node(vec![
+                property("switch", Datatype::Boolean, "false"),
+                property("dim", Datatype::Integer, "70"),
+            ])),
+            (Some(false), Some(70))
+        );
+        // Earlier candidates take priority.
+        assert_eq!(
+            state(node(vec![
+                property("switch", Datatype::Boolean, "false"),
+                property("power", Datatype::Boolean, "true"),
+            ])),
+            (Some(true), None)
+        );
+        // The standard name isn't used when candidates are configured for the role.
+        assert_eq!(
+            state(node(vec![property("on", Datatype::Boolean, "true")])),
+            (None, None)
+        );
+        // Roles without candidates still use the standard name.
+        let color_temperature = node(vec![property("color-temperature", Datatype::Integer, "50")]);
+        assert_eq!(
+            role_property(&color_temperature, &config, PropertyRole::ColorTemperature)
+                .map(|property| property.id.as_str()),
+            Some("color-temperature")
+        );
+    }
+
+    #[test]
+    fn state_includes_energy() {
+        let node = |format: Option<&str>| Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: [(
+                "energy".to_string(),
+                Property {
+                    id: "energy".to_string(),
+                    name: Some("Energy".to_string()),
+                    datatype: Some(Datatype::Float),
+                    settable: false,
+                    retained: true,
+                    unit: Some("kWh".to_string()),
+                    format: format.map(ToOwned::to_owned),
+                    value: Some("4.5".to_string()),
+                },
+            )]
+            .into_iter()
+            .collect(),
+        };
+        let config = Devices {
+            energy_property: Some("energy".to_string()),
+            ..Devices::default()
+        };
+
+        let counter = node(None);
+        assert_eq!(
+            homie_node_to_state(
+                &device(counter.clone()),
+                &counter,
+                true,
+                &Devices::default()
+            ),
+            response::State {
+                online: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            homie_node_to_state(&device(counter.clone()), &counter, true, &config),
+            response::State {
+                online: true,
+                capacity_remaining: Some(vec![response::CapacityValue {
+                    raw_value: 4.5,
+                    unit: "KILOWATT_HOURS".to_string(),
+                }]),
+                ..Default::default()
+            }
+        );
+
+        // With a range, a descriptive level is reported too.
+        let battery = node(Some("0:10"));
+        assert_eq!(
+            homie_node_to_state(&device(battery.clone()), &battery, true, &config),
+            response::State {
+                online: true,
+                descriptive_capacity_remaining: Some("MEDIUM".to_string()),
+                capacity_remaining: Some(vec![response::CapacityValue {
+                    raw_value: 4.5,
+                    unit: "KILOWATT_HOURS".to_string(),
+                }]),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn on_from_brightness() {
+        let node = |brightness: &str| Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: [(
+                "brightness".to_string(),
+                Property {
+                    id: "brightness".to_string(),
+                    name: Some("Brightness".to_string()),
+                    datatype: Some(Datatype::Integer),
+                    settable: true,
+                    retained: true,
+                    unit: None,
+                    format: Some("0:100".to_string()),
+                    value: Some(brightness.to_string()),
+                },
+            )]
+            .into_iter()
+            .collect(),
+        };
+
+        let off = node("0");
+        let state = homie_node_to_state(&device(off.clone()), &off, true, &Devices::default());
+        assert_eq!(state.brightness, Some(0));
+        assert_eq!(state.on, Some(false));
+
+        let on = node("42");
+        let state = homie_node_to_state(&device(on.clone()), &on, true, &Devices::default());
+        assert_eq!(state.brightness, Some(42));
+        assert_eq!(state.on, Some(true));
+    }
+
+    #[test]
+    fn on_property_preferred_over_brightness() {
+        let mut node = Node {
+            id: "node".to_string(),
+            name: Some("Node name".to_string()),
+            node_type: None,
+            properties: HashMap::new(),
+        };
+        node.properties.insert(
+            "on".to_string(),
+            Property {
+                id: "on".to_string(),
+                name: Some("On".to_string()),
+                datatype: Some(Datatype::Boolean),
+                settable: true,
+                retained: true,
+                unit: None,
+                format: None,
+                value: Some("false".to_string()),
+            },
+        );
+        node.properties.insert(
+            "brightness".to_string(),
+            Property {
+                id: "brightness".to_string(),
+                name: Some("Brightness".to_string()),
+                datatype: Some(Datatype::Integer),
+                settable: true,
+                retained: true,
+                unit: None,
+                format: Some("0:100".to_string()),
+                value: Some("42".to_string()),
+            },
+        );
+
+        let state = homie_node_to_state(&device(node.clone()), &node, true, &Devices::default());
+        assert_eq!(state.brightness, Some(42));
+        assert_eq!(state.on, Some(false));
+    }
+
+    fn device(node: Node) -> Device {
+        Device {
+            id: "device".to_string(),
+            homie_version: "4.0".to_string(),
+            name: Some("Device name".to_string()),
+            state: State::Ready,
+            implementation: None,
+            nodes: [(node.id.clone(), node)].into_iter().collect(),
+            extensions: vec![],
+            local_ip: None,
+            mac: None,
+            firmware_name: None,
+            firmware_version: None,
+            stats_interval: None,
+            stats_uptime: None,
+            stats_signal: None,
+            stats_cputemp: None,
+            stats_cpuload: None,
+            stats_battery: None,
+            stats_freeheap: None,
+            stats_supply: None,
+        }
+    }
 }
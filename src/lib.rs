@@ -10,42 +10,178 @@
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU General Public License for more details.
 
+mod admin;
+mod audit;
+mod client_ip;
 pub mod config;
+pub mod discovery;
 mod extractors;
 mod fulfillment;
+mod health;
 pub mod homegraph;
 pub mod homie;
 pub mod json_prost;
+pub mod listener;
 mod oauth;
 mod ratelimit;
+mod retry;
+pub mod selftest;
+pub mod tls;
 mod types;
 
+use crate::fulfillment::sync_cache::SyncCache;
+use crate::homegraph::HomeGraphClient;
+use crate::homie::device_cache::DeviceStateCache;
+use crate::homie::device_times::DeviceTimes;
+use crate::homie::filter::DeviceFilter;
+use crate::oauth::token::UsedAuthorizationCodes;
+use crate::ratelimit::KeyedRateLimiter;
 use crate::types::user;
-use axum::routing::{get, post};
+use axum::handler::Handler;
+use axum::response::Redirect;
+use axum::routing::{get, post, MethodFilter};
 use axum::{AddExtensionLayer, Router};
-use config::server::Config;
-use homie_controller::HomieController;
-use http::{Request, Response};
+use config::server::{Config, PlainHttp, Tls};
+use homie_controller::{Device, HomieController};
+use http::uri::{Authority, PathAndQuery};
+use http::{header, HeaderMap, Request, Response, StatusCode, Uri};
 use hyper::Body;
 use std::collections::HashMap;
+use std::env;
+use std::sync::atomic::AtomicBool;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tower::util::AndThenLayer;
+use tower_http::compression::CompressionLayer;
 use tower_http::trace::TraceLayer;
 use tracing::{debug, debug_span, Span};
 
-async fn health_check() -> &'static str {
-    "I'm alive!"
-}
+/// If this environment variable is set, an unauthenticated `/fulfillment/test` endpoint is enabled
+/// for development.
+pub const DEV_FULFILLMENT_ENV: &str = "HOMIEFLOW_DEV_FULFILLMENT";
 
 #[derive(Clone)]
 pub struct State {
     pub config: Arc<Config>,
     pub homie_controllers: Arc<HashMap<user::ID, Arc<HomieController>>>,
+    pub fulfillment_rate_limiter: Option<Arc<KeyedRateLimiter<user::ID>>>,
+    /// When each Homie device was last heard from, for each user with a Homie controller.
+    pub last_seen: Arc<HashMap<user::ID, Arc<DeviceTimes>>>,
+    /// When each Homie device was first asked about by Google Home, for each user with a Homie
+    /// controller.
+    pub first_seen: Arc<HashMap<user::ID, Arc<DeviceTimes>>>,
+    /// When each Homie device stopped being online, for the offline grace period, for each user
+    /// with a Homie controller.
+    pub offline_since: Arc<HashMap<user::ID, Arc<DeviceTimes>>>,
+    /// Whether each user's Homie controller is currently connected to its MQTT broker.
+    pub mqtt_connected: Arc<HashMap<user::ID, Arc<AtomicBool>>>,
+    /// The filtered Homie devices for each user with a Homie controller, as of the last request
+    /// which needed them.
+    pub device_cache: Arc<HashMap<user::ID, Arc<DeviceStateCache>>>,
+    /// Which Homie nodes to expose to Google Home, compiled from the config.
+    pub device_filter: Arc<DeviceFilter>,
+    /// Authorization codes which have already been exchanged, if they can only be used once.
+    pub used_authorization_codes: Arc<UsedAuthorizationCodes>,
+    /// When the server started, for the fulfillment startup grace period.
+    pub started_at: Instant,
+    /// Recent SYNC responses for each user.
+    pub sync_cache: Arc<SyncCache>,
+    /// Clients for the Google Home Graph API, for each user whose Google project is configured.
+    pub home_graph_clients: Arc<HashMap<user::ID, HomeGraphClient>>,
+}
+
+impl State {
+    pub fn new(config: Config, homie_controllers: HashMap<user::ID, Arc<HomieController>>) -> Self {
+        let fulfillment_rate_limiter = config.fulfillment.rate_limit.as_ref().map(|rate_limit| {
+            Arc::new(KeyedRateLimiter::new(
+                rate_limit.burst,
+                rate_limit.requests_per_minute,
+            ))
+        });
+        let last_seen = homie_controllers
+            .keys()
+            .map(|user_id| (*user_id, Arc::new(DeviceTimes::default())))
+            .collect();
+        let first_seen = homie_controllers
+            .keys()
+            .map(|user_id| (*user_id, Arc::new(DeviceTimes::default())))
+            .collect();
+        let offline_since = homie_controllers
+            .keys()
+            .map(|user_id| (*user_id, Arc::new(DeviceTimes::default())))
+            .collect();
+        let mqtt_connected = homie_controllers
+            .keys()
+            .map(|user_id| (*user_id, Arc::new(AtomicBool::new(false))))
+            .collect();
+        let device_cache = homie_controllers
+            .keys()
+            .map(|user_id| (*user_id, Arc::new(DeviceStateCache::default())))
+            .collect();
+        let device_filter = DeviceFilter::new(&config.devices.include, &config.devices.exclude)
+            .expect("Device filter patterns should have been validated");
+        Self {
+            config: Arc::new(config),
+            homie_controllers: Arc::new(homie_controllers),
+            fulfillment_rate_limiter,
+            last_seen: Arc::new(last_seen),
+            first_seen: Arc::new(first_seen),
+            offline_since: Arc::new(offline_since),
+            mqtt_connected: Arc::new(mqtt_connected),
+            device_cache: Arc::new(device_cache),
+            device_filter: Arc::new(device_filter),
+            used_authorization_codes: Default::default(),
+            started_at: Instant::now(),
+            sync_cache: Default::default(),
+            home_graph_clients: Default::default(),
+        }
+    }
+
+    /// Sets the Home Graph client to use for each user, for requests which need it.
+    pub fn with_home_graph_clients(
+        self,
+        home_graph_clients: HashMap<user::ID, HomeGraphClient>,
+    ) -> Self {
+        Self {
+            home_graph_clients: Arc::new(home_graph_clients),
+            ..self
+        }
+    }
+
+    /// Returns the given user's Homie devices which are exposed to Google Home, or `None` if the
+    /// user doesn't have a Homie controller.
+    pub fn devices(&self, user_id: &user::ID) -> Option<Arc<HashMap<String, Device>>> {
+        let homie_controller = self.homie_controllers.get(user_id)?;
+        let device_cache = self.device_cache.get(user_id)?;
+        Some(device_cache.devices(homie_controller.devices(), &self.device_filter))
+    }
+
+    /// Returns whether the server is still within the configured startup grace period, and no Homie
+    /// controller has discovered any devices yet. If there are no Homie controllers at all then
+    /// there is nothing to wait for.
+    pub fn in_startup_grace(&self) -> bool {
+        if let Some(startup_grace_seconds) = self.config.fulfillment.startup_grace_seconds {
+            self.started_at.elapsed() < Duration::from_secs(startup_grace_seconds)
+                && !self.homie_controllers.is_empty()
+                && self
+                    .homie_controllers
+                    .values()
+                    .all(|controller| controller.devices().is_empty())
+        } else {
+            false
+        }
+    }
 }
 
 pub fn app(state: State) -> Router<hyper::Body> {
-    Router::new()
-        .route("/health_check", get(health_check))
+    let base_path = state.config.network.base_path.clone();
+    let compression = state.config.network.compression;
+    let router = Router::new()
+        .route("/health_check", get(health::liveness))
+        .route("/health_check/detailed", get(health::detailed))
+        .route("/admin/config", get(admin::config))
+        .route("/admin/refresh", post(admin::refresh))
+        .route("/debug/device/*id", get(fulfillment::handle_debug_device))
         .nest(
             "/oauth",
             Router::new()
@@ -53,26 +189,468 @@ pub fn app(state: State) -> Router<hyper::Body> {
                 .route("/google_login", post(oauth::google_login::handle))
                 .route("/token", post(oauth::token::handle)),
         )
-        .nest(
-            "/fulfillment",
-            Router::new().route("/google-home", post(fulfillment::handle)),
+        .nest("/fulfillment", fulfillment_router())
+        .layer(AndThenLayer::new(extractors::json_error_response))
+        .layer(AddExtensionLayer::new(state));
+    let router = if compression {
+        router.layer(CompressionLayer::new())
+    } else {
+        router
+    };
+    let router = router.layer(
+        TraceLayer::new_for_http()
+            .make_span_with(|request: &Request<Body>| {
+                debug_span!(
+                    "Request",
+                    status_code = tracing::field::Empty,
+                    ms = tracing::field::Empty,
+                    path = tracing::field::display(request.uri().path()),
+                )
+            })
+            .on_response(|response: &Response<_>, latency: Duration, span: &Span| {
+                span.record("status_code", &tracing::field::display(response.status()));
+                span.record("ms", &tracing::field::display(latency.as_millis()));
+
+                debug!("response processed")
+            }),
+    );
+    if let Some(base_path) = base_path {
+        Router::new().nest(&base_path, router)
+    } else {
+        router
+    }
+}
+
+/// Returns the app to serve on the plain HTTP listener, or `None` if it shouldn't be started.
+///
+/// This is the main app unless TLS is configured to redirect plain HTTP requests to HTTPS or to
+/// disable plain HTTP entirely.
+pub fn plain_http_app(state: State) -> Option<Router<hyper::Body>> {
+    match &state.config.tls {
+        Some(Tls {
+            plain_http: PlainHttp::Redirect,
+            port,
+            ..
+        }) => Some(https_redirect_app(*port)),
+        Some(Tls {
+            plain_http: PlainHttp::Disabled,
+            ..
+        }) => None,
+        _ => Some(app(state)),
+    }
+}
+
+/// Returns an app which redirects all requests to the same host and path over HTTPS on the given
+/// port.
+fn https_redirect_app(https_port: u16) -> Router<hyper::Body> {
+    Router::new().fallback(
+        (move |headers: HeaderMap, uri: Uri| https_redirect(headers, uri, https_port))
+            .into_service(),
+    )
+}
+
+async fn https_redirect(
+    headers: HeaderMap,
+    uri: Uri,
+    https_port: u16,
+) -> Result<Redirect, StatusCode> {
+    let host = headers
+        .get(header::HOST)
+        .and_then(|host| host.to_str().ok())
+        .and_then(|host| host.parse::<Authority>().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let authority = if https_port == 443 {
+        host.host().to_owned()
+    } else {
+        format!("{}:{}", host.host(), https_port)
+    };
+    let https_uri = Uri::builder()
+        .scheme("https")
+        .authority(authority)
+        .path_and_query(uri.path_and_query().map_or("/", PathAndQuery::as_str))
+        .build()
+        .map_err(|_| StatusCode::BAD_REQUEST)?;
+    Ok(Redirect::permanent(https_uri))
+}
+
+fn fulfillment_router() -> Router<hyper::Body> {
+    let router = Router::new().route(
+        "/google-home",
+        post(fulfillment::handle).on(!MethodFilter::POST, extractors::method_not_allowed),
+    );
+    if env::var_os(DEV_FULFILLMENT_ENV).is_some() {
+        tracing::warn!(
+            "{} is set, enabling unauthenticated /fulfillment/test endpoint.",
+            DEV_FULFILLMENT_ENV
+        );
+        router.route("/test", post(fulfillment::handle_test))
+    } else {
+        router
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::token::{AccessToken, AccessTokenPayload};
+    use chrono::Utc;
+    use http::{Method, StatusCode};
+    use std::str::FromStr;
+    use tower::ServiceExt;
+
+    fn test_state() -> State {
+        let config = toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+            "#,
         )
-        .layer(AddExtensionLayer::new(state))
-        .layer(
-            TraceLayer::new_for_http()
-                .make_span_with(|request: &Request<Body>| {
-                    debug_span!(
-                        "Request",
-                        status_code = tracing::field::Empty,
-                        ms = tracing::field::Empty,
-                        path = tracing::field::display(request.uri().path()),
+        .unwrap();
+        State::new(config, HashMap::new())
+    }
+
+    #[tokio::test]
+    async fn base_path() {
+        let config = toml::from_str(
+            r#"
+            [network]
+            base-path = "/homieflow"
+
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+            "#,
+        )
+        .unwrap();
+        let app = app(State::new(config, HashMap::new()));
+        let get = |uri| Request::get(uri).body(Body::empty()).unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(get("/homieflow/health_check"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = app.clone().oneshot(get("/health_check")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        // Nested routes work under the prefix too.
+        let response = app
+            .oneshot(get("/homieflow/fulfillment/google-home"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    fn tls_state(plain_http: &str) -> State {
+        let config = toml::from_str(&format!(
+            r#"
+            [tls]
+            certificate = "certificate.pem"
+            private-key = "private_key.pem"
+            port = 8443
+            {}
+
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+            "#,
+            plain_http
+        ))
+        .unwrap();
+        State::new(config, HashMap::new())
+    }
+
+    fn get_health_check() -> Request<Body> {
+        Request::get("/health_check?foo=bar")
+            .header(header::HOST, "homieflow.example.com:8080")
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn plain_http_serve() {
+        for state in [
+            test_state(),
+            tls_state(""),
+            tls_state("plain-http = \"serve\""),
+        ] {
+            let response = plain_http_app(state)
+                .unwrap()
+                .oneshot(get_health_check())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+    }
+
+    #[tokio::test]
+    async fn plain_http_redirect() {
+        let response = plain_http_app(tls_state("plain-http = \"redirect\""))
+            .unwrap()
+            .oneshot(get_health_check())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PERMANENT_REDIRECT);
+        assert_eq!(
+            response.headers()[header::LOCATION],
+            "https://homieflow.example.com:8443/health_check?foo=bar"
+        );
+    }
+
+    #[tokio::test]
+    async fn plain_http_disabled() {
+        assert!(plain_http_app(tls_state("plain-http = \"disabled\"")).is_none());
+    }
+
+    async fn post_test_fulfillment() -> StatusCode {
+        let request = Request::post("/fulfillment/test?user_id=861ccceaa3e349138ce2498768dbfe09")
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(
+                r#"{"requestId": "request", "inputs": [{"intent": "action.devices.SYNC"}]}"#,
+            ))
+            .unwrap();
+        app(test_state()).oneshot(request).await.unwrap().status()
+    }
+
+    #[tokio::test]
+    async fn dev_fulfillment_endpoint() {
+        env::remove_var(DEV_FULFILLMENT_ENV);
+        assert_eq!(post_test_fulfillment().await, StatusCode::NOT_FOUND);
+
+        env::set_var(DEV_FULFILLMENT_ENV, "1");
+        let status = post_test_fulfillment().await;
+        env::remove_var(DEV_FULFILLMENT_ENV);
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn debug_device() {
+        let user_id = user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap();
+        let config = toml::from_str(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+            "#,
+        )
+        .unwrap();
+        let (controller, _event_loop) = HomieController::new(
+            rumqttc::MqttOptions::new("test", "localhost", 1883),
+            "homie",
+            rumqttc::QoS::AtLeastOnce,
+        );
+        let app = app(State::new(
+            config,
+            [(user_id, Arc::new(controller))].into_iter().collect(),
+        ));
+        let access_token = AccessToken::new(
+            b"access-key",
+            AccessTokenPayload {
+                sub: user_id,
+                exp: Utc::now() + chrono::Duration::minutes(10),
+                iss: None,
+                aud: None,
+            },
+        )
+        .unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/debug/device/device/node")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = app
+            .oneshot(
+                Request::get("/debug/device/device/node")
+                    .header(
+                        http::header::AUTHORIZATION,
+                        format!("Bearer {}", access_token),
                     )
-                })
-                .on_response(|response: &Response<_>, latency: Duration, span: &Span| {
-                    span.record("status_code", &tracing::field::display(response.status()));
-                    span.record("ms", &tracing::field::display(latency.as_millis()));
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["found"], false);
+        assert_eq!(body["online"], false);
+    }
 
-                    debug!("response processed")
-                }),
+    async fn sync_content_encoding(compression: bool) -> Option<String> {
+        let config = toml::from_str(&format!(
+            r#"
+            [network]
+            compression = {}
+
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+            "#,
+            compression
+        ))
+        .unwrap();
+        let access_token = AccessToken::new(
+            b"access-key",
+            AccessTokenPayload {
+                sub: user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap(),
+                exp: Utc::now() + chrono::Duration::minutes(10),
+                iss: None,
+                aud: None,
+            },
         )
+        .unwrap();
+        let request = Request::post("/fulfillment/google-home")
+            .header(
+                http::header::AUTHORIZATION,
+                format!("Bearer {}", access_token),
+            )
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .header(http::header::ACCEPT_ENCODING, "gzip")
+            .body(Body::from(
+                r#"{"requestId": "request", "inputs": [{"intent": "action.devices.SYNC"}]}"#,
+            ))
+            .unwrap();
+        let response = app(State::new(config, HashMap::new()))
+            .oneshot(request)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        response
+            .headers()
+            .get(http::header::CONTENT_ENCODING)
+            .map(|encoding| encoding.to_str().unwrap().to_owned())
+    }
+
+    #[tokio::test]
+    async fn compression() {
+        assert_eq!(sync_content_encoding(true).await.as_deref(), Some("gzip"));
+        assert_eq!(sync_content_encoding(false).await, None);
+    }
+
+    #[tokio::test]
+    async fn rejections_are_json() {
+        let app = app(test_state());
+        for (request, status, error) in [
+            (
+                Request::get("/nonexistent").body(Body::empty()).unwrap(),
+                StatusCode::NOT_FOUND,
+                "not_found",
+            ),
+            // Missing query parameters.
+            (
+                Request::get("/oauth/authorize")
+                    .body(Body::empty())
+                    .unwrap(),
+                StatusCode::BAD_REQUEST,
+                "validation",
+            ),
+            // Form body with the wrong content type.
+            (
+                Request::post("/oauth/token")
+                    .header(http::header::CONTENT_TYPE, "text/plain")
+                    .body(Body::from("grant_type=refresh_token"))
+                    .unwrap(),
+                StatusCode::BAD_REQUEST,
+                "validation",
+            ),
+            // Form body missing required fields.
+            (
+                Request::post("/oauth/token")
+                    .header(
+                        http::header::CONTENT_TYPE,
+                        "application/x-www-form-urlencoded",
+                    )
+                    .body(Body::from("grant_type=refresh_token"))
+                    .unwrap(),
+                StatusCode::BAD_REQUEST,
+                "validation",
+            ),
+        ] {
+            let uri = request.uri().clone();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.status(), status, "Unexpected status for {}", uri);
+            assert_eq!(
+                response.headers()[http::header::CONTENT_TYPE],
+                "application/json"
+            );
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"], error, "Unexpected body {} for {}", body, uri);
+        }
+    }
+
+    async fn fulfillment_request(method: Method, content_type: &str, body: &str) -> StatusCode {
+        let access_token = AccessToken::new(
+            b"access-key",
+            AccessTokenPayload {
+                sub: user::ID::from_str("861ccceaa3e349138ce2498768dbfe09").unwrap(),
+                exp: Utc::now() + chrono::Duration::minutes(10),
+                iss: None,
+                aud: None,
+            },
+        )
+        .unwrap();
+        let request = Request::builder()
+            .method(method)
+            .uri("/fulfillment/google-home")
+            .header(
+                http::header::AUTHORIZATION,
+                format!("Bearer {}", access_token),
+            )
+            .header(http::header::CONTENT_TYPE, content_type)
+            .body(Body::from(body.to_owned()))
+            .unwrap();
+        let response = app(test_state()).oneshot(request).await.unwrap();
+        let status = response.status();
+        if status != StatusCode::OK {
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            let error: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert!(error["error"].is_string(), "Unexpected body {}", error);
+        }
+        status
+    }
+
+    #[tokio::test]
+    async fn fulfillment_wrong_requests() {
+        let sync = r#"{"requestId": "request", "inputs": [{"intent": "action.devices.SYNC"}]}"#;
+
+        assert_eq!(
+            fulfillment_request(Method::POST, "application/json", sync).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            fulfillment_request(Method::GET, "application/json", sync).await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            fulfillment_request(Method::PUT, "application/json", sync).await,
+            StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(
+            fulfillment_request(Method::POST, "text/plain", sync).await,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+        assert_eq!(
+            fulfillment_request(Method::POST, "application/json", "{not json").await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            fulfillment_request(Method::POST, "application/json", r#"{"inputs": []}"#).await,
+            StatusCode::BAD_REQUEST
+        );
+    }
 }
@@ -0,0 +1,102 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+//! Binding of the sockets which the HTTP server listens on.
+
+use crate::config::server::Network;
+use socket2::{Domain, Socket, Type};
+use std::io;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener};
+
+const BACKLOG: i32 = 1024;
+
+/// Returns the addresses which the HTTP server should listen on.
+///
+/// If dual-stack is enabled and the configured address is unspecified (`0.0.0.0` or `::`) then
+/// both the IPv4 and IPv6 unspecified addresses are returned, so that both stacks can be bound
+/// separately. Otherwise only the configured address is returned.
+pub fn listen_addresses(network: &Network) -> Vec<SocketAddr> {
+    if network.dual_stack && network.address.is_unspecified() {
+        vec![
+            SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), network.port),
+            SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), network.port),
+        ]
+    } else {
+        vec![SocketAddr::new(network.address, network.port)]
+    }
+}
+
+/// Binds a TCP listener to the given address.
+///
+/// If `only_v6` is true then IPv6 sockets are set not to accept IPv4-mapped connections, so that a
+/// separate IPv4 socket can be bound to the same port. Otherwise the platform default is used.
+pub fn bind(address: SocketAddr, only_v6: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(address), Type::STREAM, None)?;
+    if address.is_ipv6() && only_v6 {
+        socket.set_only_v6(true)?;
+    }
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(BACKLOG)?;
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::IpAddr;
+
+    fn network(address: IpAddr, dual_stack: bool) -> Network {
+        Network {
+            address,
+            port: 6001,
+            dual_stack,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn single_stack() {
+        assert_eq!(
+            listen_addresses(&network(Ipv6Addr::UNSPECIFIED.into(), false)),
+            vec!["[::]:6001".parse().unwrap()]
+        );
+        assert_eq!(
+            listen_addresses(&network(Ipv4Addr::LOCALHOST.into(), true)),
+            vec!["127.0.0.1:6001".parse().unwrap()]
+        );
+    }
+
+    #[test]
+    fn dual_stack() {
+        let expected: Vec<SocketAddr> = vec![
+            "0.0.0.0:6001".parse().unwrap(),
+            "[::]:6001".parse().unwrap(),
+        ];
+        assert_eq!(
+            listen_addresses(&network(Ipv4Addr::UNSPECIFIED.into(), true)),
+            expected
+        );
+        assert_eq!(
+            listen_addresses(&network(Ipv6Addr::UNSPECIFIED.into(), true)),
+            expected
+        );
+    }
+
+    #[test]
+    fn bind_v6_only() {
+        let listener = bind("[::1]:0".parse().unwrap(), true).unwrap();
+        let socket = Socket::from(listener);
+        assert!(socket.only_v6().unwrap());
+    }
+}
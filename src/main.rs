@@ -10,33 +10,54 @@
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU General Public License for more details.
 
-use axum_server::tls_rustls::RustlsConfig;
-use homie_controller::HomieController;
+use futures_util::future::{select_all, FutureExt, LocalBoxFuture, TryFutureExt};
 use homieflow::config::server::Config;
 use homieflow::config::Config as _;
 use homieflow::config::Error as ConfigError;
+use homieflow::discovery;
 use homieflow::homegraph::HomeGraphClient;
-use homieflow::homie::get_mqtt_options;
+use homieflow::homie::connect_users;
+use homieflow::homie::mirror::StateMirror;
 use homieflow::homie::spawn_homie_poller;
+use homieflow::homie::spawn_startup_report;
+use homieflow::homie::PollerUser;
+use homieflow::listener;
+use homieflow::selftest;
 use rustls::ClientConfig;
 use std::collections::HashMap;
 use std::env;
+use std::error::Error;
 use std::io;
 use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
-use tokio::select;
 use tracing::{debug, error, info};
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() -> Result<(), Box<dyn Error>> {
     const HIDE_TIMESTAMP_ENV: &str = "HOMIEFLOW_HIDE_TIMESTAMP";
 
+    if let Some(subcommand) = env::args().nth(1) {
+        match subcommand.as_str() {
+            "config-schema" => {
+                println!("{}", Config::json_schema());
+                return Ok(());
+            }
+            "selftest" => {
+                let report = selftest::run(&config_path(), get_tls_client_config()).await;
+                println!("{}", report);
+                std::process::exit(if report.passed() { 0 } else { 1 });
+            }
+            _ => {
+                eprintln!("Unknown subcommand {:?}", subcommand);
+                std::process::exit(1);
+            }
+        }
+    }
+
     homieflow::config::init_logging(env::var_os(HIDE_TIMESTAMP_ENV).is_some());
-    let config_path = env::var("HOMIEFLOW_CONFIG")
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| Config::default_path());
+    let config_path = config_path();
 
     debug!("Config path: {:?}", config_path);
 
@@ -52,72 +73,153 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         Err(err) => panic!("Config error: {}", err),
     };
     debug!("Config: {:#?}", config);
+    info!("Starting homieflow: {}", config.summary());
 
-    let home_graph_client;
-    let request_sync_rate_limit;
-    if let Some(google) = &config.google {
-        home_graph_client = Some(HomeGraphClient::connect(&google.credentials_file).await?);
-        request_sync_rate_limit = Duration::from_secs(google.request_sync_rate_limit_seconds);
-    } else {
-        home_graph_client = None;
-        // This value doesn't really matter, so just use a high number to avoid wasting time.
-        request_sync_rate_limit = Duration::from_secs(1000);
+    // Connect to Home Graph once for each distinct credentials file, and share the client between
+    // all users of the same Google project.
+    let mut credentials_clients = HashMap::new();
+    for google in config.google_configs() {
+        if !credentials_clients.contains_key(&google.credentials_file) {
+            let client = HomeGraphClient::connect(&google.credentials_file).await?;
+            credentials_clients.insert(google.credentials_file.clone(), client);
+        }
     }
-    let mut homie_controllers = HashMap::new();
-    let mut join_handles = Vec::new();
+    let home_graph_clients = config
+        .users
+        .iter()
+        .filter_map(|user| {
+            let google = config.get_google(&user.id)?;
+            Some((
+                user.id,
+                credentials_clients[&google.credentials_file].clone(),
+            ))
+        })
+        .collect();
     let tls_client_config = get_tls_client_config();
-    for user in &config.users {
-        if let Some(homie_config) = &user.homie {
-            let mqtt_options = get_mqtt_options(
-                homie_config,
-                if homie_config.use_tls {
-                    Some(tls_client_config.clone())
-                } else {
-                    None
-                },
+    let connections = connect_users(&config.users, &tls_client_config)?;
+    let homie_controllers = connections
+        .iter()
+        .flat_map(|connection| {
+            connection.users.iter().map(|(user_id, homie_config)| {
+                (
+                    *user_id,
+                    connection.controller(&homie_config.homie_prefix).clone(),
+                )
+            })
+        })
+        .collect();
+
+    let state = homieflow::State::new(config, homie_controllers)
+        .with_home_graph_clients(home_graph_clients);
+
+    let mut join_handles = Vec::new();
+    let user_count = connections
+        .iter()
+        .map(|connection| connection.users.len())
+        .sum();
+    let mut index = 0;
+    for connection in connections {
+        let mut poller_users = Vec::new();
+        for (user_id, homie_config) in &connection.users {
+            let user_id = *user_id;
+            let homie_config = homie_config.clone();
+            let google = state.config.get_google(&user_id);
+            let home_graph_client = state.home_graph_clients.get(&user_id).cloned();
+            let startup_report_delay =
+                google.and_then(|google| google.startup_report_delay(index, user_count));
+            index += 1;
+            // Without Google this value doesn't really matter, so just use a high number to avoid
+            // wasting time.
+            let request_sync_rate_limit = Duration::from_secs(
+                google.map_or(1000, |google| google.request_sync_rate_limit_seconds),
             );
-            let (controller, event_loop) =
-                HomieController::new(mqtt_options, &homie_config.homie_prefix);
-            let controller = Arc::new(controller);
-
-            let handle = spawn_homie_poller(
-                controller.clone(),
-                event_loop,
-                home_graph_client.clone(),
-                user.id,
-                homie_config.reconnect_interval,
-                request_sync_rate_limit,
+            let state_mirror = StateMirror::from_config(
+                &homie_config,
+                connection.controller(&homie_config.homie_prefix),
             );
-            join_handles.push(handle);
-            homie_controllers.insert(user.id, controller);
+            if let (Some(home_graph_client), Some(delay)) =
+                (&home_graph_client, startup_report_delay)
+            {
+                join_handles.push(spawn_startup_report(
+                    connection.controller(&homie_config.homie_prefix).clone(),
+                    home_graph_client.clone(),
+                    state_mirror.clone(),
+                    state.config.get_agent_user_id(&user_id),
+                    homie_config.clone(),
+                    state.config.devices.clone(),
+                    state.device_filter.clone(),
+                    state.last_seen[&user_id].clone(),
+                    state.offline_since[&user_id].clone(),
+                    delay,
+                ));
+            }
+
+            poller_users.push(PollerUser {
+                user_id,
+                agent_user_id: state.config.get_agent_user_id(&user_id),
+                homie_config,
+                home_graph_client,
+                state_mirror,
+                request_sync_rate_limit,
+                last_seen: state.last_seen[&user_id].clone(),
+                offline_since: state.offline_since[&user_id].clone(),
+                mqtt_connected: state.mqtt_connected[&user_id].clone(),
+            });
         }
+
+        join_handles.push(spawn_homie_poller(
+            connection.controllers,
+            connection.event_loop,
+            poller_users,
+            state.config.devices.clone(),
+            state.device_filter.clone(),
+        ));
     }
 
-    let state = homieflow::State {
-        config: Arc::new(config),
-        homie_controllers: Arc::new(homie_controllers),
-    };
+    if state.config.oauth.one_time_authorization_codes {
+        join_handles.push(state.used_authorization_codes.clone().spawn_sweeper(
+            Duration::from_secs(state.config.oauth.used_code_sweep_interval_seconds),
+        ));
+    }
 
-    let address = SocketAddr::new(state.config.network.address, state.config.network.port);
+    // Keep the daemon alive until the servers stop.
+    let _mdns_daemon = discovery::advertise(&state.config)?;
 
-    let fut = axum_server::bind(address).serve(homieflow::app(state.clone()).into_make_service());
-    info!("Starting server at {}", address);
+    let mut servers: Vec<LocalBoxFuture<Result<(), Box<dyn Error>>>> = Vec::new();
+    if let Some(plain_http_app) = homieflow::plain_http_app(state.clone()) {
+        for address in listener::listen_addresses(&state.config.network) {
+            let tcp_listener = listener::bind(address, state.config.network.dual_stack)?;
+            let server = axum::Server::from_tcp(tcp_listener)?.serve(
+                plain_http_app
+                    .clone()
+                    .into_make_service_with_connect_info::<SocketAddr, _>(),
+            );
+            info!("Starting server at {}", address);
+            servers.push(server.err_into().boxed_local());
+        }
+    } else {
+        info!("Not starting plain HTTP server, as it is disabled in the TLS config");
+    }
     if let Some(tls) = &state.config.tls {
         let tls_address = SocketAddr::new(tls.address, tls.port);
-        let tls_config = RustlsConfig::from_pem_file(&tls.certificate, &tls.private_key).await?;
-        let tls_fut = axum_server::bind_rustls(tls_address, tls_config)
-            .serve(homieflow::app(state).into_make_service());
+        let tls_config = homieflow::tls::load_rustls_config(tls).await?;
+        let tls_server = axum_server::bind_rustls(tls_address, tls_config).serve(
+            homieflow::app(state.clone()).into_make_service_with_connect_info::<SocketAddr, _>(),
+        );
         info!("Starting TLS server at {}", tls_address);
-
-        select! {
-            val = fut => val?,
-            val = tls_fut => val?
-        };
-    } else {
-        fut.await?;
+        servers.push(tls_server.err_into().boxed_local());
     }
 
-    Ok(())
+    // Run until any of the servers stops.
+    let (result, _, _) = select_all(servers).await;
+    result
+}
+
+/// Returns the path of the config file to use, from the environment or the default.
+fn config_path() -> PathBuf {
+    env::var("HOMIEFLOW_CONFIG")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| Config::default_path())
 }
 
 fn get_tls_client_config() -> Arc<ClientConfig> {
@@ -10,10 +10,10 @@
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU General Public License for more details.
 
-use super::verify_redirect_uri;
+use super::request_base_url;
+use super::verify_oauth_query;
 use super::AuthorizationRequestQuery;
 use crate::types::errors::InternalError;
-use crate::types::errors::OAuthError;
 use crate::types::errors::ServerError;
 use crate::State;
 use askama::Template;
@@ -39,22 +39,16 @@ pub async fn handle(
     Query(request): Query<AuthorizationRequestQuery>,
     headers: HeaderMap,
 ) -> Result<Html<String>, ServerError> {
-    let google_config = state
-        .config
-        .google
-        .as_ref()
-        .ok_or_else(|| InternalError::Other("Google Home API not configured".to_string()))?;
-    if *request.client_id != *google_config.client_id {
-        return Err(OAuthError::InvalidClient(Some(String::from("invalid client id"))).into());
+    if state.config.google_configs().next().is_none() {
+        return Err(InternalError::Other("Google Home API not configured".to_string()).into());
     }
-    verify_redirect_uri(&request.redirect_uri, &google_config.project_id)
-        .map_err(|err| OAuthError::InvalidRequest(Some(err.to_string())))?;
+    verify_oauth_query(&request, &state.config)?;
 
     let template = AuthorizeTemplate {
         client_id: request.client_id.to_owned(),
         redirect_uri: request.redirect_uri.to_owned(),
         state: request.state.to_owned(),
-        base_url: state.config.get_base_url(),
+        base_url: request_base_url(&state.config, &headers),
         google_login_client_id: state
             .config
             .logins
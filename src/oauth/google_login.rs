@@ -33,6 +33,9 @@ pub struct Request {
     g_csrf_token: String,
 }
 
+/// Issuers which Google may use for its ID tokens.
+const GOOGLE_ISSUERS: [&str; 2] = ["accounts.google.com", "https://accounts.google.com"];
+
 #[derive(Debug, Serialize, Deserialize)]
 struct TokenClaims {
     pub email: String,
@@ -54,18 +57,16 @@ pub async fn handle(
     if request.g_csrf_token != cookies.get("g_csrf_token").unwrap_or("") {
         return Err(AuthError::InvalidCsrfToken.into());
     }
-    let google_config = state
-        .config
-        .google
-        .as_ref()
-        .ok_or_else(|| InternalError::Other("Google Home API not configured".to_string()))?;
+    if state.config.google_configs().next().is_none() {
+        return Err(InternalError::Other("Google Home API not configured".to_string()).into());
+    }
     let google_login_config = state
         .config
         .logins
         .google
         .as_ref()
         .ok_or_else(|| InternalError::Other("Google login not configured".to_string()))?;
-    verify_oauth_query(&query, google_config)?;
+    let google_config = verify_oauth_query(&query, &state.config)?;
 
     // Validate JWT and parse claims.
     // See https://developers.google.com/identity/gsi/web/guides/verify-google-id-token
@@ -74,16 +75,93 @@ pub async fn handle(
         .parse::<TokenClaims>(&request.credential)
         .await
         .map_err(|e| AuthError::InvalidGoogleJwt(e.to_string()))?;
+    verify_claims(&claims, &google_login_config.client_id)?;
 
     // User has successfully authenticated with Google, see if they exist in our config.
     let user = state
         .config
         .get_user_by_email(&claims.email)
         .ok_or_else(|| OAuthError::InvalidGrant(Some(String::from("user not found"))))?;
+    // Users may only link their account through the Google project configured for them.
+    if state.config.get_google(&user.id) != Some(google_config) {
+        return Err(OAuthError::InvalidClient(Some(String::from(
+            "user not allowed for this client",
+        )))
+        .into());
+    }
 
     Ok(grant_authorization_code(
         query,
         user.id,
         &state.config.secrets,
+        &state.config.oauth,
     )?)
 }
+
+/// Checks that the token was issued by Google for our client ID, so that a token issued for some
+/// other application can't be used to log in.
+fn verify_claims(claims: &TokenClaims, client_id: &str) -> Result<(), AuthError> {
+    if claims.aud != client_id {
+        return Err(AuthError::InvalidGoogleJwt(format!(
+            "Invalid audience {:?}",
+            claims.aud
+        )));
+    }
+    if !GOOGLE_ISSUERS.contains(&claims.iss.as_str()) {
+        return Err(AuthError::InvalidGoogleJwt(format!(
+            "Invalid issuer {:?}",
+            claims.iss
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(aud: &str, iss: &str) -> TokenClaims {
+        TokenClaims {
+            email: "user@example.com".to_string(),
+            email_verified: true,
+            name: "User".to_string(),
+            picture: "https://example.com/user.png".to_string(),
+            aud: aud.to_string(),
+            iss: iss.to_string(),
+            exp: 0,
+        }
+    }
+
+    #[test]
+    fn valid_claims() {
+        assert!(verify_claims(&claims("client-id", "accounts.google.com"), "client-id").is_ok());
+        assert!(verify_claims(
+            &claims("client-id", "https://accounts.google.com"),
+            "client-id"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn wrong_audience() {
+        assert!(matches!(
+            verify_claims(
+                &claims("other-client-id", "https://accounts.google.com"),
+                "client-id"
+            ),
+            Err(AuthError::InvalidGoogleJwt(_))
+        ));
+    }
+
+    #[test]
+    fn wrong_issuer() {
+        assert!(matches!(
+            verify_claims(&claims("client-id", "https://example.com"), "client-id"),
+            Err(AuthError::InvalidGoogleJwt(_))
+        ));
+        assert!(matches!(
+            verify_claims(&claims("client-id", "google.com"), "client-id"),
+            Err(AuthError::InvalidGoogleJwt(_))
+        ));
+    }
+}
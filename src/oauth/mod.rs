@@ -14,8 +14,9 @@ pub mod authorize;
 pub mod google_login;
 pub mod token;
 
+use crate::config::server::Config;
 use crate::config::server::Google;
-use crate::config::server::Secrets;
+use crate::config::server::{OAuth, Secrets};
 use crate::types::errors::OAuthError;
 use crate::types::errors::TokenError;
 use crate::types::token::AuthorizationCode;
@@ -23,6 +24,7 @@ use crate::types::token::AuthorizationCodePayload;
 use crate::types::user::ID as UserID;
 use chrono::Duration;
 use chrono::Utc;
+use http::HeaderMap;
 use serde::Deserialize;
 use serde::Serialize;
 use url::Url;
@@ -55,21 +57,48 @@ fn default_user_locale() -> String {
     String::from("en_US")
 }
 
+/// Returns the public base URL of the server for the given request.
+///
+/// If `trust-forwarded-headers` is enabled and the request came via a reverse proxy which set
+/// `X-Forwarded-Host`, this is taken from the forwarded headers. Otherwise it comes from the
+/// config.
+fn request_base_url(config: &Config, headers: &HeaderMap) -> Url {
+    let base_url = config.get_base_url();
+    if !config.network.trust_forwarded_headers {
+        return base_url;
+    }
+    let forwarded_header = |name| {
+        let value = headers.get(name)?.to_str().ok()?;
+        // A chain of proxies may each append a value; the first is from the client-facing proxy.
+        Some(value.split(',').next()?.trim().to_owned())
+    };
+    if let Some(host) = forwarded_header("x-forwarded-host") {
+        let scheme =
+            forwarded_header("x-forwarded-proto").unwrap_or_else(|| base_url.scheme().to_owned());
+        let base_path = config.network.base_path();
+        match Url::parse(&format!("{}://{}{}/", scheme, host, base_path)) {
+            Ok(url) => return url,
+            Err(e) => tracing::warn!("Invalid forwarded host {:?}: {}", host, e),
+        }
+    }
+    base_url
+}
+
 const GOOGLE_OAUTH_REDIRECT_URL: &str = "oauth-redirect.googleusercontent.com";
 const GOOGLE_SANDBOX_OAUTH_REDIRECT_URL: &str = "oauth-redirect-sandbox.googleusercontent.com";
 
-fn verify_oauth_query(
+/// Checks that the query is from one of the configured Google clients and redirects back to its
+/// project, and returns the config for the client.
+fn verify_oauth_query<'a>(
     query: &AuthorizationRequestQuery,
-    google_config: &Google,
-) -> Result<(), OAuthError> {
-    if *query.client_id != *google_config.client_id {
-        return Err(OAuthError::InvalidClient(Some(
-            "invalid client id".to_string(),
-        )));
-    }
+    config: &'a Config,
+) -> Result<&'a Google, OAuthError> {
+    let google_config = config
+        .get_google_by_client_id(&query.client_id)
+        .ok_or_else(|| OAuthError::InvalidClient(Some("invalid client id".to_string())))?;
     verify_redirect_uri(&query.redirect_uri, &google_config.project_id)
         .map_err(|err| OAuthError::InvalidRequest(Some(err.to_string())))?;
-    Ok(())
+    Ok(google_config)
 }
 
 fn verify_redirect_uri(
@@ -125,10 +154,13 @@ fn grant_authorization_code(
     query: AuthorizationRequestQuery,
     user_id: UserID,
     secrets: &Secrets,
+    oauth: &OAuth,
 ) -> Result<http::Response<axum::body::Body>, TokenError> {
     let authorization_code_payload = AuthorizationCodePayload {
         sub: user_id,
         exp: Utc::now() + Duration::minutes(10),
+        iss: oauth.issuer.clone(),
+        aud: oauth.audience.clone(),
     };
     let authorization_code = AuthorizationCode::new(
         secrets.authorization_code_key.as_bytes(),
@@ -154,6 +186,59 @@ mod tests {
     use super::*;
     use url::Url;
 
+    mod base_url {
+        use super::*;
+
+        fn config(trust_forwarded_headers: bool) -> Config {
+            toml::from_str(&format!(
+                r#"
+                [network]
+                address = "127.0.0.1"
+                port = 6001
+                trust-forwarded-headers = {}
+
+                [secrets]
+                refresh-key = "refresh-key"
+                access-key = "access-key"
+                authorization-code-key = "authorization-code-key"
+                "#,
+                trust_forwarded_headers
+            ))
+            .unwrap()
+        }
+
+        fn forwarded_headers() -> HeaderMap {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-forwarded-proto", "https".parse().unwrap());
+            headers.insert(
+                "x-forwarded-host",
+                "homieflow.example.com, proxy.internal".parse().unwrap(),
+            );
+            headers
+        }
+
+        #[test]
+        fn forwarded_headers_trusted() {
+            assert_eq!(
+                request_base_url(&config(true), &forwarded_headers()).as_str(),
+                "https://homieflow.example.com/"
+            );
+            // Without forwarded headers the config is used.
+            assert_eq!(
+                request_base_url(&config(true), &HeaderMap::new()).as_str(),
+                "http://127.0.0.1:6001/"
+            );
+        }
+
+        #[test]
+        fn forwarded_headers_ignored() {
+            assert_eq!(
+                request_base_url(&config(false), &forwarded_headers()).as_str(),
+                "http://127.0.0.1:6001/"
+            );
+        }
+    }
+
     mod redirect_uri {
         use super::*;
         const PROJECT_ID: &str = "some-project-id";
@@ -10,7 +10,8 @@
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU General Public License for more details.
 
-use crate::config::server::Google;
+use crate::config::server::Config;
+use crate::extractors::ClientIp;
 use crate::types::errors::InternalError;
 use crate::types::errors::OAuthError;
 use crate::types::errors::ServerError;
@@ -23,10 +24,16 @@ use crate::State;
 use axum::extract::Extension;
 use axum::extract::Form;
 use axum::Json;
+use chrono::DateTime;
 use chrono::Duration;
 use chrono::Utc;
 use serde::Deserialize;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+use tokio::task::{self, JoinHandle};
+use tokio::time;
 
 const GOOGLE_HOME_ACCESS_TOKEN_DURATION_MINUTES: i64 = 10;
 
@@ -74,14 +81,56 @@ pub enum TokenType {
     Bearer,
 }
 
+/// Authorization codes which have already been exchanged for tokens, with their expiry times, so
+/// that they can't be used again.
+#[derive(Debug, Default)]
+pub struct UsedAuthorizationCodes {
+    codes: Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl UsedAuthorizationCodes {
+    /// Records that the given code has been used, returning false if it had already been used.
+    pub fn use_code(&self, code: &str, expiry: DateTime<Utc>) -> bool {
+        self.codes
+            .lock()
+            .unwrap()
+            .insert(code.to_owned(), expiry)
+            .is_none()
+    }
+
+    /// Forgets codes which have expired, as they will be rejected anyway.
+    pub fn remove_expired(&self) {
+        let now = Utc::now();
+        self.codes
+            .lock()
+            .unwrap()
+            .retain(|_, code_expiry| *code_expiry > now);
+    }
+
+    /// Spawns a task which calls [`remove_expired`](Self::remove_expired) every `interval`, so that
+    /// the store doesn't grow without bound.
+    pub fn spawn_sweeper(self: Arc<Self>, interval: std::time::Duration) -> JoinHandle<()> {
+        task::spawn(async move {
+            let mut interval = time::interval(interval);
+            loop {
+                interval.tick().await;
+                self.remove_expired();
+            }
+        })
+    }
+}
+
 async fn on_refresh_token_grant(
     state: State,
     refresh_token: String,
 ) -> Result<Response, ServerError> {
-    let refresh_token =
-        RefreshToken::decode(state.config.secrets.refresh_key.as_bytes(), &refresh_token).map_err(
-            |err| OAuthError::InvalidGrant(Some(format!("invalid refresh token: {}", err))),
-        )?;
+    let refresh_token = RefreshToken::decode(
+        state.config.secrets.refresh_key.as_bytes(),
+        &refresh_token,
+        state.config.oauth.issuer.as_deref(),
+        state.config.oauth.audience.as_deref(),
+    )
+    .map_err(|err| OAuthError::InvalidGrant(Some(format!("invalid refresh token: {}", err))))?;
 
     tracing::info!(user_id = %refresh_token.claims.sub, "Refresh token grant");
 
@@ -91,6 +140,8 @@ async fn on_refresh_token_grant(
         AccessTokenPayload {
             sub: refresh_token.claims.sub,
             exp: Utc::now() + expires_in,
+            iss: state.config.oauth.issuer.clone(),
+            aud: state.config.oauth.audience.clone(),
         },
     )?;
 
@@ -103,13 +154,25 @@ async fn on_refresh_token_grant(
 }
 
 async fn on_authorization_code_grant(state: State, code: String) -> Result<Response, ServerError> {
+    let encoded_code = code;
     let code = AuthorizationCode::decode(
         state.config.secrets.authorization_code_key.as_bytes(),
-        &code,
+        &encoded_code,
+        state.config.oauth.issuer.as_deref(),
+        state.config.oauth.audience.as_deref(),
     )
     .map_err(|err| {
         OAuthError::InvalidGrant(Some(format!("invalid authorization code: {}", err)))
     })?;
+    if state.config.oauth.one_time_authorization_codes
+        && !state
+            .used_authorization_codes
+            .use_code(&encoded_code, code.claims.exp)
+    {
+        return Err(
+            OAuthError::InvalidGrant(Some("authorization code already used".to_string())).into(),
+        );
+    }
 
     tracing::info!(user_id = %code.claims.sub, "Authorization code grant");
 
@@ -119,6 +182,8 @@ async fn on_authorization_code_grant(state: State, code: String) -> Result<Respo
         AccessTokenPayload {
             sub: code.claims.sub,
             exp: Utc::now() + expires_in,
+            iss: state.config.oauth.issuer.clone(),
+            aud: state.config.oauth.audience.clone(),
         },
     )?;
 
@@ -127,6 +192,8 @@ async fn on_authorization_code_grant(state: State, code: String) -> Result<Respo
         RefreshTokenPayload {
             sub: code.claims.sub,
             exp: None,
+            iss: state.config.oauth.issuer.clone(),
+            aud: state.config.oauth.audience.clone(),
         },
     )?;
 
@@ -138,26 +205,28 @@ async fn on_authorization_code_grant(state: State, code: String) -> Result<Respo
     })
 }
 
-#[tracing::instrument(name = "Token", skip(state, request))]
+#[tracing::instrument(
+    name = "Token",
+    skip(state, client_ip, request),
+    fields(client_ip = %client_ip)
+)]
 pub async fn handle(
     Extension(state): Extension<State>,
+    client_ip: ClientIp,
     Form(request): Form<Request>,
 ) -> Result<Json<Response>, ServerError> {
-    let google_config = state
-        .config
-        .google
-        .as_ref()
-        .ok_or_else(|| InternalError::Other("Google Home API not configured".to_string()))?;
+    if state.config.google_configs().next().is_none() {
+        return Err(InternalError::Other("Google Home API not configured".to_string()).into());
+    }
 
     fn verify_client(
-        google_config: &Google,
+        config: &Config,
         client_id: String,
         client_secret: String,
     ) -> Result<(), ServerError> {
-        if client_id != google_config.client_id || client_secret != google_config.client_secret {
-            Err(OAuthError::InvalidClient(None).into())
-        } else {
-            Ok(())
+        match config.get_google_by_client_id(&client_id) {
+            Some(google_config) if client_secret == google_config.client_secret => Ok(()),
+            _ => Err(OAuthError::InvalidClient(None).into()),
         }
     }
 
@@ -168,7 +237,7 @@ pub async fn handle(
             client_secret,
             ..
         } => {
-            verify_client(google_config, client_id, client_secret)?;
+            verify_client(&state.config, client_id, client_secret)?;
             on_refresh_token_grant(state, refresh_token).await
         }
         Request::AuthorizationCode {
@@ -177,13 +246,107 @@ pub async fn handle(
             code,
             ..
         } => {
-            verify_client(google_config, client_id, client_secret)?;
+            verify_client(&state.config, client_id, client_secret)?;
             on_authorization_code_grant(state, code).await
         }
     }
     .map(Json)
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::server::Config;
+    use crate::types::token::AuthorizationCodePayload;
+    use std::collections::HashMap;
+
+    fn authorization_code(state: &State) -> String {
+        AuthorizationCode::new(
+            state.config.secrets.authorization_code_key.as_bytes(),
+            AuthorizationCodePayload {
+                sub: uuid::Uuid::new_v4(),
+                exp: Utc::now() + Duration::minutes(10),
+                iss: None,
+                aud: None,
+            },
+        )
+        .unwrap()
+        .to_string()
+    }
+
+    fn test_state(one_time_authorization_codes: bool) -> State {
+        let config: Config = toml::from_str(&format!(
+            r#"
+            [secrets]
+            refresh-key = "refresh-key"
+            access-key = "access-key"
+            authorization-code-key = "authorization-code-key"
+
+            [oauth]
+            one-time-authorization-codes = {}
+            "#,
+            one_time_authorization_codes
+        ))
+        .unwrap();
+        State::new(config, HashMap::new())
+    }
+
+    #[tokio::test]
+    async fn authorization_code_reused() {
+        let state = test_state(true);
+        let code = authorization_code(&state);
+
+        assert!(on_authorization_code_grant(state.clone(), code.clone())
+            .await
+            .is_ok());
+        assert_eq!(
+            on_authorization_code_grant(state.clone(), code)
+                .await
+                .unwrap_err(),
+            OAuthError::InvalidGrant(Some("authorization code already used".to_string())).into()
+        );
+
+        // Other codes can still be used.
+        let other_code = authorization_code(&state);
+        assert!(on_authorization_code_grant(state, other_code).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn authorization_code_reuse_allowed() {
+        let state = test_state(false);
+        let code = authorization_code(&state);
+
+        assert!(on_authorization_code_grant(state.clone(), code.clone())
+            .await
+            .is_ok());
+        assert!(on_authorization_code_grant(state, code).await.is_ok());
+    }
+
+    #[test]
+    fn expired_codes_removed() {
+        let used_codes = UsedAuthorizationCodes::default();
+        assert!(used_codes.use_code("old", Utc::now() - Duration::minutes(1)));
+        assert!(used_codes.use_code("new", Utc::now() + Duration::minutes(1)));
+        used_codes.remove_expired();
+        assert_eq!(used_codes.codes.lock().unwrap().len(), 1);
+        assert!(!used_codes.use_code("new", Utc::now() + Duration::minutes(1)));
+    }
+
+    #[tokio::test]
+    async fn sweeper_removes_expired() {
+        let used_codes = Arc::new(UsedAuthorizationCodes::default());
+        assert!(used_codes.use_code("old", Utc::now() - Duration::minutes(1)));
+
+        let sweeper = used_codes
+            .clone()
+            .spawn_sweeper(std::time::Duration::from_millis(10));
+        time::sleep(std::time::Duration::from_millis(100)).await;
+        sweeper.abort();
+
+        assert!(used_codes.codes.lock().unwrap().is_empty());
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use crate::types::token::AuthorizationCodePayload;
@@ -10,7 +10,14 @@
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU General Public License for more details.
 
-use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    future::Future,
+    hash::Hash,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
 use tokio::{
     sync::Notify,
     task::{self, JoinHandle},
@@ -62,3 +69,81 @@ async fn callback_run_loop(
         callback().await;
     }
 }
+
+/// A token bucket rate limiter, which keeps a separate bucket for each key.
+#[derive(Debug)]
+pub struct KeyedRateLimiter<K> {
+    capacity: f64,
+    tokens_per_second: f64,
+    buckets: Mutex<HashMap<K, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl<K: Eq + Hash> KeyedRateLimiter<K> {
+    /// Creates a new rate limiter which allows bursts of up to `capacity` calls for each key, and
+    /// `per_minute` calls per minute on average.
+    pub fn new(capacity: u32, per_minute: u32) -> Self {
+        Self {
+            capacity: capacity.into(),
+            tokens_per_second: f64::from(per_minute) / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token from the bucket for the given key if there is one, returning whether the call
+    /// should be allowed.
+    pub fn try_acquire(&self, key: K) -> bool {
+        self.try_acquire_at(key, Instant::now())
+    }
+
+    fn try_acquire_at(&self, key: K, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(key).or_insert(Bucket {
+            tokens: self.capacity,
+            last_refill: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens =
+            (bucket.tokens + elapsed.as_secs_f64() * self.tokens_per_second).min(self.capacity);
+        bucket.last_refill = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyed_rate_limiter() {
+        let limiter = KeyedRateLimiter::new(2, 60);
+        let start = Instant::now();
+
+        assert!(limiter.try_acquire_at("a", start));
+        assert!(limiter.try_acquire_at("a", start));
+        assert!(!limiter.try_acquire_at("a", start));
+        // Other keys have their own bucket.
+        assert!(limiter.try_acquire_at("b", start));
+
+        // One token is added every second.
+        assert!(!limiter.try_acquire_at("a", start + Duration::from_millis(500)));
+        assert!(limiter.try_acquire_at("a", start + Duration::from_millis(1000)));
+        assert!(!limiter.try_acquire_at("a", start + Duration::from_millis(1000)));
+
+        // The bucket doesn't fill beyond its capacity.
+        let later = start + Duration::from_secs(100);
+        assert!(limiter.try_acquire_at("a", later));
+        assert!(limiter.try_acquire_at("a", later));
+        assert!(!limiter.try_acquire_at("a", later));
+    }
+}
@@ -0,0 +1,82 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+use std::fmt::Debug;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::sleep;
+
+/// Calls `f` until it succeeds, retrying up to `retries` times after the first attempt with the
+/// given delay in between. Returns the last error if all attempts fail.
+pub async fn retry<T, E: Debug, F: Future<Output = Result<T, E>>>(
+    retries: u32,
+    delay: Duration,
+    mut f: impl FnMut() -> F,
+) -> Result<T, E> {
+    let mut attempt = 0;
+    loop {
+        match f().await {
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                tracing::debug!("Attempt {} failed, retrying: {:?}", attempt, e);
+                sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[tokio::test]
+    async fn retry_until_success() {
+        let attempts = Cell::new(0);
+        let set = || {
+            attempts.set(attempts.get() + 1);
+            let attempt = attempts.get();
+            async move {
+                if attempt < 2 {
+                    Err("broker unavailable")
+                } else {
+                    Ok(attempt)
+                }
+            }
+        };
+
+        // Without retries the first failure is returned.
+        assert_eq!(
+            retry(0, Duration::ZERO, set).await,
+            Err("broker unavailable")
+        );
+        assert_eq!(attempts.get(), 1);
+
+        // The second attempt succeeds.
+        attempts.set(0);
+        assert_eq!(retry(3, Duration::ZERO, set).await, Ok(2));
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn retry_gives_up() {
+        let attempts = Cell::new(0);
+        let result: Result<(), &str> = retry(2, Duration::ZERO, || {
+            attempts.set(attempts.get() + 1);
+            async { Err("broker unavailable") }
+        })
+        .await;
+        assert_eq!(result, Err("broker unavailable"));
+        assert_eq!(attempts.get(), 3);
+    }
+}
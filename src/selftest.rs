@@ -0,0 +1,262 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+//! A self-test which checks that the config is valid and that the configured MQTT brokers and
+//! Google Home Graph can actually be reached, before going live.
+
+use crate::config::server::Config;
+use crate::config::Config as _;
+use crate::homegraph::HomeGraphClient;
+use crate::homie::get_mqtt_options;
+use crate::types::user::Homie;
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet};
+use rustls::ClientConfig;
+use std::error::Error;
+use std::fmt::{self, Display, Formatter};
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How long to wait for each check which touches the network before giving up on it.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The result of a single self-test check.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Check {
+    /// A description of what was checked.
+    pub name: String,
+    /// The error, if the check failed.
+    pub result: Result<(), String>,
+}
+
+/// The results of all the self-test checks which were run.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Report {
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    /// Returns whether all the checks passed.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.result.is_ok())
+    }
+
+    fn add(&mut self, name: impl Into<String>, result: Result<(), String>) {
+        self.checks.push(Check {
+            name: name.into(),
+            result,
+        });
+    }
+
+    /// Runs the given check with a timeout, and adds its result to the report.
+    async fn run_check<E: Display>(
+        &mut self,
+        name: impl Into<String>,
+        timeout: Duration,
+        check: impl Future<Output = Result<(), E>>,
+    ) {
+        let result = match tokio::time::timeout(timeout, check).await {
+            Ok(result) => result.map_err(|e| e.to_string()),
+            Err(_) => Err(format!("Timed out after {:?}", timeout)),
+        };
+        self.add(name, result);
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        for check in &self.checks {
+            match &check.result {
+                Ok(()) => writeln!(f, "PASS {}", check.name)?,
+                Err(e) => writeln!(f, "FAIL {}: {}", check.name, e)?,
+            }
+        }
+        let passed = self
+            .checks
+            .iter()
+            .filter(|check| check.result.is_ok())
+            .count();
+        write!(f, "{} of {} checks passed", passed, self.checks.len())
+    }
+}
+
+/// Reads the config from the given path, then tries connecting to each configured MQTT broker and
+/// authenticating to Google Home Graph.
+pub async fn run(config_path: &Path, tls_client_config: Arc<ClientConfig>) -> Report {
+    let mut report = Report::default();
+    let config = match Config::read(config_path) {
+        Ok(config) => {
+            report.add(format!("Config {:?}", config_path), Ok(()));
+            config
+        }
+        Err(e) => {
+            // Nothing else can be checked without a config.
+            report.add(format!("Config {:?}", config_path), Err(e.to_string()));
+            return report;
+        }
+    };
+
+    for user in &config.users {
+        if let Some(homie_config) = &user.homie {
+            report
+                .run_check(
+                    format!(
+                        "MQTT broker {}:{} for user {}",
+                        homie_config.host, homie_config.port, user.id
+                    ),
+                    CHECK_TIMEOUT,
+                    connect_mqtt(homie_config, tls_client_config.clone()),
+                )
+                .await;
+        }
+    }
+
+    let google_configs = config
+        .google
+        .iter()
+        .map(|google| ("Google Home Graph authentication".to_string(), google))
+        .chain(config.google_clients.iter().map(|(name, google)| {
+            (
+                format!("Google Home Graph authentication for client {}", name),
+                google,
+            )
+        }));
+    for (name, google) in google_configs {
+        report
+            .run_check(
+                name,
+                CHECK_TIMEOUT,
+                connect_home_graph(&google.credentials_file),
+            )
+            .await;
+    }
+
+    report
+}
+
+/// Connects to Google Home Graph with the given credentials file.
+///
+/// Unlike when the server starts, building the credentials isn't retried, as the retries could take
+/// longer than the check timeout and so hide the actual error.
+async fn connect_home_graph(credentials_file: &Path) -> Result<(), Box<dyn Error>> {
+    HomeGraphClient::connect_without_retries(credentials_file).await?;
+    Ok(())
+}
+
+/// Connects to the MQTT broker for the given config, and waits until the broker acknowledges the
+/// connection.
+async fn connect_mqtt(
+    config: &Homie,
+    tls_client_config: Arc<ClientConfig>,
+) -> Result<(), Box<dyn Error>> {
+    let mqtt_options = selftest_mqtt_options(config, tls_client_config)?;
+    let (client, mut event_loop) = AsyncClient::new(mqtt_options, 10);
+    loop {
+        if let Event::Incoming(Packet::ConnAck(_)) = event_loop.poll().await? {
+            break;
+        }
+    }
+    client.disconnect().await?;
+    Ok(())
+}
+
+/// Returns the MQTT options with which to check the given config. These use a different client ID
+/// from the server, as otherwise the broker would drop the server's session if it is running.
+fn selftest_mqtt_options(
+    config: &Homie,
+    tls_client_config: Arc<ClientConfig>,
+) -> io::Result<MqttOptions> {
+    let config = Homie {
+        client_id: format!("{}-selftest", config.client_id),
+        ..config.clone()
+    };
+    get_mqtt_options(&config, config.use_tls.then_some(tls_client_config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::{pending, ready};
+
+    #[tokio::test]
+    async fn aggregate_checks() {
+        let mut report = Report::default();
+        assert!(report.passed());
+
+        report
+            .run_check("Good", CHECK_TIMEOUT, ready(Ok::<_, String>(())))
+            .await;
+        assert!(report.passed());
+
+        report
+            .run_check("Bad", CHECK_TIMEOUT, ready(Err("Broken".to_string())))
+            .await;
+        report
+            .run_check(
+                "Slow",
+                Duration::from_millis(10),
+                pending::<Result<(), String>>(),
+            )
+            .await;
+        assert!(!report.passed());
+        assert_eq!(
+            report.to_string(),
+            "PASS Good\nFAIL Bad: Broken\nFAIL Slow: Timed out after 10ms\n1 of 3 checks passed"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_google_credentials() {
+        let mut report = Report::default();
+        report
+            .run_check(
+                "Google",
+                CHECK_TIMEOUT,
+                connect_home_graph(Path::new("testdata/missing-credentials.json")),
+            )
+            .await;
+        let error = report.checks[0].result.clone().unwrap_err();
+        assert!(
+            !error.starts_with("Timed out"),
+            "Unexpected error {}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_config() {
+        let report = run(
+            Path::new("testdata/missing.toml"),
+            Arc::new(ClientConfig::new()),
+        )
+        .await;
+        assert!(!report.passed());
+        assert_eq!(report.checks.len(), 1);
+    }
+
+    #[test]
+    fn separate_client_id() {
+        let config: Homie = toml::from_str(
+            r#"
+            host = "localhost"
+            port = 1883
+            client-id = "homieflow"
+            reconnect-interval-seconds = 5
+            "#,
+        )
+        .unwrap();
+        let mqtt_options = selftest_mqtt_options(&config, Arc::new(ClientConfig::new())).unwrap();
+        assert_eq!(mqtt_options.client_id(), "homieflow-selftest");
+    }
+}
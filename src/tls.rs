@@ -0,0 +1,205 @@
+// Copyright 2022 the homieflow authors.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+//! Loading of the certificate and private key for the TLS server.
+
+use crate::config::server::Tls;
+use axum_server::tls_rustls::RustlsConfig;
+use ring::signature::{
+    EcdsaKeyPair, Ed25519KeyPair, KeyPair, RsaKeyPair, ECDSA_P256_SHA256_ASN1_SIGNING,
+    ECDSA_P384_SHA384_ASN1_SIGNING,
+};
+use rustls_pemfile::Item;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("TLS certificate file {0:?} not found")]
+    CertificateNotFound(PathBuf),
+    #[error("TLS private key file {0:?} not found")]
+    PrivateKeyNotFound(PathBuf),
+    #[error("Error reading {0:?}: {1}")]
+    IO(PathBuf, io::Error),
+    #[error("No certificates found in TLS certificate file {0:?}")]
+    NoCertificate(PathBuf),
+    #[error("No RSA or PKCS#8 private key found in TLS private key file {0:?}")]
+    NoPrivateKey(PathBuf),
+    #[error("TLS private key {private_key:?} doesn't match certificate {certificate:?}")]
+    KeyMismatch {
+        certificate: PathBuf,
+        private_key: PathBuf,
+    },
+    #[error("Invalid TLS certificate or private key: {0}")]
+    Invalid(io::Error),
+}
+
+/// Loads the certificate and private key files from the given TLS config, checking that they exist
+/// and match each other.
+pub async fn load_rustls_config(tls: &Tls) -> Result<RustlsConfig, Error> {
+    let certificate_pem = read_file(&tls.certificate, Error::CertificateNotFound).await?;
+    let private_key_pem = read_file(&tls.private_key, Error::PrivateKeyNotFound).await?;
+
+    let certificates = rustls_pemfile::certs(&mut certificate_pem.as_slice())
+        .map_err(|e| Error::IO(tls.certificate.clone(), e))?;
+    let private_key = read_private_key(&private_key_pem)
+        .map_err(|e| Error::IO(tls.private_key.clone(), e))?
+        .ok_or_else(|| Error::NoPrivateKey(tls.private_key.clone()))?;
+    let leaf = certificates
+        .first()
+        .ok_or_else(|| Error::NoCertificate(tls.certificate.clone()))?;
+    if !key_matches_certificate(&private_key, leaf) {
+        return Err(Error::KeyMismatch {
+            certificate: tls.certificate.clone(),
+            private_key: tls.private_key.clone(),
+        });
+    }
+
+    RustlsConfig::from_der(certificates, private_key)
+        .await
+        .map_err(Error::Invalid)
+}
+
+/// Reads the given file, using `not_found` to construct the error if it doesn't exist.
+async fn read_file(path: &Path, not_found: fn(PathBuf) -> Error) -> Result<Vec<u8>, Error> {
+    tokio::fs::read(path).await.map_err(|e| {
+        if e.kind() == io::ErrorKind::NotFound {
+            not_found(path.to_owned())
+        } else {
+            Error::IO(path.to_owned(), e)
+        }
+    })
+}
+
+/// Returns the first RSA or PKCS#8 private key in the given PEM data, if any.
+fn read_private_key(pem: &[u8]) -> io::Result<Option<Vec<u8>>> {
+    let mut reader = pem;
+    while let Some(item) = rustls_pemfile::read_one(&mut reader)? {
+        match item {
+            Item::RSAKey(key) | Item::PKCS8Key(key) => return Ok(Some(key)),
+            Item::X509Certificate(_) => {}
+        }
+    }
+    Ok(None)
+}
+
+/// Returns whether the public key corresponding to the given DER-encoded private key appears in
+/// the given DER-encoded certificate. Keys of unsupported types are assumed to match, and left for
+/// rustls to check.
+fn key_matches_certificate(private_key: &[u8], certificate: &[u8]) -> bool {
+    if let Some(public_key) = public_key(private_key) {
+        certificate
+            .windows(public_key.len())
+            .any(|window| window == public_key)
+    } else {
+        true
+    }
+}
+
+/// Derives the public key from the given DER-encoded private key, if it is of a supported type.
+fn public_key(private_key: &[u8]) -> Option<Vec<u8>> {
+    if let Ok(key_pair) = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, private_key) {
+        Some(key_pair.public_key().as_ref().to_owned())
+    } else if let Ok(key_pair) =
+        EcdsaKeyPair::from_pkcs8(&ECDSA_P384_SHA384_ASN1_SIGNING, private_key)
+    {
+        Some(key_pair.public_key().as_ref().to_owned())
+    } else if let Ok(key_pair) = Ed25519KeyPair::from_pkcs8_maybe_unchecked(private_key) {
+        Some(key_pair.public_key().as_ref().to_owned())
+    } else if let Ok(key_pair) =
+        RsaKeyPair::from_pkcs8(private_key).or_else(|_| RsaKeyPair::from_der(private_key))
+    {
+        Some(key_pair.public_key().as_ref().to_owned())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TESTDATA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/testdata/tls");
+
+    fn tls(certificate: &str, private_key: &str) -> Tls {
+        Tls {
+            address: [127, 0, 0, 1].into(),
+            port: 6002,
+            certificate: Path::new(TESTDATA).join(certificate),
+            private_key: Path::new(TESTDATA).join(private_key),
+            plain_http: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn valid() {
+        load_rustls_config(&tls("certificate.pem", "private_key.pem"))
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn missing_certificate() {
+        let error = load_rustls_config(&tls("missing.pem", "private_key.pem"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, Error::CertificateNotFound(path) if path.ends_with("missing.pem")),
+            "Unexpected error {:?}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_private_key() {
+        let error = load_rustls_config(&tls("certificate.pem", "missing.pem"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(&error, Error::PrivateKeyNotFound(path) if path.ends_with("missing.pem")),
+            "Unexpected error {:?}",
+            error
+        );
+    }
+
+    #[tokio::test]
+    async fn key_mismatch() {
+        let error = load_rustls_config(&tls("certificate.pem", "other_private_key.pem"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, Error::KeyMismatch { .. }),
+            "Unexpected error {:?}",
+            error
+        );
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "TLS private key {:?} doesn't match certificate {:?}",
+                Path::new(TESTDATA).join("other_private_key.pem"),
+                Path::new(TESTDATA).join("certificate.pem")
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn no_private_key() {
+        let error = load_rustls_config(&tls("certificate.pem", "certificate.pem"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, Error::NoPrivateKey(_)),
+            "Unexpected error {:?}",
+            error
+        );
+    }
+}
@@ -28,4 +28,10 @@ pub enum Error {
     /// The CSRF token cookie was missing, or didn't match the token in the request.
     #[error("Missing or invalid CSRF token")]
     InvalidCsrfToken,
+    /// The health check secret header was missing or didn't match the configured secret.
+    #[error("Missing or invalid health check secret")]
+    InvalidHealthSecret,
+    /// The user is authenticated but not allowed to access the resource.
+    #[error("user is not an admin")]
+    NotAdmin,
 }
@@ -17,6 +17,8 @@ use serde::Serialize;
 pub enum Error {
     #[error("clerk: {0}")]
     Clerk(String),
+    #[error("home graph: {0}")]
+    HomeGraph(String),
     #[error("mailer: {0}")]
     Mailer(String),
     #[error("other: {0}")]
@@ -35,10 +35,20 @@ pub enum ServerError {
     Internal(#[from] InternalError),
     #[error("validation error: {0}")]
     Validation(String),
+    #[error("unsupported media type: {0}")]
+    UnsupportedMediaType(String),
+    #[error("method not allowed")]
+    MethodNotAllowed,
+    #[error("not found: {0}")]
+    NotFound(String),
     #[error("auth error: {0}")]
     Auth(#[from] AuthError),
     #[error("oauth error: {0}")]
     OAuth(#[from] OAuthError),
+    #[error("too many requests")]
+    TooManyRequests,
+    #[error("service unavailable")]
+    ServiceUnavailable,
 }
 
 impl axum::response::IntoResponse for ServerError {
@@ -50,12 +60,19 @@ impl axum::response::IntoResponse for ServerError {
         use http::StatusCode;
         let status = match self {
             Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
             Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            Self::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
             Self::Auth(ref err) => match err {
                 AuthError::InvalidAuthorizationHeader(_) => StatusCode::UNAUTHORIZED,
                 AuthError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
                 AuthError::InvalidGoogleJwt(_) => StatusCode::UNAUTHORIZED,
                 AuthError::InvalidCsrfToken => StatusCode::UNAUTHORIZED,
+                AuthError::InvalidHealthSecret => StatusCode::UNAUTHORIZED,
+                AuthError::NotAdmin => StatusCode::FORBIDDEN,
             },
             Self::OAuth(oauth) => {
                 let header = oauth.www_authenticate_header();
@@ -89,3 +106,38 @@ impl From<askama::Error> for ServerError {
         Self::Internal(e.into())
     }
 }
+
+impl From<tonic::Status> for InternalError {
+    fn from(status: tonic::Status) -> Self {
+        Self::HomeGraph(format!("{:?}: {}", status.code(), status.message()))
+    }
+}
+
+impl From<tonic::Status> for ServerError {
+    fn from(status: tonic::Status) -> Self {
+        Self::Internal(status.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+    use http::StatusCode;
+
+    #[test]
+    fn home_graph_status() {
+        let error: ServerError =
+            tonic::Status::permission_denied("The caller does not have permission").into();
+        assert_eq!(
+            error,
+            ServerError::Internal(InternalError::HomeGraph(
+                "PermissionDenied: The caller does not have permission".to_string()
+            ))
+        );
+        assert_eq!(
+            error.into_response().status(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
@@ -69,6 +69,12 @@ pub struct AccessTokenPayload {
     pub sub: Uuid,
     #[serde(with = "chrono::serde::ts_seconds")]
     pub exp: DateTime<Utc>,
+    /// The deployment which issued the token, if configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// The deployment which the token is intended for, if configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -76,6 +82,12 @@ pub struct AuthorizationCodePayload {
     pub sub: Uuid,
     #[serde(with = "chrono::serde::ts_seconds")]
     pub exp: DateTime<Utc>,
+    /// The deployment which issued the token, if configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// The deployment which the token is intended for, if configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
 }
 
 #[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
@@ -83,6 +95,12 @@ pub struct RefreshTokenPayload {
     pub sub: Uuid,
     #[serde(with = "chrono::serde::ts_seconds_option")]
     pub exp: Option<DateTime<Utc>>,
+    /// The deployment which issued the token, if configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub iss: Option<String>,
+    /// The deployment which the token is intended for, if configured.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aud: Option<String>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -136,12 +154,20 @@ impl<P: ser::Serialize + de::DeserializeOwned> Token<P> {
         })
     }
 
-    /// Validate the signature, and the expiry if it is present.
-    pub fn decode(key: &[u8], token: &str) -> Result<TokenData<P>, Error> {
+    /// Validate the signature, and the expiry if it is present. If an issuer or audience is given
+    /// then the token must have a matching `iss` or `aud` claim.
+    pub fn decode(
+        key: &[u8],
+        token: &str,
+        issuer: Option<&str>,
+        audience: Option<&str>,
+    ) -> Result<TokenData<P>, Error> {
         // Hack to allow tokens without "exp", but validate it if it is present.
         let unvalidated_data: TokenData<BasePayload> = dangerous_insecure_decode(token)?;
         let validation = Validation {
             validate_exp: unvalidated_data.claims.exp.is_some(),
+            iss: issuer.map(ToOwned::to_owned),
+            aud: audience.map(|audience| [audience.to_owned()].into_iter().collect()),
             ..Validation::default()
         };
 
@@ -170,10 +196,12 @@ mod tests {
             let payload = AccessTokenPayload {
                 sub: Uuid::new_v4(),
                 exp: Utc::now().round_subsecs(0) + chrono::Duration::hours(1),
+                iss: None,
+                aud: None,
             };
             let token = AccessToken::new(&key, payload).unwrap();
             let encoded = token.encode();
-            let decoded = AccessToken::decode(&key, &encoded).unwrap();
+            let decoded = AccessToken::decode(&key, &encoded, None, None).unwrap();
             assert_eq!(token.header, decoded.header);
             assert_eq!(token.payload, decoded.claims);
         }
@@ -185,10 +213,12 @@ mod tests {
             let payload = AccessTokenPayload {
                 sub: Uuid::new_v4(),
                 exp: Utc::now() - expired_by,
+                iss: None,
+                aud: None,
             };
             let token = AccessToken::new(&key, payload).unwrap();
             let encoded = token.encode();
-            let err = Token::<AccessTokenPayload>::decode(&key, &encoded).unwrap_err();
+            let err = Token::<AccessTokenPayload>::decode(&key, &encoded, None, None).unwrap_err();
             assert_eq!(
                 err,
                 Error {
@@ -204,10 +234,12 @@ mod tests {
             let payload = AccessTokenPayload {
                 sub: Uuid::new_v4(),
                 exp: Utc::now() - chrono::Duration::hours(1),
+                iss: None,
+                aud: None,
             };
             let token = AccessToken::new(&valid_key, payload).unwrap();
             let encoded = token.encode();
-            let err = AccessToken::decode(&invalid_key, &encoded).unwrap_err();
+            let err = AccessToken::decode(&invalid_key, &encoded, None, None).unwrap_err();
             assert_eq!(
                 err,
                 Error {
@@ -217,6 +249,77 @@ mod tests {
         }
     }
 
+    mod claims {
+        use super::*;
+
+        fn token(key: &[u8], iss: Option<&str>, aud: Option<&str>) -> String {
+            AccessToken::new(
+                key,
+                AccessTokenPayload {
+                    sub: Uuid::new_v4(),
+                    exp: Utc::now() + chrono::Duration::hours(1),
+                    iss: iss.map(ToOwned::to_owned),
+                    aud: aud.map(ToOwned::to_owned),
+                },
+            )
+            .unwrap()
+            .encode()
+        }
+
+        #[test]
+        fn valid() {
+            let key = get_key();
+            let encoded = token(&key, Some("homieflow-a"), Some("google-a"));
+            let decoded =
+                AccessToken::decode(&key, &encoded, Some("homieflow-a"), Some("google-a")).unwrap();
+            assert_eq!(decoded.claims.iss.as_deref(), Some("homieflow-a"));
+            assert_eq!(decoded.claims.aud.as_deref(), Some("google-a"));
+        }
+
+        #[test]
+        fn wrong_audience() {
+            let key = get_key();
+            let encoded = token(&key, Some("homieflow-a"), Some("google-b"));
+            let err = AccessToken::decode(&key, &encoded, Some("homieflow-a"), Some("google-a"))
+                .unwrap_err();
+            assert_eq!(
+                err,
+                Error {
+                    description: "InvalidAudience".to_string()
+                }
+            );
+        }
+
+        #[test]
+        fn wrong_issuer() {
+            let key = get_key();
+            let encoded = token(&key, Some("homieflow-b"), None);
+            let err = AccessToken::decode(&key, &encoded, Some("homieflow-a"), None).unwrap_err();
+            assert_eq!(
+                err,
+                Error {
+                    description: "InvalidIssuer".to_string()
+                }
+            );
+        }
+
+        #[test]
+        fn missing_claims() {
+            let key = get_key();
+            let encoded = token(&key, None, None);
+            let err = AccessToken::decode(&key, &encoded, None, Some("google-a")).unwrap_err();
+            assert_eq!(
+                err,
+                Error {
+                    description: "InvalidAudience".to_string()
+                }
+            );
+            // Tokens with claims are still accepted if nothing is required.
+            let encoded = token(&key, Some("homieflow-a"), Some("google-a"));
+            AccessToken::decode(&key, &encoded, None, None).unwrap();
+        }
+    }
+
     mod rt {
         use super::*;
 
@@ -226,10 +329,12 @@ mod tests {
             let payload = RefreshTokenPayload {
                 sub: Uuid::new_v4(),
                 exp: Some(Utc::now().round_subsecs(0) + chrono::Duration::hours(1)),
+                iss: None,
+                aud: None,
             };
             let token = RefreshToken::new(&key, payload).unwrap();
             let encoded = token.encode();
-            let decoded = RefreshToken::decode(&key, &encoded).unwrap();
+            let decoded = RefreshToken::decode(&key, &encoded, None, None).unwrap();
             assert_eq!(token.header, decoded.header);
             assert_eq!(token.payload, decoded.claims);
         }
@@ -240,10 +345,12 @@ mod tests {
             let payload = RefreshTokenPayload {
                 sub: Uuid::new_v4(),
                 exp: None,
+                iss: None,
+                aud: None,
             };
             let token = RefreshToken::new(&key, payload).unwrap();
             let encoded = token.encode();
-            let decoded = RefreshToken::decode(&key, &encoded).unwrap();
+            let decoded = RefreshToken::decode(&key, &encoded, None, None).unwrap();
             assert_eq!(token.header, decoded.header);
             assert_eq!(token.payload, decoded.claims);
         }
@@ -255,10 +362,12 @@ mod tests {
             let payload = RefreshTokenPayload {
                 sub: Uuid::new_v4(),
                 exp: Some(Utc::now() - expired_by),
+                iss: None,
+                aud: None,
             };
             let token = Token::new(&key, payload).unwrap();
             let encoded = token.encode();
-            let err = RefreshToken::decode(&key, &encoded).unwrap_err();
+            let err = RefreshToken::decode(&key, &encoded, None, None).unwrap_err();
             assert_eq!(
                 err,
                 Error {
@@ -274,10 +383,12 @@ mod tests {
             let payload = RefreshTokenPayload {
                 sub: Uuid::new_v4(),
                 exp: Some(Utc::now().round_subsecs(0) + chrono::Duration::hours(1)),
+                iss: None,
+                aud: None,
             };
             let token = RefreshToken::new(&valid_key, payload).unwrap();
             let encoded = token.encode();
-            let err = RefreshToken::decode(&invalid_key, &encoded).unwrap_err();
+            let err = RefreshToken::decode(&invalid_key, &encoded, None, None).unwrap_err();
             assert_eq!(
                 err,
                 Error {
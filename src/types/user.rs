@@ -10,27 +10,48 @@
 // MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
 // GNU General Public License for more details.
 
+use rumqttc::QoS;
+use schemars::JsonSchema;
+use serde::de::Error as _;
 use serde::Deserialize;
 use serde::Deserializer;
 use serde::Serialize;
+use serde::Serializer;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
 use std::time::Duration;
 use uuid::Uuid;
 
 pub type ID = Uuid;
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct User {
     /// Unique ID of the user
     pub id: ID,
     /// Email of the user
     pub email: String,
+    /// Whether the user may access admin endpoints.
+    #[serde(default)]
+    pub admin: bool,
+    /// Whether to refuse all EXECUTE commands from the user.
+    #[serde(default)]
+    pub read_only: bool,
+    /// A stable ID to identify the user to Google Home, if different from `id`.
+    #[serde(default)]
+    pub agent_user_id: Option<String>,
+    /// The name of the entry in `google-clients` through which the user links their account, if
+    /// not the global `google` config.
+    #[serde(default)]
+    pub google_client: Option<String>,
     /// Homie controller for the user.
     #[serde(default)]
     pub homie: Option<Homie>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
-#[serde(rename_all = "kebab-case")]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
 pub struct Homie {
     /// The hostname of the MQTT broker.
     pub host: String,
@@ -45,24 +66,279 @@ pub struct Homie {
     /// The password with which to authenticate to the MQTT broker, if any.
     #[serde(default)]
     pub password: Option<String>,
+    /// A file containing the password with which to authenticate to the MQTT broker, as an
+    /// alternative to `password`.
+    #[serde(default)]
+    pub password_file: Option<PathBuf>,
     /// The client ID to use for the MQTT connection.
     pub client_id: String,
-    /// The Homie base MQTT topic.
-    #[serde(default = "default_homie_prefix")]
+    /// The Homie base MQTT topic. Any trailing slashes are removed.
+    #[serde(default = "default_homie_prefix", deserialize_with = "de_homie_prefix")]
     pub homie_prefix: String,
     #[serde(
         deserialize_with = "de_duration_seconds",
+        serialize_with = "ser_duration_seconds",
         rename = "reconnect-interval-seconds"
     )]
+    #[schemars(with = "DurationSeconds")]
     pub reconnect_interval: Duration,
+    /// A prefix to add to the IDs of devices from this broker when exposing them to Google Home,
+    /// to avoid collisions between brokers using the same Homie device IDs.
+    #[serde(default)]
+    pub device_id_prefix: Option<String>,
+    /// The MQTT QoS level to use for subscriptions and publishes, from 0 to 2.
+    #[serde(default = "default_qos", deserialize_with = "de_qos")]
+    pub qos: u8,
+    /// How long to wait for the connection to the MQTT broker to be established before giving up
+    /// and trying again later, if not the default.
+    #[serde(default)]
+    pub connect_timeout_seconds: Option<u64>,
+    /// An MQTT topic to which to republish the state reported to Google Home for each node, as
+    /// JSON, if any. `{device}` and `{node}` are replaced by the Homie device and node IDs.
+    #[serde(default)]
+    pub mirror_topic: Option<String>,
+    /// The maximum number of outgoing QoS 1 and 2 messages which may be awaiting acknowledgement
+    /// from the MQTT broker at once, if not the default.
+    #[serde(default)]
+    pub inflight: Option<u16>,
+    /// How many requests to the MQTT event loop may be queued before publishing blocks, if not the
+    /// default.
+    #[serde(default)]
+    pub request_channel_capacity: Option<usize>,
+    /// Whether to share a single MQTT connection with other users who also set this and use the
+    /// same broker host, port and username.
+    #[serde(default)]
+    pub share_connection: bool,
+    /// How many consecutive times to try connecting to the MQTT broker before giving up on it
+    /// entirely, if at all.
+    #[serde(default)]
+    pub max_reconnect_attempts: Option<u32>,
+}
+
+impl Homie {
+    /// Returns the configured MQTT QoS level.
+    pub fn qos(&self) -> QoS {
+        match self.qos {
+            0 => QoS::AtMostOnce,
+            1 => QoS::AtLeastOnce,
+            _ => QoS::ExactlyOnce,
+        }
+    }
+
+    /// Returns the configured MQTT password, reading it from `password_file` if that is set.
+    /// Trailing newlines in the file are ignored.
+    pub fn password(&self) -> io::Result<Option<String>> {
+        if let Some(password_file) = &self.password_file {
+            let password = fs::read_to_string(password_file).map_err(|e| {
+                io::Error::new(
+                    e.kind(),
+                    format!(
+                        "Error reading MQTT password file {:?}: {}",
+                        password_file, e
+                    ),
+                )
+            })?;
+            Ok(Some(password.trim_end_matches(['\r', '\n']).to_owned()))
+        } else {
+            Ok(self.password.clone())
+        }
+    }
+
+    /// Returns the key by which this broker connection may be shared with other users, if
+    /// `share_connection` is set.
+    pub fn connection_key(&self) -> Option<(&str, u16, Option<&str>)> {
+        self.share_connection
+            .then_some((self.host.as_str(), self.port, self.username.as_deref()))
+    }
+
+    /// Returns whether this config has the same MQTT connection options as the given one, so that
+    /// they may share a connection. Options which apply to each user separately, such as the Homie
+    /// prefix, may differ.
+    pub fn same_connection_options(&self, other: &Homie) -> bool {
+        *self
+            == Homie {
+                homie_prefix: self.homie_prefix.clone(),
+                device_id_prefix: self.device_id_prefix.clone(),
+                qos: self.qos,
+                mirror_topic: self.mirror_topic.clone(),
+                ..other.clone()
+            }
+    }
+
+    /// Returns the configured MQTT connection timeout, if any.
+    pub fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout_seconds.map(Duration::from_secs)
+    }
 }
 
 fn default_homie_prefix() -> String {
     "homie".to_string()
 }
 
-/// Deserialize an integer as a number of seconds.
+/// Deserialize a Homie base topic, trimming trailing slashes and rejecting empty prefixes.
+fn de_homie_prefix<'de, D: Deserializer<'de>>(d: D) -> Result<String, D::Error> {
+    let prefix = String::deserialize(d)?;
+    let trimmed = prefix.trim_end_matches('/');
+    if trimmed.is_empty() {
+        return Err(D::Error::custom(format!(
+            "invalid homie-prefix {:?}, must not be empty",
+            prefix
+        )));
+    }
+    Ok(trimmed.to_string())
+}
+
+fn default_qos() -> u8 {
+    1
+}
+
+/// Deserialize an MQTT QoS level, rejecting values outside the valid range 0 to 2.
+fn de_qos<'de, D: Deserializer<'de>>(d: D) -> Result<u8, D::Error> {
+    let qos = u8::deserialize(d)?;
+    if qos > 2 {
+        return Err(D::Error::custom(format!(
+            "invalid qos {}, must be 0, 1 or 2",
+            qos
+        )));
+    }
+    Ok(qos)
+}
+
+/// A duration in a config file, either as an integer number of seconds or as a human-readable
+/// string such as `"30s"` or `"5m"`.
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum DurationSeconds {
+    Seconds(u64),
+    Human(String),
+}
+
+/// Deserialize an integer as a number of seconds, or a string as a human-readable duration.
 fn de_duration_seconds<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
-    let seconds = u64::deserialize(d)?;
-    Ok(Duration::from_secs(seconds))
+    match DurationSeconds::deserialize(d)? {
+        DurationSeconds::Seconds(seconds) => Ok(Duration::from_secs(seconds)),
+        DurationSeconds::Human(duration) => {
+            humantime::parse_duration(&duration).map_err(D::Error::custom)
+        }
+    }
+}
+
+/// Serialize a duration as an integer number of seconds if it is a whole number of seconds, or else
+/// as a human-readable string so that it isn't truncated.
+fn ser_duration_seconds<S: Serializer>(duration: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    if duration.subsec_nanos() == 0 {
+        s.serialize_u64(duration.as_secs())
+    } else {
+        s.serialize_str(&humantime::format_duration(*duration).to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_homie(homie_prefix: &str) -> Result<Homie, toml::de::Error> {
+        toml::from_str(&format!(
+            r#"
+            host = "localhost"
+            port = 1883
+            client-id = "homieflow"
+            homie-prefix = "{}"
+            reconnect-interval-seconds = 5
+            "#,
+            homie_prefix
+        ))
+    }
+
+    #[test]
+    fn homie_prefix_unchanged() {
+        assert_eq!(parse_homie("homie").unwrap().homie_prefix, "homie");
+        assert_eq!(parse_homie("foo/homie").unwrap().homie_prefix, "foo/homie");
+    }
+
+    #[test]
+    fn homie_prefix_trailing_slash() {
+        assert_eq!(parse_homie("homie/").unwrap().homie_prefix, "homie");
+        assert_eq!(
+            parse_homie("foo/homie//").unwrap().homie_prefix,
+            "foo/homie"
+        );
+    }
+
+    #[test]
+    fn qos() {
+        let parse_qos = |qos: &str| -> Result<Homie, toml::de::Error> {
+            toml::from_str(&format!(
+                r#"
+                host = "localhost"
+                port = 1883
+                client-id = "homieflow"
+                reconnect-interval-seconds = 5
+                {}
+                "#,
+                qos
+            ))
+        };
+        assert_eq!(parse_qos("").unwrap().qos(), QoS::AtLeastOnce);
+        assert_eq!(parse_qos("qos = 0").unwrap().qos(), QoS::AtMostOnce);
+        assert_eq!(parse_qos("qos = 2").unwrap().qos(), QoS::ExactlyOnce);
+        assert!(parse_qos("qos = 7").is_err());
+        assert!(parse_qos("qos = -1").is_err());
+    }
+
+    #[test]
+    fn connect_timeout() {
+        assert_eq!(parse_homie("homie").unwrap().connect_timeout(), None);
+        let homie: Homie = toml::from_str(
+            r#"
+            host = "localhost"
+            port = 1883
+            client-id = "homieflow"
+            reconnect-interval-seconds = 5
+            connect-timeout-seconds = 10
+            "#,
+        )
+        .unwrap();
+        assert_eq!(homie.connect_timeout(), Some(Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn reconnect_interval() {
+        let parse_reconnect_interval = |reconnect_interval: &str| {
+            toml::from_str::<Homie>(&format!(
+                r#"
+                host = "localhost"
+                port = 1883
+                client-id = "homieflow"
+                reconnect-interval-seconds = {}
+                "#,
+                reconnect_interval
+            ))
+            .map(|homie| homie.reconnect_interval)
+        };
+        assert_eq!(
+            parse_reconnect_interval("30").unwrap(),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            parse_reconnect_interval("\"30s\"").unwrap(),
+            Duration::from_secs(30)
+        );
+        assert_eq!(
+            parse_reconnect_interval("\"5m\"").unwrap(),
+            Duration::from_secs(300)
+        );
+        assert_eq!(
+            parse_reconnect_interval("\"1m 30s\"").unwrap(),
+            Duration::from_secs(90)
+        );
+        assert!(parse_reconnect_interval("\"soon\"").is_err());
+        assert!(parse_reconnect_interval("-5").is_err());
+    }
+
+    #[test]
+    fn homie_prefix_empty() {
+        assert!(parse_homie("").is_err());
+        assert!(parse_homie("/").is_err());
+    }
 }